@@ -1,4 +1,8 @@
-use crate::lex::{Lex, Span, Token};
+use crate::{
+    cst::SyntaxKind,
+    diag::{self, Diagnostic, Diagnostics},
+    lex::{decode_interpolated, is_interpolated, Fragment, Lex, Span, Token},
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Programme<'a> {
@@ -8,11 +12,14 @@ pub enum Programme<'a> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Block<'a> {
-    begin: Span<'a>,
-    items: Vec<Expr<'a>>,
-    end: Span<'a>,
+    pub(crate) begin: Span<'a>,
+    pub(crate) items: Vec<Expr<'a>>,
+    pub(crate) end: Span<'a>,
 }
 
+// Display prints expressions in a normalised form, see `cst` for the
+// exact source text.
+
 impl<'a> core::fmt::Display for Closure<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let Closure {
@@ -22,47 +29,357 @@ impl<'a> core::fmt::Display for Closure<'a> {
             body,
         } = self;
         write!(f, "{open}")?;
-        for (arg, sep) in formal_args {
-            write!(f, "{arg}")?;
-            if let Some(sep) = sep {
-                write!(f, "{sep} ")?;
-            }
+        write_list(f, formal_args)?;
+        write!(f, "{close}")?;
+        if !matches!(body, Expr::Block(_)) {
+            write!(f, " ")?;
         }
-        write!(f, "{close} {body}")
+        write!(f, "{body}")
     }
 }
 
 impl<'a> core::fmt::Display for Block<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "scope")
+        for item in &self.items {
+            write!(f, "\n    {}", item.to_string().replace('\n', "\n    "))?;
+        }
+        Ok(())
     }
 }
 
 impl<'a> core::fmt::Display for FormalArg<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "arg")
+        match self {
+            FormalArg::Name(name) | FormalArg::Bad(name) => write!(f, "{name}"),
+            FormalArg::Typed(name, colon, ty) => write!(f, "{name}{colon} {ty}"),
+            FormalArg::NameWithDefault(arg, eq, value) if matches!(**arg, FormalArg::Typed(..)) => {
+                write!(f, "{arg} {eq} {value}")
+            }
+            FormalArg::NameWithDefault(arg, eq, value) => write!(f, "{arg}{eq}{value}"),
+            FormalArg::Rest(star, name) => {
+                write!(f, "{star}")?;
+                name.iter().try_for_each(|name| write!(f, "{name}"))
+            }
+            FormalArg::Pattern(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Arg<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arg::Positional(value) => write!(f, "{value}"),
+            Arg::Keyword(name, eq, value) => write!(f, "{name}{eq}{value}"),
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Entry<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Entry::Pair(key, colon, value) => write!(f, "{key}{colon} {value}"),
+            Entry::Spread(dots, value) => write!(f, "{dots}{value}"),
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Pattern<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Name(s) | Pattern::Wildcard(s) | Pattern::Bad(s) => write!(f, "{s}"),
+            Pattern::Tuple(open, items, close) | Pattern::Array(open, items, close) => {
+                write!(f, "{open}")?;
+                write_list(f, items)?;
+                write!(f, "{close}")
+            }
+            Pattern::Rest(dots, name) => {
+                write!(f, "{dots}")?;
+                if let Some(name) = name {
+                    write!(f, "{name}")?;
+                }
+                Ok(())
+            }
+            Pattern::Struct(name, open, fields, close) => {
+                write!(f, "{name}{open}")?;
+                write_list(f, fields)?;
+                write!(f, "{close}")
+            }
+            Pattern::Place(expr) | Pattern::Literal(expr) => write!(f, "{expr}"),
+            Pattern::Range(lo, dots, hi) => write!(f, "{lo}{dots}{hi}"),
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Arm<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Arm {
+            pattern,
+            guard,
+            arrow,
+            body,
+        } = self;
+        write!(f, "{pattern}")?;
+        if let Some((keyword, cond)) = guard {
+            write!(f, " {keyword} {cond}")?;
+        }
+        write!(f, " {arrow}")?;
+        if !matches!(body, Expr::Block(_)) {
+            write!(f, " ")?;
+        }
+        write!(f, "{body}")
+    }
+}
+
+impl<'a> core::fmt::Display for FieldPattern<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldPattern::Name(name) => write!(f, "{name}"),
+            FieldPattern::Named(name, eq, pattern) => write!(f, "{name}{eq}{pattern}"),
+            FieldPattern::Positional(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+
+impl<'a> core::fmt::Display for Case<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some((open, close)) = &self.brackets {
+            write!(f, "{open}")?;
+            write_list(f, &self.fields)?;
+            write!(f, "{close}")?;
+        }
+        Ok(())
     }
 }
 
 impl<'a> core::fmt::Display for Expr<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "expr")
+        match self {
+            Expr::Ident(s)
+            | Expr::Int(s)
+            | Expr::Float(s)
+            | Expr::Str(s)
+            | Expr::Interpolated(s, _)
+            | Expr::Bad(s) => write!(f, "{s}"),
+            Expr::Closure(closure) => write!(f, "{closure}"),
+            Expr::Block(block) => write!(f, "{block}"),
+            Expr::Array(open, items, close) => {
+                write!(f, "{open}")?;
+                write_list(f, items)?;
+                write!(f, "{close}")
+            }
+            Expr::Map(open, entries, close) => {
+                write!(f, "{open}")?;
+                write_list(f, entries)?;
+                write!(f, "{close}")
+            }
+            Expr::Spread(dots, value) => {
+                write!(f, "{dots}")?;
+                value.iter().try_for_each(|value| write!(f, "{value}"))
+            }
+            Expr::Binary(lhs, op, rhs) if **op == ".." => write!(f, "{lhs}{op}{rhs}"),
+            Expr::Binary(lhs, op, rhs) => write!(f, "{lhs} {op} {rhs}"),
+            Expr::Unary(op, expr) => write!(f, "{op}{expr}"),
+            Expr::Paren(open, expr, close) => write!(f, "{open}{expr}{close}"),
+            Expr::Tuple(open, items, close) => {
+                write!(f, "{open}")?;
+                write_list(f, items)?;
+                write!(f, "{close}")
+            }
+            Expr::Call(func, open, args, close) => {
+                write!(f, "{func}{open}")?;
+                write_list(f, args)?;
+                write!(f, "{close}")
+            }
+            Expr::Index(expr, open, index, close) => write!(f, "{expr}{open}{index}{close}"),
+            Expr::Dot(lhs, dot, rhs) => write!(f, "{lhs}{dot}{rhs}"),
+            Expr::Assign(target, op, value) => write!(f, "{target} {op} {value}"),
+            Expr::If(branches, otherwise) => {
+                for (i, (keyword, cond, body)) in branches.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "\n" };
+                    write!(f, "{sep}{keyword} {cond}{body}")?;
+                }
+                if let Some((keyword, body)) = otherwise {
+                    write!(f, "\n{keyword}{body}")?;
+                }
+                Ok(())
+            }
+            Expr::While(keyword, cond, body) => write!(f, "{keyword} {cond}{body}"),
+            Expr::For(keyword, pattern, in_, iter, body) => {
+                write!(f, "{keyword} {pattern} {in_} {iter}{body}")
+            }
+            Expr::Break(keyword) | Expr::Continue(keyword) | Expr::Return(keyword, None) => {
+                write!(f, "{keyword}")
+            }
+            Expr::Return(keyword, Some(value)) => write!(f, "{keyword} {value}"),
+            Expr::Let(keyword, mut_, pattern, eq, value) => {
+                write!(f, "{keyword} ")?;
+                if let Some(mut_) = mut_ {
+                    write!(f, "{mut_} ")?;
+                }
+                write!(f, "{pattern} {eq} {value}")
+            }
+            Expr::Match(keyword, value, arms) => {
+                write!(f, "{keyword} {value}")?;
+                for arm in arms {
+                    write!(f, "\n    {}", arm.to_string().replace('\n', "\n    "))?;
+                }
+                Ok(())
+            }
+            Expr::Struct(keyword, name, names) => {
+                write!(f, "{keyword} {name}")?;
+                names.iter().try_for_each(|n| write!(f, " {n}"))
+            }
+            Expr::Enum(keyword, name, cases) => {
+                write!(f, "{keyword} {name}")?;
+                cases.iter().try_for_each(|c| write!(f, " {c}"))
+            }
+            Expr::Impl(keyword, for_, name, items) => {
+                write!(f, "{keyword} ")?;
+                if let Some((trait_, for_)) = for_ {
+                    write!(f, "{trait_} {for_} ")?;
+                }
+                write!(f, "{name}")?;
+                for item in items {
+                    write!(f, "\n    {}", item.to_string().replace('\n', "\n    "))?;
+                }
+                Ok(())
+            }
+            Expr::Trait(keyword, name, items) => {
+                write!(f, "{keyword} {name}")?;
+                for item in items {
+                    write!(f, "\n    {}", item.to_string().replace('\n', "\n    "))?;
+                }
+                Ok(())
+            }
+            Expr::Fn(keyword, name, closure) => write!(f, "{keyword} {name}{closure}"),
+            Expr::Signature(keyword, name, open, formal_args, close) => {
+                write!(f, "{keyword} {name}{open}")?;
+                write_list(f, formal_args)?;
+                write!(f, "{close}")
+            }
+        }
+    }
+}
+
+// Items with their separators, spaced as in "a, b, c".
+fn write_list<T: core::fmt::Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    items: &[(T, Option<Span>)],
+) -> std::fmt::Result {
+    for (i, (item, sep)) in items.iter().enumerate() {
+        write!(f, "{item}")?;
+        if let Some(sep) = sep {
+            write!(f, "{sep}")?;
+            if i + 1 < items.len() {
+                write!(f, " ")?;
+            }
+        }
     }
+    Ok(())
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum FormalArg<'a> {
     Name(Span<'a>),
-    NameWithDefault(Span<'a>, Span<'a>, Expr<'a>),
+    /// `name: Type`. Nothing checks the type yet.
+    Typed(Span<'a>, Span<'a>, Span<'a>),
+    /// `name=value` or `name: Type = value`. The value is worked out
+    /// afresh by every call that leaves the argument out.
+    NameWithDefault(Box<FormalArg<'a>>, Span<'a>, Expr<'a>),
+    /// `*rest` takes the positional arguments left over as a tuple.
+    /// Parameters after it, or after a bare `*`, can only be passed by
+    /// keyword.
+    Rest(Span<'a>, Option<Span<'a>>),
+    /// A parameter taken apart, as in |(k, v)|.
+    Pattern(Pattern<'a>),
+    Bad(Span<'a>),
+}
+
+/// What `let`, `for`, assignment and closure parameters take a value
+/// apart into.
+///
+/// ```text
+/// (a, b) = f()
+/// let [first, ..rest] = xs
+/// for Point(x, y=height) in points
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern<'a> {
+    Name(Span<'a>),
+    /// `_` matches anything and binds nothing.
+    Wildcard(Span<'a>),
+    Tuple(Span<'a>, Vec<(Pattern<'a>, Option<Span<'a>>)>, Span<'a>),
+    Array(Span<'a>, Vec<(Pattern<'a>, Option<Span<'a>>)>, Span<'a>),
+    /// `..rest` or `..` in an array, the elements no other item takes.
+    Rest(Span<'a>, Option<Span<'a>>),
+    /// `Point(x, y=height)` takes fields by name.
+    Struct(
+        Span<'a>,
+        Span<'a>,
+        Vec<(FieldPattern<'a>, Option<Span<'a>>)>,
+        Span<'a>,
+    ),
+    /// A number or string, only in a `match`.
+    Literal(Box<Expr<'a>>),
+    /// `lo..hi`, numbers from `lo` up to but not including `hi`.
+    Range(Box<Expr<'a>>, Span<'a>, Box<Expr<'a>>),
+    /// A field or element, only on the left of an assignment.
+    Place(Box<Expr<'a>>),
     Bad(Span<'a>),
 }
 
+/// `pattern if guard => body` in a `match`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Arm<'a> {
+    pub(crate) pattern: Pattern<'a>,
+    pub(crate) guard: Option<(Span<'a>, Expr<'a>)>,
+    pub(crate) arrow: Span<'a>,
+    pub(crate) body: Expr<'a>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FieldPattern<'a> {
+    /// `x`, short for `x=x`, or the field in the same place if the
+    /// fields have no names.
+    Name(Span<'a>),
+    Named(Span<'a>, Span<'a>, Pattern<'a>),
+    /// `1` or `(a, b)`, matching the field in the same place.
+    Positional(Pattern<'a>),
+}
+
+/// A variant of an `enum`: `Empty`, `Circle(Float)` holding values in
+/// order, or `Rect{w, h}` holding them by name.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Case<'a> {
+    pub(crate) name: Span<'a>,
+    /// `(` and `)` or `{` and `}`, none for a variant that holds nothing.
+    pub(crate) brackets: Option<(Span<'a>, Span<'a>)>,
+    /// A type for each value in `(` `)`, or a name for each in `{` `}`.
+    pub(crate) fields: Vec<(Span<'a>, Option<Span<'a>>)>,
+}
+
+/// An argument in a call, `f(x, scale=2.0)`. Keyword arguments come
+/// after all the positional ones.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Arg<'a> {
+    Positional(Expr<'a>),
+    Keyword(Span<'a>, Span<'a>, Expr<'a>),
+}
+
+/// An entry of a map literal, `"k": v` or `..other`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Entry<'a> {
+    Pair(Expr<'a>, Span<'a>, Expr<'a>),
+    Spread(Span<'a>, Expr<'a>),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Closure<'a> {
-    open: Span<'a>,
-    formal_args: Vec<(FormalArg<'a>, Option<Span<'a>>)>,
-    close: Span<'a>,
-    body: Expr<'a>,
+    pub(crate) open: Span<'a>,
+    pub(crate) formal_args: Vec<(FormalArg<'a>, Option<Span<'a>>)>,
+    pub(crate) close: Span<'a>,
+    pub(crate) body: Expr<'a>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -70,11 +387,16 @@ pub enum Expr<'a> {
     Ident(Span<'a>),
     Int(Span<'a>),
     Float(Span<'a>),
-    Hex(Span<'a>),
     Str(Span<'a>),
+    Interpolated(Span<'a>, Vec<Segment<'a>>),
     Closure(Box<Closure<'a>>),
     Block(Block<'a>),
-    Array(Span<'a>, Vec<Expr<'a>>, Span<'a>),
+    Array(Span<'a>, Vec<(Expr<'a>, Option<Span<'a>>)>, Span<'a>),
+    /// `{"k": v, ..other}`, with later keys replacing earlier ones.
+    Map(Span<'a>, Vec<(Entry<'a>, Option<Span<'a>>)>, Span<'a>),
+    /// `..xs` in an array, the items of `xs`. A bare `..` is only
+    /// allowed when the array is an assignment target.
+    Spread(Span<'a>, Option<Box<Expr<'a>>>),
     Binary(Box<Expr<'a>>, Span<'a>, Box<Expr<'a>>),
     Unary(Span<'a>, Box<Expr<'a>>),
     Paren(Span<'a>, Box<Expr<'a>>, Span<'a>),
@@ -82,20 +404,538 @@ pub enum Expr<'a> {
     Call(
         Box<Expr<'a>>,
         Span<'a>,
-        Vec<(Expr<'a>, Option<Span<'a>>)>,
+        Vec<(Arg<'a>, Option<Span<'a>>)>,
         Span<'a>,
     ),
     Index(Box<Expr<'a>>, Span<'a>, Box<Expr<'a>>, Span<'a>),
     Dot(Box<Expr<'a>>, Span<'a>, Box<Expr<'a>>),
+    /// `target = value` or a compound assignment such as `target += value`.
+    Assign(Box<Pattern<'a>>, Span<'a>, Box<Expr<'a>>),
+    /// `if` and `elif` keywords with their conditions and bodies, then
+    /// any `else`.
+    If(
+        Vec<(Span<'a>, Expr<'a>, Expr<'a>)>,
+        Option<(Span<'a>, Box<Expr<'a>>)>,
+    ),
+    While(Span<'a>, Box<Expr<'a>>, Box<Expr<'a>>),
+    /// `for pattern in iterable` and the body.
+    For(
+        Span<'a>,
+        Pattern<'a>,
+        Span<'a>,
+        Box<Expr<'a>>,
+        Box<Expr<'a>>,
+    ),
+    Break(Span<'a>),
+    Continue(Span<'a>),
+    Return(Span<'a>, Option<Box<Expr<'a>>>),
+    /// `let pattern = value` or `let mut pattern = value`.
+    Let(
+        Span<'a>,
+        Option<Span<'a>>,
+        Pattern<'a>,
+        Span<'a>,
+        Box<Expr<'a>>,
+    ),
+    /// `match value` and the arms tried in order.
+    Match(Span<'a>, Box<Expr<'a>>, Vec<Arm<'a>>),
+    /// `struct Name a b c`, a type and the names of its fields.
+    Struct(Span<'a>, Span<'a>, Vec<Span<'a>>),
+    /// `enum Name A B(Int) C{x, y}`, a type and its variants. Like the
+    /// prelude's, each variant is bound by its own name as well as `Name.A`.
+    Enum(Span<'a>, Span<'a>, Vec<Case<'a>>),
+    /// `impl Name` or `impl Trait for Name`, and the `fn`s it adds to
+    /// the type as methods.
+    Impl(
+        Span<'a>,
+        Option<(Span<'a>, Span<'a>)>,
+        Span<'a>,
+        Vec<Expr<'a>>,
+    ),
+    /// `trait Name` and its methods, `Signature`s a type must implement
+    /// and `Fn`s it gets by default.
+    Trait(Span<'a>, Span<'a>, Vec<Expr<'a>>),
+    /// `fn name(params)` and its body, a method in an `impl` or `trait`.
+    /// The closure's brackets are the round ones.
+    Fn(Span<'a>, Span<'a>, Box<Closure<'a>>),
+    /// `fn name(params)` with no body, a method a trait requires.
+    Signature(
+        Span<'a>,
+        Span<'a>,
+        Span<'a>,
+        Vec<(FormalArg<'a>, Option<Span<'a>>)>,
+        Span<'a>,
+    ),
     Bad(Span<'a>),
 }
 
+/// Part of an interpolated string such as "x = {x:>8.3}".
+#[derive(Debug, PartialEq, Clone)]
+pub enum Segment<'a> {
+    Text(String),
+    Expr(Expr<'a>, Option<FormatSpec<'a>>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// [[fill]align][+][#][0][width][.precision][type] as in Rust and Python.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FormatSpec<'a> {
+    pub span: Span<'a>,
+    pub fill: char,
+    pub align: Option<Align>,
+    pub sign: bool,
+    pub alternate: bool,
+    pub zero: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+    pub kind: Option<char>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct ParseError<'a> {
     span: &'a str,
     reason: &'static str,
 }
 
+impl<'a> Expr<'a> {
+    /// Call `f` on this expression and then every expression inside it.
+    pub fn walk(&self, f: &mut impl FnMut(&Expr<'a>)) {
+        f(self);
+        match self {
+            Expr::Ident(_)
+            | Expr::Int(_)
+            | Expr::Float(_)
+            | Expr::Str(_)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Return(_, None)
+            | Expr::Struct(..)
+            | Expr::Enum(..)
+            | Expr::Bad(_) => (),
+            Expr::Return(_, Some(value)) => value.walk(f),
+            Expr::Impl(_, _, _, items) | Expr::Trait(_, _, items) => {
+                items.iter().for_each(|e| e.walk(f))
+            }
+            Expr::Signature(_, _, _, formal_args, _) => {
+                formal_args.iter().for_each(|(arg, _)| arg.walk(f))
+            }
+            Expr::Fn(_, _, closure) => {
+                closure.formal_args.iter().for_each(|(arg, _)| arg.walk(f));
+                closure.body.walk(f);
+            }
+            Expr::Let(_, _, pattern, _, value) => {
+                pattern.walk(f);
+                value.walk(f);
+            }
+            Expr::Match(_, value, arms) => {
+                value.walk(f);
+                for arm in arms {
+                    arm.pattern.walk(f);
+                    if let Some((_, guard)) = &arm.guard {
+                        guard.walk(f);
+                    }
+                    arm.body.walk(f);
+                }
+            }
+            Expr::If(branches, otherwise) => {
+                for (_, cond, body) in branches {
+                    cond.walk(f);
+                    body.walk(f);
+                }
+                if let Some((_, body)) = otherwise {
+                    body.walk(f);
+                }
+            }
+            Expr::While(_, cond, body) => {
+                cond.walk(f);
+                body.walk(f);
+            }
+            Expr::For(_, pattern, _, iter, body) => {
+                pattern.walk(f);
+                iter.walk(f);
+                body.walk(f);
+            }
+            Expr::Interpolated(_, segments) => {
+                for segment in segments {
+                    if let Segment::Expr(expr, _) = segment {
+                        expr.walk(f);
+                    }
+                }
+            }
+            Expr::Closure(closure) => {
+                closure.formal_args.iter().for_each(|(arg, _)| arg.walk(f));
+                closure.body.walk(f);
+            }
+            Expr::Block(block) => block.items.iter().for_each(|e| e.walk(f)),
+            Expr::Array(_, items, _) | Expr::Tuple(_, items, _) => {
+                items.iter().for_each(|(e, _)| e.walk(f))
+            }
+            Expr::Map(_, entries, _) => {
+                for (entry, _) in entries {
+                    match entry {
+                        Entry::Pair(key, _, value) => {
+                            key.walk(f);
+                            value.walk(f);
+                        }
+                        Entry::Spread(_, value) => value.walk(f),
+                    }
+                }
+            }
+            Expr::Spread(_, value) => value.iter().for_each(|value| value.walk(f)),
+            Expr::Binary(lhs, _, rhs) | Expr::Dot(lhs, _, rhs) | Expr::Index(lhs, _, rhs, _) => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Expr::Assign(target, _, value) => {
+                target.walk(f);
+                value.walk(f);
+            }
+            Expr::Unary(_, expr) | Expr::Paren(_, expr, _) => expr.walk(f),
+            Expr::Call(func, _, args, _) => {
+                func.walk(f);
+                args.iter().for_each(|(arg, _)| arg.value().walk(f));
+            }
+        }
+    }
+
+    /// A copy of this expression with every span replaced by `f(span)`,
+    /// used to move a tree onto a new version of the source.
+    pub fn map_spans<'b>(&self, f: &mut impl FnMut(&Span<'a>) -> Span<'b>) -> Expr<'b> {
+        let list = |items: &[(Expr<'a>, Option<Span<'a>>)], f: &mut _| {
+            items
+                .iter()
+                .map(|(e, sep)| (e.map_spans(f), sep.as_ref().map(&mut *f)))
+                .collect()
+        };
+        match self {
+            Expr::Ident(s) => Expr::Ident(f(s)),
+            Expr::Int(s) => Expr::Int(f(s)),
+            Expr::Float(s) => Expr::Float(f(s)),
+            Expr::Str(s) => Expr::Str(f(s)),
+            Expr::Bad(s) => Expr::Bad(f(s)),
+            Expr::Interpolated(s, segments) => Expr::Interpolated(
+                f(s),
+                segments
+                    .iter()
+                    .map(|segment| match segment {
+                        Segment::Text(text) => Segment::Text(text.clone()),
+                        Segment::Expr(expr, spec) => Segment::Expr(
+                            expr.map_spans(f),
+                            spec.as_ref().map(|spec| FormatSpec {
+                                span: f(&spec.span),
+                                ..*spec
+                            }),
+                        ),
+                    })
+                    .collect(),
+            ),
+            Expr::Closure(closure) => Expr::Closure(Box::new(closure.map_spans(f))),
+            Expr::Struct(keyword, name, fields) => {
+                Expr::Struct(f(keyword), f(name), fields.iter().map(&mut *f).collect())
+            }
+            Expr::Enum(keyword, name, cases) => Expr::Enum(
+                f(keyword),
+                f(name),
+                cases
+                    .iter()
+                    .map(|case| Case {
+                        name: f(&case.name),
+                        brackets: (case.brackets.as_ref()).map(|(open, close)| (f(open), f(close))),
+                        fields: (case.fields.iter())
+                            .map(|(field, sep)| (f(field), sep.as_ref().map(&mut *f)))
+                            .collect(),
+                    })
+                    .collect(),
+            ),
+            Expr::Impl(keyword, for_, name, items) => Expr::Impl(
+                f(keyword),
+                for_.as_ref().map(|(trait_, for_)| (f(trait_), f(for_))),
+                f(name),
+                items.iter().map(|e| e.map_spans(f)).collect(),
+            ),
+            Expr::Trait(keyword, name, items) => Expr::Trait(
+                f(keyword),
+                f(name),
+                items.iter().map(|e| e.map_spans(f)).collect(),
+            ),
+            Expr::Signature(keyword, name, open, formal_args, close) => Expr::Signature(
+                f(keyword),
+                f(name),
+                f(open),
+                formal_args
+                    .iter()
+                    .map(|(arg, sep)| (arg.map_spans(f), sep.as_ref().map(&mut *f)))
+                    .collect(),
+                f(close),
+            ),
+            Expr::Fn(keyword, name, closure) => {
+                Expr::Fn(f(keyword), f(name), Box::new(closure.map_spans(f)))
+            }
+            Expr::Block(block) => Expr::Block(Block {
+                begin: f(&block.begin),
+                items: block.items.iter().map(|e| e.map_spans(f)).collect(),
+                end: f(&block.end),
+            }),
+            Expr::Array(open, items, close) => Expr::Array(f(open), list(items, f), f(close)),
+            Expr::Map(open, entries, close) => Expr::Map(
+                f(open),
+                entries
+                    .iter()
+                    .map(|(entry, sep)| {
+                        let entry = match entry {
+                            Entry::Pair(key, colon, value) => {
+                                Entry::Pair(key.map_spans(f), f(colon), value.map_spans(f))
+                            }
+                            Entry::Spread(dots, value) => {
+                                Entry::Spread(f(dots), value.map_spans(f))
+                            }
+                        };
+                        (entry, sep.as_ref().map(&mut *f))
+                    })
+                    .collect(),
+                f(close),
+            ),
+            Expr::Spread(dots, value) => Expr::Spread(
+                f(dots),
+                value.as_ref().map(|value| Box::new(value.map_spans(f))),
+            ),
+            Expr::Tuple(open, items, close) => Expr::Tuple(f(open), list(items, f), f(close)),
+            Expr::Binary(lhs, op, rhs) => Expr::Binary(
+                Box::new(lhs.map_spans(f)),
+                f(op),
+                Box::new(rhs.map_spans(f)),
+            ),
+            Expr::If(branches, otherwise) => Expr::If(
+                branches
+                    .iter()
+                    .map(|(keyword, cond, body)| (f(keyword), cond.map_spans(f), body.map_spans(f)))
+                    .collect(),
+                otherwise
+                    .as_ref()
+                    .map(|(keyword, body)| (f(keyword), Box::new(body.map_spans(f)))),
+            ),
+            Expr::While(keyword, cond, body) => Expr::While(
+                f(keyword),
+                Box::new(cond.map_spans(f)),
+                Box::new(body.map_spans(f)),
+            ),
+            Expr::For(keyword, pattern, in_, iter, body) => Expr::For(
+                f(keyword),
+                pattern.map_spans(f),
+                f(in_),
+                Box::new(iter.map_spans(f)),
+                Box::new(body.map_spans(f)),
+            ),
+            Expr::Break(keyword) => Expr::Break(f(keyword)),
+            Expr::Continue(keyword) => Expr::Continue(f(keyword)),
+            Expr::Return(keyword, value) => Expr::Return(
+                f(keyword),
+                value.as_ref().map(|value| Box::new(value.map_spans(f))),
+            ),
+            Expr::Let(keyword, mut_, pattern, eq, value) => Expr::Let(
+                f(keyword),
+                mut_.as_ref().map(&mut *f),
+                pattern.map_spans(f),
+                f(eq),
+                Box::new(value.map_spans(f)),
+            ),
+            Expr::Match(keyword, value, arms) => Expr::Match(
+                f(keyword),
+                Box::new(value.map_spans(f)),
+                arms.iter()
+                    .map(|arm| Arm {
+                        pattern: arm.pattern.map_spans(f),
+                        guard: arm
+                            .guard
+                            .as_ref()
+                            .map(|(keyword, guard)| (f(keyword), guard.map_spans(f))),
+                        arrow: f(&arm.arrow),
+                        body: arm.body.map_spans(f),
+                    })
+                    .collect(),
+            ),
+            Expr::Assign(target, op, value) => Expr::Assign(
+                Box::new(target.map_spans(f)),
+                f(op),
+                Box::new(value.map_spans(f)),
+            ),
+            Expr::Dot(lhs, dot, rhs) => Expr::Dot(
+                Box::new(lhs.map_spans(f)),
+                f(dot),
+                Box::new(rhs.map_spans(f)),
+            ),
+            Expr::Index(lhs, open, rhs, close) => Expr::Index(
+                Box::new(lhs.map_spans(f)),
+                f(open),
+                Box::new(rhs.map_spans(f)),
+                f(close),
+            ),
+            Expr::Unary(op, expr) => Expr::Unary(f(op), Box::new(expr.map_spans(f))),
+            Expr::Paren(open, expr, close) => {
+                Expr::Paren(f(open), Box::new(expr.map_spans(f)), f(close))
+            }
+            Expr::Call(func, open, args, close) => Expr::Call(
+                Box::new(func.map_spans(f)),
+                f(open),
+                args.iter()
+                    .map(|(arg, sep)| {
+                        let arg = match arg {
+                            Arg::Positional(value) => Arg::Positional(value.map_spans(f)),
+                            Arg::Keyword(name, eq, value) => {
+                                Arg::Keyword(f(name), f(eq), value.map_spans(f))
+                            }
+                        };
+                        (arg, sep.as_ref().map(&mut *f))
+                    })
+                    .collect(),
+                f(close),
+            ),
+        }
+    }
+}
+
+impl<'a> Closure<'a> {
+    /// A copy of this closure with every span replaced by `f(span)`, see
+    /// `Expr::map_spans`.
+    pub fn map_spans<'b>(&self, f: &mut impl FnMut(&Span<'a>) -> Span<'b>) -> Closure<'b> {
+        Closure {
+            open: f(&self.open),
+            formal_args: self
+                .formal_args
+                .iter()
+                .map(|(arg, sep)| (arg.map_spans(f), sep.as_ref().map(&mut *f)))
+                .collect(),
+            close: f(&self.close),
+            body: self.body.map_spans(f),
+        }
+    }
+}
+
+impl<'a> Pattern<'a> {
+    /// Call `f` on every expression inside the pattern.
+    pub fn walk(&self, f: &mut impl FnMut(&Expr<'a>)) {
+        match self {
+            Pattern::Name(_) | Pattern::Wildcard(_) | Pattern::Rest(..) | Pattern::Bad(_) => (),
+            Pattern::Tuple(_, items, _) | Pattern::Array(_, items, _) => {
+                items.iter().for_each(|(p, _)| p.walk(f))
+            }
+            Pattern::Struct(_, _, fields, _) => {
+                for (field, _) in fields {
+                    if let FieldPattern::Named(_, _, pattern) | FieldPattern::Positional(pattern) =
+                        field
+                    {
+                        pattern.walk(f);
+                    }
+                }
+            }
+            Pattern::Place(expr) | Pattern::Literal(expr) => expr.walk(f),
+            Pattern::Range(lo, _, hi) => {
+                lo.walk(f);
+                hi.walk(f);
+            }
+        }
+    }
+
+    /// A copy of this pattern with every span replaced by `f(span)`, see
+    /// `Expr::map_spans`.
+    pub fn map_spans<'b>(&self, f: &mut impl FnMut(&Span<'a>) -> Span<'b>) -> Pattern<'b> {
+        let list = |items: &[(Pattern<'a>, Option<Span<'a>>)], f: &mut _| {
+            items
+                .iter()
+                .map(|(p, sep)| (p.map_spans(f), sep.as_ref().map(&mut *f)))
+                .collect()
+        };
+        match self {
+            Pattern::Name(s) => Pattern::Name(f(s)),
+            Pattern::Wildcard(s) => Pattern::Wildcard(f(s)),
+            Pattern::Bad(s) => Pattern::Bad(f(s)),
+            Pattern::Tuple(open, items, close) => Pattern::Tuple(f(open), list(items, f), f(close)),
+            Pattern::Array(open, items, close) => Pattern::Array(f(open), list(items, f), f(close)),
+            Pattern::Rest(dots, name) => Pattern::Rest(f(dots), name.as_ref().map(&mut *f)),
+            Pattern::Struct(name, open, fields, close) => Pattern::Struct(
+                f(name),
+                f(open),
+                fields
+                    .iter()
+                    .map(|(field, sep)| {
+                        let field = match field {
+                            FieldPattern::Name(s) => FieldPattern::Name(f(s)),
+                            FieldPattern::Named(s, eq, pattern) => {
+                                FieldPattern::Named(f(s), f(eq), pattern.map_spans(f))
+                            }
+                            FieldPattern::Positional(pattern) => {
+                                FieldPattern::Positional(pattern.map_spans(f))
+                            }
+                        };
+                        (field, sep.as_ref().map(&mut *f))
+                    })
+                    .collect(),
+                f(close),
+            ),
+            Pattern::Place(expr) => Pattern::Place(Box::new(expr.map_spans(f))),
+            Pattern::Literal(expr) => Pattern::Literal(Box::new(expr.map_spans(f))),
+            Pattern::Range(lo, dots, hi) => Pattern::Range(
+                Box::new(lo.map_spans(f)),
+                f(dots),
+                Box::new(hi.map_spans(f)),
+            ),
+        }
+    }
+}
+
+impl<'a> Arg<'a> {
+    pub fn value(&self) -> &Expr<'a> {
+        match self {
+            Arg::Positional(value) | Arg::Keyword(_, _, value) => value,
+        }
+    }
+}
+
+impl<'a> FormalArg<'a> {
+    /// Call `f` on every expression in the parameter, such as its default.
+    pub fn walk(&self, f: &mut impl FnMut(&Expr<'a>)) {
+        match self {
+            FormalArg::NameWithDefault(arg, _, expr) => {
+                arg.walk(f);
+                expr.walk(f);
+            }
+            FormalArg::Pattern(pattern) => pattern.walk(f),
+            _ => (),
+        }
+    }
+
+    /// A copy of this parameter with every span replaced by `f(span)`, see
+    /// `Expr::map_spans`.
+    pub fn map_spans<'b>(&self, f: &mut impl FnMut(&Span<'a>) -> Span<'b>) -> FormalArg<'b> {
+        match self {
+            FormalArg::Name(s) => FormalArg::Name(f(s)),
+            FormalArg::Typed(s, colon, ty) => FormalArg::Typed(f(s), f(colon), f(ty)),
+            FormalArg::NameWithDefault(arg, eq, expr) => {
+                FormalArg::NameWithDefault(Box::new(arg.map_spans(f)), f(eq), expr.map_spans(f))
+            }
+            FormalArg::Rest(star, name) => FormalArg::Rest(f(star), name.as_ref().map(&mut *f)),
+            FormalArg::Pattern(pattern) => FormalArg::Pattern(pattern.map_spans(f)),
+            FormalArg::Bad(s) => FormalArg::Bad(f(s)),
+        }
+    }
+
+    /// The name a caller can pass the argument by, if it has one.
+    pub fn name(&self) -> Option<&Span<'a>> {
+        match self {
+            FormalArg::Name(name) | FormalArg::Typed(name, _, _) => Some(name),
+            FormalArg::Rest(_, name) => name.as_ref(),
+            FormalArg::NameWithDefault(arg, _, _) => arg.name(),
+            FormalArg::Pattern(_) | FormalArg::Bad(_) => None,
+        }
+    }
+}
+
 pub trait Parse<'l, 'a>: Sized {
     // Return either Some(item) or None if it cannot be one from
     // the first token.
@@ -116,21 +956,28 @@ impl<'l, 'a> Parse<'l, 'a> for Expr<'a> {
             | Token::Punct("!")
             | Token::Punct("|")
             | Token::Punct("(")
+            | Token::Punct("[")
+            | Token::Punct("{")
             | Token::Int(_)
             | Token::Float(_)
-            | Token::Hex(_)
             | Token::Ident(_)
-            | Token::Str(_) => Some(parse_binop(lex, 0)),
+            | Token::Str(_)
+            | Token::Keyword(
+                "if" | "while" | "for" | "break" | "continue" | "return" | "let" | "match"
+                | "struct" | "enum" | "impl" | "trait",
+            ) => Some(parse_assign(lex)),
             _ => None,
         }
     }
 }
 
-/// |x, y=5, z: u64 = 3| x + y + z
+/// |x, y=5, z: u64 = 3, *rest, key=1| x + y + z
 fn parse_closure<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
     macro_rules! expect {
         ($token : pat) => {{
             if !matches!(lex.peek(), $token) {
+                let msg = format!("expected `|`, found {}", lex.peek());
+                lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
                 return Expr::Bad(lex.advance());
             }
             lex.advance()
@@ -138,7 +985,7 @@ fn parse_closure<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
     }
 
     let open = expect!(Token::Punct("|"));
-    let formal_args = parse_formal_args(lex);
+    let formal_args = parse_formal_args(lex, "|");
     let close = expect!(Token::Punct("|"));
     if let Some(body) = Expr::parse(lex) {
         let function = Closure {
@@ -149,7 +996,8 @@ fn parse_closure<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
         };
         Expr::Closure(Box::new(function))
     } else {
-        lex.error(lex.span(), format!("expected expression after |..|"));
+        let msg = format!("expected expression after |..|, found {}", lex.peek());
+        lex.error(diag::EXPECTED_EXPR, lex.span(), msg);
         Expr::Bad(lex.advance())
     }
 }
@@ -162,19 +1010,20 @@ fn parse_block<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
         if let Some(item) = Expr::parse(lex) {
             items.push(item);
         } else {
-            lex.error(lex.span(), "expected expression".into());
-            items.push(Expr::Bad(lex.advance()));
+            let msg = format!("expected expression, found {}", lex.peek());
+            lex.error(diag::EXPECTED_EXPR, lex.span(), msg);
+            items.push(parse_bad(lex));
         }
         if matches!(lex.peek(), Token::Newline(_)) || matches!(lex.peek(), Token::Punct(";")) {
             lex.advance();
         } else if matches!(lex.peek(), Token::End(_)) || matches!(lex.peek(), Token::Eof(_)) {
             break;
+        } else if lex.follows_block() {
+            continue;
         } else {
-            lex.error(
-                lex.span(),
-                format!("expected newline or ; got {:?}", lex.peek()),
-            );
-            items.push(Expr::Bad(lex.advance()));
+            let msg = format!("expected newline or `;`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_SEPARATOR, lex.span(), msg);
+            items.push(parse_bad(lex));
         }
     }
     if matches!(lex.peek(), Token::End(_)) {
@@ -182,80 +1031,469 @@ fn parse_block<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
         Expr::Block(Block { begin, items, end })
     } else {
         let bad = lex.advance();
-        lex.error(bad.clone(), format!("expected closing scope."));
+        lex.report(
+            Diagnostic::error(diag::UNTERMINATED_BLOCK, "unterminated block")
+                .with_primary(bad.clone(), "expected end of block")
+                .with_secondary(begin, "block starts here"),
+        );
         Expr::Bad(bad)
     }
 }
 
+/// x = 1, a[i] += 2, p.x = q.x = 0, (a, b) = (b, a)
+/// Assignment binds loosest of all and groups to the right.
+fn parse_assign<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let start = lex.checkpoint();
+    let target = parse_binop(lex, 0);
+    if lex.follows_block()
+        || !matches!(
+            lex.peek(),
+            Token::Punct("=" | "+=" | "-=" | "*=" | "/=" | "%=")
+        )
+    {
+        return target;
+    }
+    let op = lex.advance();
+    let target = assign_target(lex, target, &op);
+    let value = parse_value(lex, &op);
+    node(
+        lex,
+        start,
+        Expr::Assign(Box::new(target), op, Box::new(value)),
+    )
+}
+
+// The pattern an expression on the left of `op` stands for, reporting
+// anything that cannot be assigned to.
+fn assign_target<'a>(lex: &mut Lex<'a>, target: Expr<'a>, op: &Span<'a>) -> Pattern<'a> {
+    let plain = **op == "=";
+    match target {
+        Expr::Ident(name) if *name == "_" && plain => Pattern::Wildcard(name),
+        Expr::Ident(name) => Pattern::Name(name),
+        Expr::Index(..) | Expr::Dot(..) => Pattern::Place(Box::new(target)),
+        Expr::Paren(_, inner, _) if plain => assign_target(lex, *inner, op),
+        Expr::Tuple(open, items, close) if plain => Pattern::Tuple(
+            open,
+            items
+                .into_iter()
+                .map(|(item, sep)| (assign_target(lex, item, op), sep))
+                .collect(),
+            close,
+        ),
+        Expr::Array(open, items, close) if plain => {
+            let items = items
+                .into_iter()
+                .map(|(item, sep)| {
+                    let item = match item {
+                        Expr::Spread(dots, None) => Pattern::Rest(dots, None),
+                        Expr::Spread(dots, Some(value)) => match *value {
+                            Expr::Ident(name) => Pattern::Rest(dots, Some(name)),
+                            value => {
+                                assign_target(lex, Expr::Spread(dots, Some(Box::new(value))), op)
+                            }
+                        },
+                        item => assign_target(lex, item, op),
+                    };
+                    (item, sep)
+                })
+                .collect::<Vec<_>>();
+            check_rests(lex, &items);
+            Pattern::Array(open, items, close)
+        }
+        target => {
+            let span = expr_span(lex, &target);
+            let help = match target {
+                _ if !plain => format!("`{op}` needs a single name, field or indexed element"),
+                // f(x) = 1 is a mistake far more often than a struct pattern.
+                Expr::Call(..) => format!("to take a struct apart, use `let {target} = ..`"),
+                _ => "only names, fields, indexed elements and patterns of them can be assigned to"
+                    .to_string(),
+            };
+            lex.report(
+                Diagnostic::error(
+                    diag::INVALID_ASSIGN_TARGET,
+                    format!("cannot assign to `{target}`"),
+                )
+                .with_primary(span.clone(), "")
+                .with_secondary(op.clone(), "assignment here")
+                .with_help(help),
+            );
+            Pattern::Bad(span)
+        }
+    }
+}
+
+// The value after `=` in an assignment or `let`.
+fn parse_value<'a>(lex: &mut Lex<'a>, op: &Span<'a>) -> Expr<'a> {
+    Expr::parse(lex).unwrap_or_else(|| {
+        let msg = format!("expected expression after `{op}`, found {}", lex.peek());
+        lex.error(diag::EXPECTED_EXPR, lex.span(), msg);
+        Expr::Bad(lex.span())
+    })
+}
+
+/// x, _, (a, b), [first, ..rest], Point(x, y=height), Shape.Rect{w, h}, -1, "s", 0..10
+fn parse_pattern<'a>(lex: &mut Lex<'a>) -> Pattern<'a> {
+    let start = lex.checkpoint();
+    let pattern = match lex.peek() {
+        // Names are single tokens, so they get no node of their own.
+        Token::Ident("_") => return Pattern::Wildcard(lex.advance()),
+        Token::Ident(_) => {
+            let name = lex.advance();
+            return parse_named_pattern(lex, start, name);
+        }
+        Token::Punct("(") => {
+            let open = lex.advance();
+            let mut items = parse_list(lex, ")", parse_pattern);
+            let close = parse_close(lex, &open, ")");
+            if matches!(items.as_slice(), [(_, None)]) {
+                items.pop().unwrap().0
+            } else {
+                Pattern::Tuple(open, items, close)
+            }
+        }
+        Token::Int(_) | Token::Float(_) | Token::Str(_) | Token::Punct("-") => {
+            let lo = parse_literal_pattern(lex);
+            if lex.peek() == &Token::Punct("..") {
+                let dots = lex.advance();
+                let hi = parse_literal_pattern(lex);
+                Pattern::Range(Box::new(lo), dots, Box::new(hi))
+            } else {
+                Pattern::Literal(Box::new(lo))
+            }
+        }
+        Token::Punct("[") => {
+            let open = lex.advance();
+            let items = parse_list(lex, "]", parse_element_pattern);
+            let close = parse_close(lex, &open, "]");
+            check_rests(lex, &items);
+            Pattern::Array(open, items, close)
+        }
+        _ => {
+            let msg = format!("expected a pattern, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            return Pattern::Bad(lex.span());
+        }
+    };
+    lex.finish_node(SyntaxKind::Pattern, start);
+    pattern
+}
+
+// Report any `..` after the first in an array pattern.
+fn check_rests<'a>(lex: &mut Lex<'a>, items: &[(Pattern<'a>, Option<Span<'a>>)]) {
+    let mut rests = items.iter().filter_map(|(item, _)| match item {
+        Pattern::Rest(dots, _) => Some(dots.clone()),
+        _ => None,
+    });
+    if let (Some(first), Some(second)) = (rests.next(), rests.next()) {
+        lex.report(
+            Diagnostic::error(
+                diag::MULTIPLE_REST,
+                "an array pattern can only have one `..`",
+            )
+            .with_primary(second, "")
+            .with_secondary(first, "first `..` here"),
+        );
+    }
+}
+
+// 1, -2.5 or "s" in a pattern.
+fn parse_literal_pattern<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let start = lex.checkpoint();
+    let expr = match lex.peek() {
+        Token::Punct("-") => {
+            let minus = lex.advance();
+            if !matches!(lex.peek(), Token::Int(_) | Token::Float(_)) {
+                let msg = format!("expected a number after `-`, found {}", lex.peek());
+                lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+                return Expr::Bad(lex.span());
+            }
+            let number = parse_literal_pattern(lex);
+            Expr::Unary(minus, Box::new(number))
+        }
+        Token::Int(_) => Expr::Int(lex.advance()),
+        Token::Float(_) => Expr::Float(lex.advance()),
+        Token::Str(text) if !is_interpolated(text) => Expr::Str(lex.advance()),
+        _ => {
+            let msg = format!("expected a number or plain string, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            return Expr::Bad(lex.span());
+        }
+    };
+    node(lex, start, expr)
+}
+
+// An item of an array pattern, which may be `..rest`.
+fn parse_element_pattern<'a>(lex: &mut Lex<'a>) -> Pattern<'a> {
+    if lex.peek() != &Token::Punct("..") {
+        return parse_pattern(lex);
+    }
+    let start = lex.checkpoint();
+    let dots = lex.advance();
+    let name = if matches!(lex.peek(), Token::Ident(_)) {
+        Some(lex.advance())
+    } else {
+        None
+    };
+    lex.finish_node(SyntaxKind::Pattern, start);
+    Pattern::Rest(dots, name)
+}
+
+// The rest of a pattern starting with a name: nothing for a name to bind
+// or a variant holding nothing, or the fields of `Point(x, y=height)`,
+// `Some(1)` or `Rect{w, h}`. `Shape.Rect` names a variant with its enum.
+fn parse_named_pattern<'a>(lex: &mut Lex<'a>, start: usize, name: Span<'a>) -> Pattern<'a> {
+    let mut name = name;
+    let qualified = lex.peek() == &Token::Punct(".");
+    if qualified {
+        lex.advance();
+        let variant = if matches!(lex.peek(), Token::Ident(_)) {
+            lex.advance()
+        } else {
+            let msg = format!("expected a variant after `.`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            lex.span()
+        };
+        name = lex.cover([name, variant]).unwrap();
+    }
+    let close = match lex.peek() {
+        Token::Punct("(") => ")",
+        Token::Punct("{") => "}",
+        _ => {
+            if qualified {
+                lex.finish_node(SyntaxKind::Pattern, start);
+            }
+            return Pattern::Name(name);
+        }
+    };
+    let open = lex.advance();
+    let fields = parse_list(lex, close, parse_field_pattern);
+    let close = parse_close(lex, &open, close);
+    lex.finish_node(SyntaxKind::Pattern, start);
+    Pattern::Struct(name, open, fields, close)
+}
+
+// x or x=pattern in a struct pattern.
+fn parse_field_pattern<'a>(lex: &mut Lex<'a>) -> FieldPattern<'a> {
+    let start = lex.checkpoint();
+    match lex.peek() {
+        Token::Ident(name) if *name != "_" => {
+            let name = lex.advance();
+            match lex.peek() {
+                Token::Punct("=") => {
+                    let eq = lex.advance();
+                    FieldPattern::Named(name, eq, parse_pattern(lex))
+                }
+                Token::Punct("(" | "{" | ".") => {
+                    FieldPattern::Positional(parse_named_pattern(lex, start, name))
+                }
+                _ => FieldPattern::Name(name),
+            }
+        }
+        _ => FieldPattern::Positional(parse_pattern(lex)),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+// Binding power of the unary operators, tighter than everything but `**`
+// so that -2 ** 2 is -(2 ** 2) and -a * b is (-a) * b.
+const UNARY: usize = 110;
+
+// Defaults of closure parameters bind tighter than the `|` after them, so
+// `|x=a or b|` needs brackets.
+const DEFAULT: usize = 60;
+
+/// The binary operators, loosest first.
+///
+/// | operators                    | associativity |
+/// |------------------------------|---------------|
+/// | `..`                         | none          |
+/// | `or`                         | left          |
+/// | `and`                        | left          |
+/// | `==` `!=` `<` `>` `<=` `>=`  | none          |
+/// | `\|`                         | left          |
+/// | `^`                          | left          |
+/// | `&`                          | left          |
+/// | `<<` `>>` `>>>`              | left          |
+/// | `+` `-`                      | left          |
+/// | `*` `/` `%`                  | left          |
+/// | unary `-` `+` `!`            |               |
+/// | `**`                         | right         |
+///
+/// Assignment is looser than all of these, see `parse_assign`.
+fn binary_op(token: &Token) -> Option<(usize, Assoc)> {
+    Some(match token {
+        Token::Punct("..") => (10, Assoc::None),
+        Token::Keyword("or") => (20, Assoc::Left),
+        Token::Keyword("and") => (30, Assoc::Left),
+        Token::Punct("==" | "!=" | "<" | ">" | "<=" | ">=") => (40, Assoc::None),
+        Token::Punct("|") => (50, Assoc::Left),
+        Token::Punct("^") => (60, Assoc::Left),
+        Token::Punct("&") => (70, Assoc::Left),
+        Token::Punct("<<" | ">>" | ">>>") => (80, Assoc::Left),
+        Token::Punct("+" | "-") => (90, Assoc::Left),
+        Token::Punct("*" | "/" | "%") => (100, Assoc::Left),
+        Token::Punct("**") => (120, Assoc::Right),
+        _ => return None,
+    })
+}
+
 /// A binop is a series of atoms joined by binary operators +, -, * etc.
 fn parse_binop<'a>(lex: &mut Lex<'a>, min_precidence: usize) -> Expr<'a> {
     // println!("parse_binop {:?}", lex.peek());
+    let start = lex.checkpoint();
     let mut lhs = parse_atom(lex);
     loop {
-        let precidence = match lex.peek() {
-            Token::Punct("+") => 30,
-            Token::Punct("-") => 40,
-            Token::Punct("*") => 40,
-            Token::Punct("/") => 40,
-            Token::Punct("%") => 40,
-            Token::Punct("**") => 50,
-            _ => break lhs,
+        // A block ends its line, so an operator after it starts a new one.
+        if lex.follows_block() {
+            break lhs;
+        }
+        let Some((precidence, assoc)) = binary_op(lex.peek()) else {
+            break lhs;
         };
-
         if precidence < min_precidence {
             break lhs;
-        } else {
-            let span = lex.advance();
-            let rhs = parse_binop(lex, precidence + 1);
-            lhs = Expr::Binary(Box::new(lhs), span, Box::new(rhs));
+        }
+        let span = lex.advance();
+        let next = match assoc {
+            Assoc::Right => precidence,
+            Assoc::Left | Assoc::None => precidence + 1,
+        };
+        let rhs = parse_binop(lex, next);
+        lhs = node(
+            lex,
+            start,
+            Expr::Binary(Box::new(lhs), span.clone(), Box::new(rhs)),
+        );
+        if assoc == Assoc::None && binary_op(lex.peek()) == Some((precidence, assoc)) {
+            lex.report(
+                Diagnostic::error(
+                    diag::CHAINED_OPERATOR,
+                    format!("`{span}` operators cannot be chained"),
+                )
+                .with_primary(lex.span(), "")
+                .with_secondary(span, "first one here")
+                .with_help("add parentheses"),
+            );
         }
     }
 }
 
 // An atom is the bread in the binop sandwich.
 // 1, fred, "xyz", +1, fred[2], fred(1, 2, 3), [1, 2, 3], (1, 2, 3), (1+2)
+// {"k": v}
 // fred(1)(2) fred[1](2)
 fn parse_atom<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
-    println!("parse_atom {:?}", lex.peek());
-    let mut prefix = match lex.peek() {
+    let start = lex.checkpoint();
+    let prefix = match lex.peek() {
+        Token::Keyword("if") => parse_if(lex),
+        Token::Keyword("while") => parse_while(lex),
+        Token::Keyword("for") => parse_for(lex),
+        Token::Keyword("let") => parse_let(lex),
+        Token::Keyword("match") => parse_match(lex),
+        Token::Keyword("struct") => {
+            let (keyword, name) = parse_declared(lex);
+            Expr::Struct(keyword, name, parse_names(lex, |lex| lex.advance()))
+        }
+        Token::Keyword("enum") => {
+            let (keyword, name) = parse_declared(lex);
+            Expr::Enum(keyword, name, parse_names(lex, parse_case))
+        }
+        Token::Keyword("impl") => parse_impl(lex),
+        Token::Keyword("trait") => {
+            let (keyword, name) = parse_declared(lex);
+            let items = parse_methods(lex, &keyword, true);
+            Expr::Trait(keyword, name, items)
+        }
+        Token::Keyword("break") => Expr::Break(lex.advance()),
+        Token::Keyword("continue") => Expr::Continue(lex.advance()),
+        Token::Keyword("return") => {
+            let keyword = lex.advance();
+            Expr::Return(keyword, Expr::parse(lex).map(Box::new))
+        }
         Token::Punct("|") => parse_closure(lex),
         Token::Begin(_) => parse_block(lex),
         Token::Punct("!") | Token::Punct("+") | Token::Punct("-") => {
-            Expr::Unary(lex.advance(), Box::new(parse_atom(lex)))
+            Expr::Unary(lex.advance(), Box::new(parse_binop(lex, UNARY)))
         }
         Token::Punct("(") => {
             let lparen = lex.advance();
             let mut args = parse_args(lex);
             if matches!(args.as_slice(), &[(_, None)]) {
                 let expr = args.pop().unwrap().0;
-                Expr::Paren(lparen, Box::new(expr), parse_close(lex, ")"))
+                let rparen = parse_close(lex, &lparen, ")");
+                Expr::Paren(lparen, Box::new(expr), rparen)
             } else {
-                Expr::Tuple(lparen, args, parse_close(lex, ")"))
+                let rparen = parse_close(lex, &lparen, ")");
+                Expr::Tuple(lparen, args, rparen)
             }
         }
+        Token::Punct("[") => {
+            let open = lex.advance();
+            let items = parse_list(lex, "]", parse_element);
+            let close = parse_close(lex, &open, "]");
+            Expr::Array(open, items, close)
+        }
+        Token::Punct("{") => {
+            let open = lex.advance();
+            let entries = parse_list(lex, "}", parse_entry);
+            let close = parse_close(lex, &open, "}");
+            Expr::Map(open, entries, close)
+        }
         Token::Ident(_) => Expr::Ident(lex.advance()),
         Token::Int(_) => Expr::Int(lex.advance()),
         Token::Float(_) => Expr::Float(lex.advance()),
-        Token::Hex(_) => Expr::Hex(lex.advance()),
+        Token::Str(text) if is_interpolated(text) => parse_interpolated(lex),
         Token::Str(_) => Expr::Str(lex.advance()),
+        // The lexer has already reported these.
+        Token::UnknownToken(_) | Token::UnterminatedString(_) => return parse_bad(lex),
         _ => {
+            let msg = format!("expected expression, found {}", lex.peek());
             let span = lex.advance();
-            lex.error(span.clone(), format!("parse_atom"));
-            return Expr::Bad(span);
+            lex.error(diag::EXPECTED_EXPR, span.clone(), msg);
+            return node(lex, start, Expr::Bad(span));
         }
     };
+    let mut prefix = node(lex, start, prefix);
+    // These end with a block or an expression, so nothing can follow them.
+    if matches!(
+        prefix,
+        Expr::If(..)
+            | Expr::While(..)
+            | Expr::For(..)
+            | Expr::Break(_)
+            | Expr::Continue(_)
+            | Expr::Return(..)
+            | Expr::Let(..)
+            | Expr::Match(..)
+            | Expr::Struct(..)
+            | Expr::Enum(..)
+            | Expr::Impl(..)
+            | Expr::Trait(..)
+    ) {
+        return prefix;
+    }
     loop {
-        prefix = match lex.peek() {
+        if lex.follows_block() {
+            return prefix;
+        }
+        let expr = match lex.peek() {
             Token::Punct("[") => {
                 let lspan = lex.advance();
-                let expr = parse_binop(lex, usize::MAX);
-                let rspan = parse_close(lex, "]");
+                let expr = parse_binop(lex, 0);
+                let rspan = parse_close(lex, &lspan, "]");
                 Expr::Index(Box::new(prefix), lspan, Box::new(expr), rspan)
             }
             Token::Punct("(") => {
                 let lspan = lex.advance();
-                let args = parse_args(lex);
-                let rspan = parse_close(lex, ")");
+                let args = parse_call_args(lex);
+                let rspan = parse_close(lex, &lspan, ")");
                 Expr::Call(Box::new(prefix), lspan, args, rspan)
             }
             Token::Punct(".") => {
@@ -264,15 +1502,529 @@ fn parse_atom<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
                 Expr::Dot(Box::new(prefix), lspan, Box::new(rhs))
             }
             _ => return prefix,
+        };
+        prefix = node(lex, start, expr);
+    }
+}
+
+// The source text of an expression, for diagnostics.
+fn expr_span<'a>(lex: &Lex<'a>, expr: &Expr<'a>) -> Span<'a> {
+    let mut spans = vec![];
+    expr.map_spans(&mut |span| {
+        spans.push(span.clone());
+        span.clone()
+    });
+    lex.cover(spans).unwrap_or_else(|| lex.span())
+}
+
+/// if x < 0
+///     -1
+/// elif x == 0
+///     0
+/// else
+///     1
+fn parse_if<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let mut branches = vec![];
+    loop {
+        let keyword = lex.advance();
+        let cond = parse_condition(lex, &keyword);
+        let body = parse_body(lex, &keyword);
+        branches.push((keyword, cond, body));
+        if !matches!(lex.peek(), Token::Keyword("elif")) {
+            break;
+        }
+    }
+    let otherwise = if matches!(lex.peek(), Token::Keyword("else")) {
+        let keyword = lex.advance();
+        let body = parse_body(lex, &keyword);
+        Some((keyword, Box::new(body)))
+    } else {
+        None
+    };
+    Expr::If(branches, otherwise)
+}
+
+/// while i < n
+///     i += 1
+fn parse_while<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let keyword = lex.advance();
+    let cond = parse_condition(lex, &keyword);
+    let body = parse_body(lex, &keyword);
+    Expr::While(keyword, Box::new(cond), Box::new(body))
+}
+
+/// for (i, x) in enumerate(xs)
+///     print(i, x)
+fn parse_for<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let keyword = lex.advance();
+    let pattern = parse_pattern(lex);
+    let in_ = if matches!(lex.peek(), Token::Keyword("in")) {
+        lex.advance()
+    } else {
+        let msg = format!("expected `in`, found {}", lex.peek());
+        lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+        lex.span()
+    };
+    let iter = parse_condition(lex, &keyword);
+    let body = parse_body(lex, &keyword);
+    Expr::For(keyword, pattern, in_, Box::new(iter), Box::new(body))
+}
+
+/// let x = 1
+/// let mut (lo, hi) = bounds
+fn parse_let<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let keyword = lex.advance();
+    let mut_ = if matches!(lex.peek(), Token::Keyword("mut")) {
+        Some(lex.advance())
+    } else {
+        None
+    };
+    let pattern = parse_pattern(lex);
+    let eq = if matches!(lex.peek(), Token::Punct("=")) {
+        lex.advance()
+    } else {
+        let msg = format!("expected `=`, found {}", lex.peek());
+        lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+        lex.span()
+    };
+    let value = parse_value(lex, &eq);
+    Expr::Let(keyword, mut_, pattern, eq, Box::new(value))
+}
+
+// The keyword of a declaration and the name it declares.
+fn parse_declared<'a>(lex: &mut Lex<'a>) -> (Span<'a>, Span<'a>) {
+    let keyword = lex.advance();
+    let name = if matches!(lex.peek(), Token::Ident(_)) {
+        lex.advance()
+    } else {
+        let msg = format!("expected a name after `{keyword}`, found {}", lex.peek());
+        lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+        lex.span()
+    };
+    (keyword, name)
+}
+
+/// struct Point x y
+/// enum Colour
+///     Red Green
+///     Blue
+///
+/// Each item starts with a name, and is parsed by `item`.
+fn parse_names<'a, T>(lex: &mut Lex<'a>, mut item: impl FnMut(&mut Lex<'a>) -> T) -> Vec<T> {
+    let mut names = vec![];
+    while matches!(lex.peek(), Token::Ident(_)) {
+        names.push(item(lex));
+    }
+    if !matches!(lex.peek(), Token::Begin(_)) {
+        return names;
+    }
+    lex.advance();
+    loop {
+        match lex.peek() {
+            Token::Ident(_) => names.push(item(lex)),
+            Token::Newline(_) => {
+                lex.advance();
+            }
+            Token::End(_) => {
+                lex.advance();
+                return names;
+            }
+            Token::Eof(_) => return names,
+            _ => {
+                let msg = format!("expected a name, found {}", lex.peek());
+                lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+                skip_line(lex);
+            }
         }
     }
 }
 
+/// Empty, Circle(Float) or Rect{w, h}
+fn parse_case<'a>(lex: &mut Lex<'a>) -> Case<'a> {
+    let start = lex.checkpoint();
+    let name = lex.advance();
+    let close = match lex.peek() {
+        Token::Punct("(") => ")",
+        Token::Punct("{") => "}",
+        _ => {
+            let (brackets, fields) = (None, vec![]);
+            return Case {
+                name,
+                brackets,
+                fields,
+            };
+        }
+    };
+    let open = lex.advance();
+    let fields = parse_list(lex, close, |lex| {
+        if matches!(lex.peek(), Token::Ident(_)) {
+            lex.advance()
+        } else {
+            let msg = format!("expected a name, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            lex.span()
+        }
+    });
+    let close = parse_close(lex, &open, close);
+    lex.finish_node(SyntaxKind::Case, start);
+    let brackets = Some((open, close));
+    Case {
+        name,
+        brackets,
+        fields,
+    }
+}
+
+/// impl Point
+///     fn norm(self)
+///         (self.x ** 2 + self.y ** 2) ** 0.5
+/// impl Shape for Point
+///     fn area(self)
+///         0
+fn parse_impl<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let (keyword, name) = parse_declared(lex);
+    let (for_, name) = if matches!(lex.peek(), Token::Keyword("for")) {
+        // `for` is followed by a name as a declaration's keyword is.
+        let (for_, ty) = parse_declared(lex);
+        (Some((name, for_)), ty)
+    } else {
+        (None, name)
+    };
+    // A trait's default methods may be all a type needs.
+    let items = parse_methods(lex, &keyword, for_.is_some());
+    Expr::Impl(keyword, for_, name, items)
+}
+
+// The indented block of `fn`s after `impl` or `trait`, which may be
+// left out if `optional`.
+fn parse_methods<'a>(lex: &mut Lex<'a>, keyword: &Span<'a>, optional: bool) -> Vec<Expr<'a>> {
+    if !matches!(lex.peek(), Token::Begin(_)) {
+        if optional {
+            return vec![];
+        }
+        lex.report(
+            Diagnostic::error(
+                diag::EXPECTED_BLOCK,
+                format!("expected an indented block of `fn`s, found {}", lex.peek()),
+            )
+            .with_primary(lex.span(), "")
+            .with_secondary(keyword.clone(), format!("for this `{keyword}`")),
+        );
+        return vec![];
+    }
+    let begin = lex.advance();
+    let mut items = vec![];
+    while !matches!(lex.peek(), Token::End(_) | Token::Eof(_)) {
+        if !matches!(lex.peek(), Token::Keyword("fn")) {
+            let msg = format!("expected `fn`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            skip_line(lex);
+            continue;
+        }
+        items.push(parse_fn(lex, **keyword == "trait"));
+        if matches!(lex.peek(), Token::Newline(_) | Token::Punct(";")) {
+            lex.advance();
+        } else if !matches!(lex.peek(), Token::End(_) | Token::Eof(_)) && !lex.follows_block() {
+            let msg = format!("expected newline or `;`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_SEPARATOR, lex.span(), msg);
+            skip_line(lex);
+        }
+    }
+    if matches!(lex.peek(), Token::End(_)) {
+        lex.advance();
+    } else {
+        lex.report(
+            Diagnostic::error(diag::UNTERMINATED_BLOCK, "unterminated block")
+                .with_primary(lex.span(), "expected end of block")
+                .with_secondary(begin, "block starts here"),
+        );
+    }
+    items
+}
+
+/// fn scale(self, by=2)
+///     Point(self.x * by, self.y * by)
+///
+/// In a trait the body may be left out.
+fn parse_fn<'a>(lex: &mut Lex<'a>, in_trait: bool) -> Expr<'a> {
+    let start = lex.checkpoint();
+    let (keyword, name) = parse_declared(lex);
+    let open = if matches!(lex.peek(), Token::Punct("(")) {
+        lex.advance()
+    } else {
+        let msg = format!("expected `(`, found {}", lex.peek());
+        lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+        skip_line(lex);
+        return node(lex, start, Expr::Bad(keyword));
+    };
+    let formal_args = parse_formal_args(lex, ")");
+    let close = parse_close(lex, &open, ")");
+    if in_trait && !matches!(lex.peek(), Token::Begin(_)) {
+        let signature = Expr::Signature(keyword, name, open, formal_args, close);
+        return node(lex, start, signature);
+    }
+    let body = parse_body(lex, &keyword);
+    let closure = Closure {
+        open,
+        formal_args,
+        close,
+        body,
+    };
+    node(lex, start, Expr::Fn(keyword, name, Box::new(closure)))
+}
+
+/// match shape
+///     Circle(r) => 3.14 * r * r
+///     (0, y) if y > 0 => y
+///     _ =>
+///         0
+fn parse_match<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let keyword = lex.advance();
+    let value = parse_condition(lex, &keyword);
+    if !matches!(lex.peek(), Token::Begin(_)) {
+        lex.report(
+            Diagnostic::error(
+                diag::EXPECTED_BLOCK,
+                format!("expected an indented block of arms, found {}", lex.peek()),
+            )
+            .with_primary(lex.span(), "")
+            .with_secondary(keyword.clone(), format!("for this `{keyword}`")),
+        );
+        return Expr::Match(keyword, Box::new(value), vec![]);
+    }
+    let begin = lex.advance();
+    let mut arms = vec![];
+    while !matches!(lex.peek(), Token::End(_) | Token::Eof(_)) {
+        let start = lex.checkpoint();
+        let pattern = parse_pattern(lex);
+        let guard = if matches!(lex.peek(), Token::Keyword("if")) {
+            let keyword = lex.advance();
+            let cond = parse_condition(lex, &keyword);
+            Some((keyword, cond))
+        } else {
+            None
+        };
+        if !matches!(lex.peek(), Token::Punct("=>")) {
+            let msg = format!("expected `=>`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            skip_line(lex);
+            continue;
+        }
+        let arrow = lex.advance();
+        let body = if matches!(lex.peek(), Token::Begin(_)) {
+            parse_body(lex, &arrow)
+        } else {
+            parse_value(lex, &arrow)
+        };
+        arms.push(Arm {
+            pattern,
+            guard,
+            arrow,
+            body,
+        });
+        lex.finish_node(SyntaxKind::Arm, start);
+        if matches!(lex.peek(), Token::Newline(_) | Token::Punct(";")) {
+            lex.advance();
+        } else if !matches!(lex.peek(), Token::End(_) | Token::Eof(_)) && !lex.follows_block() {
+            let msg = format!("expected newline or `;`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_SEPARATOR, lex.span(), msg);
+            skip_line(lex);
+        }
+    }
+    if matches!(lex.peek(), Token::End(_)) {
+        lex.advance();
+    } else {
+        lex.report(
+            Diagnostic::error(diag::UNTERMINATED_BLOCK, "unterminated block")
+                .with_primary(lex.span(), "expected end of block")
+                .with_secondary(begin, "block starts here"),
+        );
+    }
+    Expr::Match(keyword, Box::new(value), arms)
+}
+
+// Skip the rest of a line that could not be parsed, along with any
+// block nested in it.
+fn skip_line(lex: &mut Lex) {
+    let mut depth = 0;
+    loop {
+        match lex.peek() {
+            Token::Eof(_) => return,
+            Token::End(_) if depth == 0 => return,
+            Token::Newline(_) if depth == 0 => {
+                lex.advance();
+                return;
+            }
+            Token::Begin(_) => depth += 1,
+            Token::End(_) => depth -= 1,
+            _ => (),
+        }
+        let start = lex.checkpoint();
+        lex.advance();
+        lex.finish_node(SyntaxKind::Error, start);
+    }
+}
+
+// The expression after `if`, `elif`, `while` or `for .. in`.
+fn parse_condition<'a>(lex: &mut Lex<'a>, keyword: &Span<'a>) -> Expr<'a> {
+    // A block here is the body, so the condition is missing.
+    let cond = if matches!(lex.peek(), Token::Begin(_)) {
+        None
+    } else {
+        Expr::parse(lex)
+    };
+    cond.unwrap_or_else(|| {
+        let msg = format!(
+            "expected expression after `{keyword}`, found {}",
+            lex.peek()
+        );
+        lex.error(diag::EXPECTED_EXPR, lex.span(), msg);
+        Expr::Bad(lex.span())
+    })
+}
+
+// The indented block after a condition or `else`.
+fn parse_body<'a>(lex: &mut Lex<'a>, keyword: &Span<'a>) -> Expr<'a> {
+    if matches!(lex.peek(), Token::Begin(_)) {
+        let start = lex.checkpoint();
+        let block = parse_block(lex);
+        node(lex, start, block)
+    } else {
+        lex.report(
+            Diagnostic::error(
+                diag::EXPECTED_BLOCK,
+                format!("expected an indented block, found {}", lex.peek()),
+            )
+            .with_primary(lex.span(), "")
+            .with_secondary(keyword.clone(), format!("for this `{keyword}`")),
+        );
+        Expr::Bad(lex.span())
+    }
+}
+
+// Skip a token that cannot start an expression.
+fn parse_bad<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let start = lex.checkpoint();
+    let bad = Expr::Bad(lex.advance());
+    node(lex, start, bad)
+}
+
+// Record the syntax node of an expression that began at `checkpoint`.
+fn node<'a>(lex: &mut Lex<'a>, checkpoint: usize, expr: Expr<'a>) -> Expr<'a> {
+    lex.finish_node(SyntaxKind::of(&expr), checkpoint);
+    expr
+}
+
+// Parse the placeholders of an interpolated string as expressions.
+fn parse_interpolated<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    let span = lex.advance();
+    // The lexer has already reported any errors in the string itself.
+    let mut reported = Diagnostics::new();
+    let segments = decode_interpolated(&span, &mut reported)
+        .into_iter()
+        .map(|fragment| match fragment {
+            Fragment::Text(text) => Segment::Text(text),
+            Fragment::Placeholder(src, spec) => {
+                let mut sub = Lex::new(src);
+                let expr = if src.trim().is_empty() {
+                    Expr::Bad(sub.span())
+                } else if let Some(expr) = Expr::parse(&mut sub) {
+                    if !sub.is_eof() {
+                        let msg = format!("expected `}}` after expression, found {}", sub.peek());
+                        sub.error(diag::EXPECTED_CLOSE, sub.span(), msg);
+                    }
+                    expr
+                } else {
+                    if !matches!(
+                        sub.peek(),
+                        Token::UnknownToken(_) | Token::UnterminatedString(_)
+                    ) {
+                        let msg = format!("expected expression, found {}", sub.peek());
+                        sub.error(diag::EXPECTED_EXPR, sub.span(), msg);
+                    }
+                    Expr::Bad(sub.span())
+                };
+                for d in sub.take_diagnostics() {
+                    lex.report(d);
+                }
+                let spec = spec.map(|spec| parse_format_spec(lex, spec));
+                Segment::Expr(expr, spec)
+            }
+        })
+        .collect();
+    Expr::Interpolated(span, segments)
+}
+
+fn parse_format_spec<'a>(lex: &mut Lex<'a>, text: &'a str) -> FormatSpec<'a> {
+    let align = |c| match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    };
+    let mut spec = FormatSpec {
+        span: Span::new(text),
+        fill: ' ',
+        align: None,
+        sign: false,
+        alternate: false,
+        zero: false,
+        width: None,
+        precision: None,
+        kind: None,
+    };
+    let mut chars = text.chars().peekable();
+    let mut first_two = text.chars();
+    match (first_two.next(), first_two.next()) {
+        (Some(fill), Some(a)) if align(a).is_some() => {
+            spec.fill = fill;
+            spec.align = align(a);
+            chars.nth(1);
+        }
+        (Some(a), _) if align(a).is_some() => {
+            spec.align = align(a);
+            chars.next();
+        }
+        _ => (),
+    }
+    spec.sign = chars.next_if_eq(&'+').is_some();
+    spec.alternate = chars.next_if_eq(&'#').is_some();
+    spec.zero = chars.next_if_eq(&'0').is_some();
+    let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        let mut n: Option<usize> = None;
+        while let Some(d) = chars.next_if(char::is_ascii_digit) {
+            n = Some(
+                n.unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(d as usize - '0' as usize),
+            );
+        }
+        n
+    };
+    spec.width = number(&mut chars);
+    if chars.next_if_eq(&'.').is_some() {
+        spec.precision = number(&mut chars);
+        if spec.precision.is_none() {
+            lex.error(
+                diag::INVALID_FORMAT_SPEC,
+                spec.span.clone(),
+                "expected precision after `.`".into(),
+            );
+        }
+    }
+    spec.kind = chars.next_if(|c| matches!(c, 'x' | 'X' | 'o' | 'b' | 'e' | 'E' | '?'));
+    if let Some(c) = chars.next() {
+        let msg = format!("unexpected `{c}` in format spec `{text}`");
+        lex.error(diag::INVALID_FORMAT_SPEC, spec.span.clone(), msg);
+    }
+    spec
+}
+
 // Parse a list of comma
 fn parse_args<'a>(lex: &mut Lex<'a>) -> Vec<(Expr<'a>, Option<Span<'a>>)> {
     let mut args = vec![];
     while lex.peek() != &Token::Punct(")") {
-        let expr = Expr::parse(lex).unwrap_or_else(|| Expr::Bad(lex.advance()));
+        let expr = Expr::parse(lex).unwrap_or_else(|| parse_bad(lex));
         if lex.peek() == &Token::Punct(",") {
             args.push((expr, Some(lex.advance())));
         } else {
@@ -283,17 +2035,131 @@ fn parse_args<'a>(lex: &mut Lex<'a>) -> Vec<(Expr<'a>, Option<Span<'a>>)> {
     args
 }
 
-// Parse formal args of a function.
-fn parse_formal_args<'a>(lex: &mut Lex<'a>) -> Vec<(FormalArg<'a>, Option<Span<'a>>)> {
+// The arguments of a call, `x, y, scale=2.0`.
+fn parse_call_args<'a>(lex: &mut Lex<'a>) -> Vec<(Arg<'a>, Option<Span<'a>>)> {
     let mut args = vec![];
+    let mut keyword: Option<Span<'a>> = None;
     while lex.peek() != &Token::Punct(")") {
-        if !matches!(lex.peek(), Token::Ident(_)) {
-            let span = lex.advance();
-            args.push((FormalArg::Bad(span), None));
-            continue;
+        let start = lex.span();
+        let arg = match Expr::parse(lex).unwrap_or_else(|| parse_bad(lex)) {
+            // `name=value` parses as an assignment to a name.
+            Expr::Assign(target, op, value) if *op == "=" => match *target {
+                Pattern::Name(name) => {
+                    keyword.get_or_insert(name.clone());
+                    Arg::Keyword(name, op, *value)
+                }
+                target => Arg::Positional(Expr::Assign(Box::new(target), op, value)),
+            },
+            value => Arg::Positional(value),
+        };
+        if let (Arg::Positional(_), Some(first)) = (&arg, &keyword) {
+            lex.report(
+                Diagnostic::error(
+                    diag::POSITIONAL_AFTER_KEYWORD,
+                    "positional argument after a keyword argument",
+                )
+                .with_primary(start, "")
+                .with_secondary(first.clone(), "first keyword argument"),
+            );
+        }
+        if lex.peek() == &Token::Punct(",") {
+            args.push((arg, Some(lex.advance())));
+        } else {
+            args.push((arg, None));
+            break;
+        }
+    }
+    args
+}
+
+// An item of an array, which may be `..xs`.
+fn parse_element<'a>(lex: &mut Lex<'a>) -> Expr<'a> {
+    if lex.peek() != &Token::Punct("..") {
+        return parse_assign(lex);
+    }
+    let start = lex.checkpoint();
+    let dots = lex.advance();
+    // A bare `..` is left for an array pattern to take.
+    let value = Expr::parse(lex).map(Box::new);
+    node(lex, start, Expr::Spread(dots, value))
+}
+
+// `key: value` or `..other` in a map.
+fn parse_entry<'a>(lex: &mut Lex<'a>) -> Entry<'a> {
+    let start = lex.checkpoint();
+    let entry = if lex.peek() == &Token::Punct("..") {
+        let dots = lex.advance();
+        let value = parse_value(lex, &dots);
+        Entry::Spread(dots, value)
+    } else {
+        let key = parse_binop(lex, 0);
+        let colon = if lex.peek() == &Token::Punct(":") {
+            lex.advance()
+        } else {
+            let msg = format!("expected `:` after a map key, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            lex.span()
+        };
+        let value = parse_value(lex, &colon);
+        Entry::Pair(key, colon, value)
+    };
+    lex.finish_node(SyntaxKind::Entry, start);
+    entry
+}
+
+// Items separated by commas, up to but not including `close`.
+fn parse_list<'a, T>(
+    lex: &mut Lex<'a>,
+    close: &str,
+    mut item: impl FnMut(&mut Lex<'a>) -> T,
+) -> Vec<(T, Option<Span<'a>>)> {
+    let mut items = vec![];
+    while !matches!(lex.peek(), Token::Punct(p) if *p == close) {
+        let value = item(lex);
+        if lex.peek() == &Token::Punct(",") {
+            items.push((value, Some(lex.advance())));
+        } else {
+            items.push((value, None));
+            break;
         }
-        let name = lex.advance();
-        let arg = FormalArg::Name(name);
+    }
+    items
+}
+
+// Parse formal args of a function, up to `close`.
+//
+// x, (k, v), y: Int, z=5, *rest, key=1
+fn parse_formal_args<'a>(lex: &mut Lex<'a>, close: &str) -> Vec<(FormalArg<'a>, Option<Span<'a>>)> {
+    let mut args = vec![];
+    let mut star: Option<Span<'a>> = None;
+    while !matches!(lex.peek(), Token::Punct(p) if *p == close) && !lex.is_eof() {
+        let start = lex.checkpoint();
+        let arg = match lex.peek() {
+            Token::Punct("*") => {
+                let span = lex.advance();
+                let name = matches!(lex.peek(), Token::Ident(_)).then(|| lex.advance());
+                if let Some(first) = &star {
+                    lex.report(
+                        Diagnostic::error(
+                            diag::MULTIPLE_REST,
+                            "a closure can only have one `*` parameter",
+                        )
+                        .with_primary(span.clone(), "")
+                        .with_secondary(first.clone(), "first one here"),
+                    );
+                }
+                star.get_or_insert(span.clone());
+                FormalArg::Rest(span, name)
+            }
+            Token::Ident(_) | Token::Punct("(" | "[") => parse_formal_arg(lex, close),
+            _ => {
+                let span = lex.advance();
+                lex.finish_node(SyntaxKind::Error, start);
+                args.push((FormalArg::Bad(span), None));
+                continue;
+            }
+        };
+        lex.finish_node(SyntaxKind::FormalArg, start);
         if lex.peek() == &Token::Punct(",") {
             args.push((arg, Some(lex.advance())));
         } else {
@@ -304,13 +2170,51 @@ fn parse_formal_args<'a>(lex: &mut Lex<'a>) -> Vec<(FormalArg<'a>, Option<Span<'
     args
 }
 
+// A name or pattern with an optional type and default.
+fn parse_formal_arg<'a>(lex: &mut Lex<'a>, close: &str) -> FormalArg<'a> {
+    let mut arg = match parse_pattern(lex) {
+        Pattern::Name(name) => FormalArg::Name(name),
+        pattern => FormalArg::Pattern(pattern),
+    };
+    if let (FormalArg::Name(name), Token::Punct(":")) = (&arg, lex.peek()) {
+        let name = name.clone();
+        let colon = lex.advance();
+        if !matches!(lex.peek(), Token::Ident(_)) {
+            let msg = format!("expected a type, found {}", lex.peek());
+            lex.error(diag::EXPECTED_TOKEN, lex.span(), msg);
+            return FormalArg::Bad(name);
+        }
+        arg = FormalArg::Typed(name, colon, lex.advance());
+    }
+    if lex.peek() == &Token::Punct("=") {
+        let eq = lex.advance();
+        // `|` closes the parameters, so a default binds tighter than it.
+        let value = if matches!(lex.peek(), Token::Punct(p) if *p == close || *p == ",") {
+            let msg = format!("expected expression after `=`, found {}", lex.peek());
+            lex.error(diag::EXPECTED_EXPR, lex.span(), msg);
+            Expr::Bad(lex.span())
+        } else {
+            parse_binop(lex, DEFAULT)
+        };
+        arg = FormalArg::NameWithDefault(Box::new(arg), eq, value);
+    }
+    arg
+}
+
 // Parse a closing token f a pair such as [], () or {}
-fn parse_close<'a>(lex: &mut Lex<'a>, closer: &'static str) -> Span<'a> {
+fn parse_close<'a>(lex: &mut Lex<'a>, opener: &Span<'a>, closer: &'static str) -> Span<'a> {
     if matches!(lex.peek(), Token::Punct(span) if *span == closer) {
         lex.advance()
     } else {
         // TODO: some error recovery.
-        lex.error(lex.span().clone(), format!("Expected {closer}"));
+        lex.report(
+            Diagnostic::error(
+                diag::EXPECTED_CLOSE,
+                format!("expected `{closer}`, found {}", lex.peek()),
+            )
+            .with_primary(lex.span(), "")
+            .with_secondary(opener.clone(), format!("unclosed `{opener}`")),
+        );
         lex.span()
     }
 }
@@ -321,11 +2225,44 @@ pub fn parse_programme<'a>(lex: &mut Lex<'a>) -> Programme<'a> {
         while lex.is_newline() {
             lex.advance();
         }
-        let Some(expr) = Expr::parse(lex) else {
-            lex.error(lex.span(), format!("Unexpected token"));
+        if lex.is_eof() {
+            break;
+        }
+        let Some(expr) = parse_top_level(lex) else {
             return Programme::Bad();
         };
         exprs.push(expr);
     }
     Programme::Good(exprs)
 }
+
+// One expression of a programme, or None having reported an error if
+// there is no expression here.
+pub(crate) fn parse_top_level<'a>(lex: &mut Lex<'a>) -> Option<Expr<'a>> {
+    let expr = Expr::parse(lex);
+    if expr.is_none()
+        && !matches!(
+            lex.peek(),
+            Token::UnknownToken(_) | Token::UnterminatedString(_)
+        )
+    {
+        let msg = format!("expected expression, found {}", lex.peek());
+        lex.error(diag::EXPECTED_EXPR, lex.span(), msg);
+    }
+    // Another expression on the same line, like `1` in `Point 1 2`, is a
+    // mistake rather than the start of the next one.
+    if expr.is_some()
+        && !lex.follows_block()
+        && !matches!(
+            lex.peek(),
+            Token::Newline(_)
+                | Token::Eof(_)
+                | Token::UnknownToken(_)
+                | Token::UnterminatedString(_)
+        )
+    {
+        let msg = format!("expected newline, found {}", lex.peek());
+        lex.error(diag::EXPECTED_SEPARATOR, lex.span(), msg);
+    }
+    expr
+}
@@ -0,0 +1,1699 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{
+        self, Arg, Arm, Closure, Entry, Expr, FieldPattern, FormalArg, Pattern, Programme, Segment,
+    },
+    diag::{self, Diagnostic, Diagnostics},
+    lex::{decode_str, Span},
+    literal::literal_value,
+    runtime::{
+        match_args, prelude, Capture, Case, Field, Fields, FormatSpec, Op, Param, ParamKind, Proto,
+        Ref, Shape, Str, Switch, Trait, Type, TypeKind, Variant,
+    },
+};
+
+/// How a name came to be bound.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum BindingKind {
+    /// `let x = ..`, which may not be assigned again.
+    Let,
+    /// `let mut x = ..`.
+    LetMut,
+    /// `x = ..` without `let`, a parameter, a loop variable or a name in
+    /// a `match` arm. These can always be assigned.
+    Implicit,
+}
+
+/// Where a variable lives at run time.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Slot {
+    /// Names bound at the top level of the programme are looked up by
+    /// name, so functions can call each other before they are defined.
+    Global,
+    Local(u32),
+}
+
+#[derive(Debug)]
+struct Binding<'a> {
+    name: Span<'a>,
+    kind: BindingKind,
+    mut_: Option<Span<'a>>,
+    assigned: bool,
+    slot: Slot,
+    /// The parameters of the closure a `let` binds the name to.
+    params: Option<Ref<[Param]>>,
+}
+
+// `let` bindings live until the end of their block, plain assignments
+// until the end of the function, or the programme, they are made in.
+#[derive(Debug, Default)]
+struct Scope<'a> {
+    bindings: Vec<Binding<'a>>,
+    function: bool,
+    /// The index in `Compiler::functions` of the function it is in.
+    owner: usize,
+}
+
+// Where `break` and `continue` go.
+#[derive(Debug)]
+struct Loop {
+    /// The stack depth in the body, which `continue` goes back to.
+    depth: u32,
+    /// The stack depth after the loop, which `break` goes back to.
+    exit_depth: u32,
+    start: u32,
+    breaks: Vec<usize>,
+}
+
+// The bytecode of a closure, or the programme, being compiled.
+#[derive(Debug)]
+struct Function {
+    name: String,
+    params: Vec<Param>,
+    locals: u32,
+    /// Captured variables by the function that owns them and their slot.
+    captures: Vec<((usize, u32), Capture)>,
+    code: Vec<Op>,
+    /// How many values the code emitted so far leaves on the stack.
+    depth: u32,
+    loops: Vec<Loop>,
+}
+
+// How to get at a variable from the current function.
+enum Access {
+    Local(u32),
+    Cap(u32),
+    Global(String),
+}
+
+// The last step of a place being assigned to, once everything before it
+// is on the stack.
+enum Last {
+    Field(String),
+    Index,
+}
+
+/// Checks a programme and compiles it to bytecode.
+///
+/// ```text
+/// let x = 1
+/// x = 2      # error: cannot assign twice to immutable variable `x`
+/// let mut y = 1
+/// print(y)   # warning: variable `y` does not need to be mutable
+/// ```
+#[derive(Debug)]
+pub struct Compiler<'a> {
+    scopes: Vec<Scope<'a>>,
+    functions: Vec<Function>,
+    /// Jumps to take when a pattern in a `match` arm does not match. None
+    /// outside a `match`, where patterns must match anything.
+    fails: Option<Vec<usize>>,
+    /// The enum of each variant a pattern can name, as `None` or
+    /// `Option.None`, so that it is tested for rather than bound. A later
+    /// enum with a variant of the same name takes it over.
+    variants: HashMap<String, Ref<Type>>,
+    diagnostics: Diagnostics<'a>,
+}
+
+impl<'a> Default for Compiler<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new() -> Self {
+        let mut compiler = Self {
+            scopes: vec![],
+            functions: vec![],
+            fails: None,
+            variants: HashMap::new(),
+            diagnostics: Diagnostics::new(),
+        };
+        compiler.add_enums(prelude().map(Ref::new));
+        compiler
+    }
+
+    /// Let patterns name the variants of enums declared elsewhere, such
+    /// as by an earlier programme run on the same `Vm`.
+    pub fn add_enums(&mut self, types: impl IntoIterator<Item = Ref<Type>>) {
+        for ty in types {
+            for case in ty.cases() {
+                self.variants.insert(case.name.clone(), ty.clone());
+                (self.variants).insert(format!("{}.{}", ty.name, case.name), ty.clone());
+            }
+        }
+    }
+
+    /// The programme as a function of no arguments, or None if it could
+    /// not be parsed. Check `diagnostics` for errors before running it.
+    pub fn compile(&mut self, programme: &Programme<'a>) -> Option<Ref<Proto>> {
+        let Programme::Good(exprs) = programme else {
+            return None;
+        };
+        self.functions.push(Function::new("main", vec![]));
+        self.push(true);
+        self.sequence(exprs);
+        self.pop();
+        self.emit(Op::Return);
+        Some(self.functions.pop().unwrap().finish())
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics<'a> {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Diagnostics<'a> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    // Every expression leaves exactly one value on the stack.
+    fn expr(&mut self, expr: &Expr<'a>) {
+        match expr {
+            Expr::Ident(name) => self.load(name),
+            Expr::Int(_) | Expr::Float(_) => match literal_value(expr, &mut self.diagnostics) {
+                Some(value) => self.constant(value),
+                None => self.emit(Op::Unit),
+            },
+            Expr::Str(span) => {
+                let value = decode_str(span, &mut Diagnostics::new()).into();
+                self.constant(value);
+            }
+            Expr::Interpolated(_, segments) => {
+                for segment in segments {
+                    match segment {
+                        Segment::Text(text) => self.emit(Op::Str(text.as_str().into())),
+                        Segment::Expr(expr, spec) => {
+                            self.expr(expr);
+                            let spec = spec.as_ref().map(|spec| {
+                                Ref::new(FormatSpec {
+                                    fill: spec.fill,
+                                    align: spec.align,
+                                    sign: spec.sign,
+                                    alternate: spec.alternate,
+                                    zero: spec.zero,
+                                    width: spec.width,
+                                    precision: spec.precision,
+                                    kind: spec.kind,
+                                })
+                            });
+                            self.emit(Op::Format(spec));
+                        }
+                    }
+                }
+                self.emit(Op::Concat(segments.len() as u32));
+            }
+            Expr::Closure(closure) => self.closure("closure", closure),
+            Expr::Block(block) => {
+                self.push(false);
+                self.sequence(&block.items);
+                self.pop();
+            }
+            Expr::Array(_, items, _) => {
+                // Runs of plain items become arrays, which spreads extend.
+                let mut run = 0;
+                let mut started = false;
+                for (item, _) in items {
+                    match item {
+                        Expr::Spread(_, Some(value)) => {
+                            self.flush(Op::Array(run), started);
+                            started = true;
+                            run = 0;
+                            self.expr(value);
+                            self.emit(Op::Extend);
+                        }
+                        item => {
+                            self.expr(item);
+                            run += 1;
+                        }
+                    }
+                }
+                self.flush(Op::Array(run), started);
+            }
+            Expr::Map(_, entries, _) => {
+                let mut run = 0;
+                let mut started = false;
+                for (entry, _) in entries {
+                    match entry {
+                        Entry::Pair(key, _, value) => {
+                            self.expr(key);
+                            self.expr(value);
+                            run += 1;
+                        }
+                        Entry::Spread(_, value) => {
+                            self.flush(Op::Map(run), started);
+                            started = true;
+                            run = 0;
+                            self.expr(value);
+                            self.emit(Op::Extend);
+                        }
+                    }
+                }
+                self.flush(Op::Map(run), started);
+            }
+            Expr::Spread(dots, _) => {
+                self.diagnostics.push(
+                    Diagnostic::error(diag::EMPTY_SPREAD, "expected a value to spread after `..`")
+                        .with_primary(dots.clone(), "")
+                        .with_help(
+                            "a bare `..` can only take the rest of an array being assigned to",
+                        ),
+                );
+                self.emit(Op::Unit);
+            }
+            Expr::Tuple(_, items, _) => {
+                items.iter().for_each(|(e, _)| self.expr(e));
+                self.emit(Op::Tuple(items.len() as u32));
+            }
+            Expr::Call(func, _, args, _) if self.is_wrap(func, args.len()) => {
+                let (Arg::Positional(value), _) = &args[0] else {
+                    unreachable!()
+                };
+                self.expr(value);
+                self.wrap(func);
+            }
+            Expr::Call(func, _, args, _) => {
+                if let Expr::Ident(name) = &**func {
+                    self.check_args(name, args);
+                }
+                self.expr(func);
+                let names = self.args(args);
+                self.emit(match names.is_empty() {
+                    true => Op::Call(args.len() as u32),
+                    false => Op::CallKw(args.len() as u32, names.into()),
+                });
+            }
+            Expr::Binary(lhs, op, rhs) if matches!(&***op, "and" | "or") => {
+                // `a and b` is `a` if it is false, otherwise `b`.
+                self.expr(lhs);
+                self.emit(Op::Dup);
+                let jump = match &***op {
+                    "and" => self.emit_jump(Op::JumpIfFalse(0)),
+                    _ => self.emit_jump(Op::JumpIfTrue(0)),
+                };
+                self.emit(Op::Pop);
+                self.expr(rhs);
+                self.patch(jump);
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.emit(binary_op(op));
+            }
+            Expr::Unary(op, expr) => {
+                self.expr(expr);
+                match &***op {
+                    "-" => self.emit(Op::Neg),
+                    "!" => self.emit(Op::Not),
+                    _ => (),
+                }
+            }
+            Expr::Paren(_, expr, _) => self.expr(expr),
+            Expr::Index(lhs, _, index, _) => {
+                self.expr(lhs);
+                self.expr(index);
+                self.emit(Op::Index);
+            }
+            Expr::Dot(lhs, dot, rhs) => {
+                self.expr(lhs);
+                self.dot(dot, rhs);
+            }
+            Expr::Assign(target, op, value) => {
+                if **op == "=" {
+                    self.value(value, target);
+                    self.pattern(target, None);
+                } else {
+                    self.compound(target, op, value);
+                }
+                self.emit(Op::Unit);
+            }
+            Expr::Let(_, mut_, pattern, _, value) => {
+                // The value cannot see the name it is bound to.
+                let params = self.value(value, pattern);
+                let kind = match mut_ {
+                    Some(_) => BindingKind::LetMut,
+                    None => BindingKind::Let,
+                };
+                self.pattern(pattern, Some((kind, mut_.as_ref())));
+                // Calls can be checked while the name stays bound to it.
+                if let (Some(params), BindingKind::Let) = (params, kind) {
+                    let binding = self.scopes.last_mut().unwrap().bindings.last_mut();
+                    binding.unwrap().params = Some(params);
+                }
+                self.emit(Op::Unit);
+            }
+            Expr::If(branches, otherwise) => {
+                let depth = self.function().depth;
+                let mut ends = vec![];
+                for (_, cond, body) in branches {
+                    self.expr(cond);
+                    let next = self.emit_jump(Op::JumpIfFalse(0));
+                    self.expr(body);
+                    ends.push(self.emit_jump(Op::Jump(0)));
+                    self.patch(next);
+                    self.function().depth = depth;
+                }
+                match otherwise {
+                    Some((_, body)) => self.expr(body),
+                    None => self.emit(Op::Unit),
+                }
+                ends.into_iter().for_each(|end| self.patch(end));
+            }
+            Expr::While(_, cond, body) => {
+                let depth = self.function().depth;
+                let start = self.here();
+                self.expr(cond);
+                let exit = self.emit_jump(Op::JumpIfFalse(0));
+                self.push_loop(depth, depth, start);
+                self.expr(body);
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.pop_loop();
+                self.emit(Op::Unit);
+            }
+            Expr::For(_, pattern, _, iter, body) => {
+                let depth = self.function().depth;
+                self.expr(iter);
+                self.emit(Op::Iter);
+                let start = self.here();
+                let exit = self.emit_jump(Op::Next(0));
+                self.push_loop(depth + 1, depth, start);
+                self.push(false);
+                self.pattern(pattern, Some((BindingKind::Implicit, None)));
+                self.expr(body);
+                self.emit(Op::Pop);
+                self.pop();
+                self.emit(Op::Jump(start));
+                // `Next` drops the iterator when it is done.
+                self.patch(exit);
+                self.function().depth = depth;
+                self.pop_loop();
+                self.emit(Op::Unit);
+            }
+            Expr::Break(keyword) | Expr::Continue(keyword) => {
+                let depth = self.function().depth;
+                let Some(inner) = self.function().loops.last() else {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            diag::OUTSIDE_LOOP,
+                            format!("`{keyword}` outside of a loop"),
+                        )
+                        .with_primary(keyword.clone(), "cannot leave anything from here"),
+                    );
+                    self.emit(Op::Unit);
+                    return;
+                };
+                let (to, start) = match &***keyword {
+                    "break" => (inner.exit_depth, None),
+                    _ => (inner.depth, Some(inner.start)),
+                };
+                self.emit(Op::Truncate(to));
+                match start {
+                    Some(start) => self.emit(Op::Jump(start)),
+                    None => {
+                        let jump = self.emit_jump(Op::Jump(0));
+                        self.function().loops.last_mut().unwrap().breaks.push(jump);
+                    }
+                }
+                // Nothing after this runs, but it is an expression.
+                self.function().depth = depth + 1;
+            }
+            Expr::Return(_, value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => self.emit(Op::Unit),
+                }
+                self.emit(Op::Return);
+                self.function().depth += 1;
+            }
+            Expr::Match(keyword, value, arms) => self.match_(keyword, value, arms),
+            Expr::Struct(_, name, fields) => {
+                self.duplicate_names("field", fields);
+                let ty = Type {
+                    name: name.to_string(),
+                    field: fields
+                        .iter()
+                        .map(|field| Field {
+                            name: field.to_string(),
+                        })
+                        .collect(),
+                    kind: TypeKind::Struct,
+                    methods: Default::default(),
+                };
+                let params = ty.params().into();
+                self.emit(Op::Type(Ref::new(ty)));
+                self.declare(name, Some(params));
+            }
+            Expr::Enum(_, name, cases) => {
+                let names = cases.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+                self.duplicate_names("variant", &names);
+                for case in cases {
+                    if matches!(&case.brackets, Some((open, _)) if **open == "{") {
+                        let names = (case.fields.iter())
+                            .map(|(f, _)| f.clone())
+                            .collect::<Vec<_>>();
+                        self.duplicate_names("field", &names);
+                    }
+                }
+                let ty = Ref::new(enum_type(name, cases));
+                self.add_enums([ty.clone()]);
+                self.emit(Op::Type(ty.clone()));
+                // Each variant is bound by its own name as well as with
+                // the enum's, `Rect` as well as `Shape.Rect`.
+                for (case, variant) in cases.iter().zip(ty.cases()) {
+                    self.emit(Op::Dup);
+                    self.emit(Op::Dot((*case.name).into()));
+                    self.define(case.name.clone(), BindingKind::Let, None);
+                    if variant.fields != Fields::Unit {
+                        let binding = self.scopes.last_mut().unwrap().bindings.last_mut();
+                        binding.unwrap().params = Some(variant.params().into());
+                    }
+                }
+                self.declare(name, None);
+            }
+            Expr::Impl(_, for_, name, items) => {
+                if let Some((trait_, _)) = for_ {
+                    self.load(trait_);
+                }
+                self.load(name);
+                self.methods(name, items);
+                if for_.is_some() {
+                    self.emit(Op::Implement);
+                } else {
+                    self.emit(Op::Pop);
+                }
+                self.emit(Op::Unit);
+            }
+            Expr::Trait(_, name, items) => {
+                let required = items
+                    .iter()
+                    .filter_map(|item| match item {
+                        Expr::Signature(_, method, ..) => Some(method.to_string()),
+                        _ => None,
+                    })
+                    .collect();
+                let trait_ = Trait {
+                    name: name.to_string(),
+                    required,
+                    methods: Default::default(),
+                };
+                self.emit(Op::Trait(Ref::new(trait_)));
+                self.methods(name, items);
+                self.declare(name, None);
+            }
+            // Methods are compiled by their `impl` or `trait`.
+            Expr::Fn(_, name, closure) => self.closure(name, closure),
+            Expr::Signature(..) => self.emit(Op::Unit),
+            Expr::Bad(_) => self.emit(Op::Unit),
+        }
+    }
+
+    // A closure named `name` for error messages.
+    fn closure(&mut self, name: &str, closure: &Closure<'a>) {
+        let args = closure
+            .formal_args
+            .iter()
+            .map(|(arg, _)| arg)
+            .collect::<Vec<_>>();
+        self.functions.push(Function::new(name, params(&args)));
+        self.push(true);
+        // Parameters are the first locals. Defaults are worked out by the
+        // call, in order, so they can use earlier parameters.
+        for (i, arg) in args.into_iter().filter(|a| has_local(a)).enumerate() {
+            let i = i as u32;
+            let arg = match arg {
+                FormalArg::NameWithDefault(arg, _, value) => {
+                    let given = self.emit_jump(Op::JumpIfGiven(i, 0));
+                    self.expr(value);
+                    self.emit(Op::SetLoc(i));
+                    self.patch(given);
+                    arg
+                }
+                arg => arg,
+            };
+            if let FormalArg::Pattern(pattern) = arg {
+                self.emit(Op::Loc(i));
+                self.pattern(pattern, Some((BindingKind::Implicit, None)));
+            } else if let Some(name) = arg.name() {
+                self.bind(name.clone(), BindingKind::Implicit, None, Some(i));
+            }
+        }
+        self.duplicate_params();
+        self.expr(&closure.body);
+        self.emit(Op::Return);
+        self.pop();
+        let function = self.functions.pop().unwrap();
+        let captures = function.captures.iter().map(|(_, c)| *c).collect();
+        self.emit(Op::Fn(function.finish(), captures));
+    }
+
+    // Whether `func(value)` makes a `Result` of the prelude: `Ok` and
+    // `Err` not bound to anything else.
+    fn is_wrap(&mut self, func: &Expr<'a>, args: usize) -> bool {
+        matches!(func, Expr::Ident(name) if matches!(&***name, "Ok" | "Err"))
+            && args == 1
+            && self.lookup(&func.to_string()).is_none()
+    }
+
+    // Wrap the value on top of the stack in `Ok` or `Err`.
+    fn wrap(&mut self, func: &Expr<'a>) {
+        match func.to_string().as_str() {
+            "Ok" => self.emit(Op::Ok),
+            _ => self.emit(Op::Err),
+        }
+    }
+
+    // Add the `fn`s of an `impl` or `trait` to the type or trait on top
+    // of the stack.
+    fn methods(&mut self, name: &Span<'a>, items: &[Expr<'a>]) {
+        let methods = items
+            .iter()
+            .filter_map(|item| match item {
+                Expr::Fn(_, method, _) | Expr::Signature(_, method, ..) => Some(method.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.duplicate_names("method", &methods);
+        for item in items {
+            if let Expr::Fn(_, method, closure) = item {
+                self.closure(&format!("{name}.{method}"), closure);
+                self.emit(Op::Impl((**method).into()));
+            }
+        }
+    }
+
+    // Bind the name of a `struct`, `enum` or `trait` to the type or
+    // trait on top of the stack, like `let`.
+    fn declare(&mut self, name: &Span<'a>, params: Option<Ref<[Param]>>) {
+        self.define(name.clone(), BindingKind::Let, None);
+        let binding = self.scopes.last_mut().unwrap().bindings.last_mut();
+        binding.unwrap().params = params;
+        self.emit(Op::Unit);
+    }
+
+    // Report fields, variants or methods declared twice.
+    fn duplicate_names(&mut self, what: &str, names: &[Span<'a>]) {
+        for (i, name) in names.iter().enumerate() {
+            let Some(first) = names[..i].iter().find(|n| **n == *name) else {
+                continue;
+            };
+            self.diagnostics.push(
+                Diagnostic::error(
+                    diag::DUPLICATE_NAME,
+                    format!("{what} `{name}` is named more than once"),
+                )
+                .with_primary(name.clone(), "")
+                .with_secondary(first.clone(), "first named here"),
+            );
+        }
+    }
+
+    // Build the `Array` or `Map` of the items since the last spread,
+    // adding them to the one before if there is one.
+    fn flush(&mut self, op: Op, started: bool) {
+        match op {
+            Op::Array(0) | Op::Map(0) if started => (),
+            op => {
+                self.emit(op);
+                if started {
+                    self.emit(Op::Extend);
+                }
+            }
+        }
+    }
+
+    // The items of a block or programme, leaving the value of the last.
+    fn sequence(&mut self, items: &[Expr<'a>]) {
+        // Patterns can name the variants of an enum declared further on,
+        // as in a function that is only called after it.
+        let enums = items.iter().filter_map(|item| match item {
+            Expr::Enum(_, name, cases) => Some(Ref::new(enum_type(name, cases))),
+            _ => None,
+        });
+        self.add_enums(enums.collect::<Vec<_>>());
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.emit(Op::Pop);
+            }
+            self.expr(item);
+        }
+        if items.is_empty() {
+            self.emit(Op::Unit);
+        }
+    }
+
+    // The value bound to a pattern, naming closures after the variable
+    // they are bound to. Gives the parameters of a closure so named.
+    fn value(&mut self, value: &Expr<'a>, pattern: &Pattern<'a>) -> Option<Ref<[Param]>> {
+        self.expr(value);
+        if let (Expr::Closure(_), Pattern::Name(name)) = (value, pattern) {
+            if let Some(Op::Fn(proto, _)) = self.function().code.last_mut() {
+                Ref::make_mut(proto).name = name.to_string();
+                return Some(proto.params.clone());
+            }
+        }
+        None
+    }
+
+    // The values of the arguments of a call, giving the names of the
+    // keyword ones.
+    fn args(&mut self, args: &[(Arg<'a>, Option<Span<'a>>)]) -> Vec<Str> {
+        let mut names: Vec<&Span<'a>> = vec![];
+        for (arg, _) in args {
+            self.expr(arg.value());
+            let Arg::Keyword(name, _, _) = arg else {
+                continue;
+            };
+            if let Some(first) = names.iter().find(|n| **n == name) {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        diag::DUPLICATE_ARG,
+                        format!("argument `{name}` is passed more than once"),
+                    )
+                    .with_primary(name.clone(), "")
+                    .with_secondary((*first).clone(), "first passed here"),
+                );
+            }
+            names.push(name);
+        }
+        names.into_iter().map(|name| (**name).into()).collect()
+    }
+
+    // Check a call to a closure or type whose parameters are known.
+    fn check_args(&mut self, func: &Span<'a>, args: &[(Arg<'a>, Option<Span<'a>>)]) {
+        let keywords = args
+            .iter()
+            .filter_map(|(arg, _)| match arg {
+                Arg::Keyword(name, _, _) => Some(**name),
+                Arg::Positional(_) => None,
+            })
+            .collect::<Vec<_>>();
+        // Duplicates are reported by `args`.
+        if keywords
+            .iter()
+            .enumerate()
+            .any(|(i, k)| keywords[..i].contains(k))
+        {
+            return;
+        }
+        self.check_call(func, args.len() - keywords.len(), &keywords);
+    }
+
+    // Check the numbers and names of arguments against the parameters
+    // of `func`, if `let` or a declaration has made them known.
+    fn check_call(&mut self, func: &Span<'a>, positional: usize, keywords: &[&str]) {
+        let Some(binding) = self.lookup(func) else {
+            return;
+        };
+        let Some(params) = binding.params.clone() else {
+            return;
+        };
+        let defined = binding.name.clone();
+        if let Err(message) = match_args(func, &params, positional, keywords) {
+            self.diagnostics.push(
+                Diagnostic::error(diag::ARGUMENT_MISMATCH, message)
+                    .with_primary(func.clone(), "")
+                    .with_secondary(defined, "defined here"),
+            );
+        }
+    }
+
+    // The steps after `.`, with the value before it on the stack.
+    fn dot(&mut self, dot: &Span<'a>, rhs: &Expr<'a>) {
+        match rhs {
+            Expr::Ident(name) => self.emit(Op::Dot((**name).into())),
+            Expr::Call(func, _, args, _) => {
+                let n = args.len() as u32;
+                if let Expr::Ident(name) = &**func {
+                    let names = self.args(args);
+                    self.emit(match names.is_empty() {
+                        true => Op::Method((**name).into(), n),
+                        false => Op::MethodKw((**name).into(), n, names.into()),
+                    });
+                } else {
+                    self.dot(dot, func);
+                    let names = self.args(args);
+                    self.emit(match names.is_empty() {
+                        true => Op::Call(n),
+                        false => Op::CallKw(n, names.into()),
+                    });
+                }
+            }
+            Expr::Index(lhs, _, index, _) => {
+                self.dot(dot, lhs);
+                self.expr(index);
+                self.emit(Op::Index);
+            }
+            Expr::Dot(lhs, dot, rhs) => {
+                self.dot(dot, lhs);
+                self.dot(dot, rhs);
+            }
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error(diag::EXPECTED_FIELD, "expected a field or method name")
+                        .with_primary(dot.clone(), "after this `.`"),
+                );
+            }
+        }
+    }
+
+    // The steps of a place after `.`, leaving the last one to assign to.
+    fn place_dot(&mut self, dot: &Span<'a>, rhs: &Expr<'a>) -> Option<Last> {
+        match rhs {
+            Expr::Ident(name) => Some(Last::Field(name.to_string())),
+            Expr::Index(lhs, _, index, _) => {
+                self.dot(dot, lhs);
+                self.expr(index);
+                Some(Last::Index)
+            }
+            Expr::Dot(lhs, dot, rhs) => {
+                self.dot(dot, lhs);
+                self.place_dot(dot, rhs)
+            }
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        diag::INVALID_ASSIGN_TARGET,
+                        format!("cannot assign to `{rhs}`"),
+                    )
+                    .with_primary(dot.clone(), "")
+                    .with_help("only fields and indexed elements can be assigned to"),
+                );
+                None
+            }
+        }
+    }
+
+    // Everything of a field or element but the last step.
+    fn place(&mut self, expr: &Expr<'a>) -> Option<Last> {
+        match expr {
+            Expr::Index(lhs, _, index, _) => {
+                self.expr(lhs);
+                self.expr(index);
+                Some(Last::Index)
+            }
+            Expr::Dot(lhs, dot, rhs) => {
+                self.expr(lhs);
+                self.place_dot(dot, rhs)
+            }
+            _ => None,
+        }
+    }
+
+    // `target op= value` for a name or a place.
+    fn compound(&mut self, target: &Pattern<'a>, op: &Span<'a>, value: &Expr<'a>) {
+        let op = binary_op(&op[..op.len() - 1]);
+        match target {
+            Pattern::Name(name) => {
+                self.load(name);
+                self.expr(value);
+                self.emit(op);
+                self.assign(name);
+            }
+            Pattern::Place(place) => match self.place(place) {
+                Some(Last::Index) => {
+                    self.emit(Op::Dup2);
+                    self.emit(Op::Index);
+                    self.expr(value);
+                    self.emit(op);
+                    self.emit(Op::Under(2));
+                    self.emit(Op::SetIndex);
+                }
+                Some(Last::Field(name)) => {
+                    self.emit(Op::Dup);
+                    self.emit(Op::Dot(name.as_str().into()));
+                    self.expr(value);
+                    self.emit(op);
+                    self.emit(Op::Under(1));
+                    self.emit(Op::SetDot(name.as_str().into()));
+                }
+                None => self.expr(value),
+            },
+            // The parser has reported anything else.
+            _ => self.expr(value),
+        }
+    }
+
+    /// match value
+    ///     1 => ..       # integer arms at the start jump straight to
+    ///     2 => ..       # their bodies through a table
+    ///     (x, 0) => ..  # the rest are tried in order
+    fn match_(&mut self, keyword: &Span<'a>, value: &Expr<'a>, arms: &[Arm<'a>]) {
+        self.unreachable_arms(arms);
+        self.missing_variants(keyword, arms);
+        self.expr(value);
+        let depth = self.function().depth;
+        let mut ends = vec![];
+
+        let cases = arms
+            .iter()
+            .map_while(|arm| match (&arm.pattern, &arm.guard) {
+                (Pattern::Literal(expr), None) => match constant(expr) {
+                    Some(Variant::Int(n)) => Some((n.0, &arm.body)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let rest = if cases.len() >= 2 {
+            let switch = self.here() as usize;
+            self.emit(Op::Switch(Ref::new(Switch {
+                cases: HashMap::new(),
+                default: 0,
+            })));
+            let mut table = HashMap::new();
+            for (n, body) in &cases {
+                table.entry(*n).or_insert(self.here());
+                self.emit(Op::Pop);
+                self.expr(body);
+                ends.push(self.emit_jump(Op::Jump(0)));
+                self.function().depth = depth;
+            }
+            self.function().code[switch] = Op::Switch(Ref::new(Switch {
+                cases: table,
+                default: self.here(),
+            }));
+            &arms[cases.len()..]
+        } else {
+            arms
+        };
+
+        for arm in rest {
+            self.push(false);
+            self.emit(Op::Dup);
+            let outer = self.fails.replace(vec![]);
+            self.pattern(&arm.pattern, Some((BindingKind::Implicit, None)));
+            let mut fails = std::mem::replace(&mut self.fails, outer).unwrap();
+            if let Some((_, guard)) = &arm.guard {
+                self.expr(guard);
+                fails.push(self.emit_jump(Op::JumpIfFalse(0)));
+            }
+            self.emit(Op::Pop);
+            self.expr(&arm.body);
+            ends.push(self.emit_jump(Op::Jump(0)));
+            self.pop();
+            fails.into_iter().for_each(|fail| self.patch(fail));
+            self.emit(Op::Truncate(depth));
+        }
+        self.emit(Op::NoMatch);
+        ends.into_iter().for_each(|end| self.patch(end));
+    }
+
+    // Warn about arms that come after one matching everything they do.
+    fn unreachable_arms(&mut self, arms: &[Arm<'a>]) {
+        for (i, later) in arms.iter().enumerate() {
+            let earlier = arms[..i].iter().find(|arm| {
+                arm.guard.is_none() && covers(&self.variants, &arm.pattern, &later.pattern)
+            });
+            if let Some(earlier) = earlier {
+                self.diagnostics.push(
+                    Diagnostic::warning(diag::UNREACHABLE_ARM, "unreachable match arm")
+                        .with_primary(first_span(&later.pattern), "")
+                        .with_secondary(
+                            first_span(&earlier.pattern),
+                            "this arm matches everything it does",
+                        ),
+                );
+            }
+        }
+    }
+
+    // Warn about a `match` on the variants of an enum without an arm for
+    // every variant or one for anything.
+    fn missing_variants(&mut self, keyword: &Span<'a>, arms: &[Arm<'a>]) {
+        let enum_of = |arm: &Arm| match &arm.pattern {
+            Pattern::Name(s) | Pattern::Struct(s, ..) => {
+                variant(&self.variants, s).map(|(ty, _)| ty.clone())
+            }
+            _ => None,
+        };
+        let Some(ty) = arms.iter().find_map(enum_of) else {
+            return;
+        };
+        let arms = arms.iter().filter(|arm| arm.guard.is_none());
+        let patterns = arms.map(|arm| &arm.pattern).collect::<Vec<_>>();
+        let anything = Pattern::Wildcard(keyword.clone());
+        if (patterns.iter())
+            .any(|p| matches!(p, Pattern::Bad(_)) || covers(&self.variants, p, &anything))
+        {
+            return;
+        }
+        let names = |s: &str, case: &Case| {
+            variant(&self.variants, s).is_some_and(|(t, c)| t.name == ty.name && c == case)
+        };
+        let missing = (ty.cases().iter())
+            .filter(|case| {
+                !patterns.iter().any(|p| match p {
+                    Pattern::Name(s) => names(s, case),
+                    Pattern::Struct(s, _, fields, _) => {
+                        names(s, case)
+                            && fields.iter().all(|(field, _)| match field {
+                                FieldPattern::Name(_) => true,
+                                FieldPattern::Named(_, _, p) | FieldPattern::Positional(p) => {
+                                    covers(&self.variants, p, &anything)
+                                }
+                            })
+                    }
+                    _ => false,
+                })
+            })
+            .map(|case| format!("`{}`", case.name))
+            .collect::<Vec<_>>();
+        let Some((last, rest)) = missing.split_last() else {
+            return;
+        };
+        let missing = match rest {
+            [] => last.clone(),
+            _ => format!("{} and {last}", rest.join(", ")),
+        };
+        self.diagnostics.push(
+            Diagnostic::warning(
+                diag::NON_EXHAUSTIVE,
+                format!("match does not cover {missing}"),
+            )
+            .with_primary(keyword.clone(), "")
+            .with_help(format!(
+                "add an arm for each variant of `{}`, or `_` for the rest",
+                ty.name
+            )),
+        );
+    }
+
+    // Take apart the value on the stack, binding every name in the
+    // pattern, or assigning to them if `binding` is None.
+    fn pattern(
+        &mut self,
+        pattern: &Pattern<'a>,
+        binding: Option<(BindingKind, Option<&Span<'a>>)>,
+    ) {
+        let name = |this: &mut Self, name: &Span<'a>| match binding {
+            Some((kind, mut_)) => this.define(name.clone(), kind, mut_.cloned()),
+            None => this.assign(name),
+        };
+        match pattern {
+            Pattern::Name(s) if is_unit(&self.variants, s) => {
+                let (ty, case) = variant(&self.variants, s).unwrap();
+                self.unpack(Shape::Case(
+                    ty.name.as_str().into(),
+                    case.name.as_str().into(),
+                    Ref::new([]),
+                ))
+            }
+            // A name to bind starts with a lowercase letter and has no `.`,
+            // so this is most likely a variant misspelt or not declared.
+            Pattern::Name(s)
+                if self.fails.is_some() && s.starts_with(char::is_uppercase) || s.contains('.') =>
+            {
+                let message = match self.variants.contains_key(**s) {
+                    true => {
+                        format!("variant `{s}` holds values, so its name alone does not match it")
+                    }
+                    false => format!("no variant named `{s}`"),
+                };
+                self.diagnostics.push(
+                    Diagnostic::error(diag::UNKNOWN_VARIANT, message).with_primary(s.clone(), ""),
+                );
+                self.emit(Op::Pop);
+            }
+            Pattern::Name(s) | Pattern::Rest(_, Some(s)) => name(self, s),
+            Pattern::Wildcard(_) | Pattern::Rest(_, None) | Pattern::Bad(_) => self.emit(Op::Pop),
+            Pattern::Tuple(_, items, _) => {
+                self.unpack(Shape::Tuple(items.len() as u32));
+                items.iter().for_each(|(p, _)| self.pattern(p, binding))
+            }
+            Pattern::Array(_, items, _) => {
+                let rest = items
+                    .iter()
+                    .position(|(p, _)| matches!(p, Pattern::Rest(..)));
+                let before = rest.unwrap_or(items.len()) as u32;
+                let after = rest.map_or(0, |i| items.len() - i - 1) as u32;
+                self.unpack(Shape::Array(before, after, rest.is_some()));
+                items.iter().for_each(|(p, _)| self.pattern(p, binding))
+            }
+            Pattern::Struct(type_name, _, fields, _) => {
+                let keys = fields
+                    .iter()
+                    .map(|(field, _)| match field {
+                        FieldPattern::Name(s) | FieldPattern::Named(s, _, _) => Some((**s).into()),
+                        FieldPattern::Positional(_) => None,
+                    })
+                    .collect();
+                match variant(&self.variants, type_name) {
+                    Some((ty, case)) => {
+                        let ty = ty.name.as_str().into();
+                        self.unpack(Shape::Case(ty, case.name.as_str().into(), keys))
+                    }
+                    // Only an enum's variants are named with a `.`.
+                    None if type_name.contains('.') => {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                diag::UNKNOWN_VARIANT,
+                                format!("no variant named `{type_name}`"),
+                            )
+                            .with_primary(type_name.clone(), ""),
+                        );
+                        self.unpack(Shape::Struct((**type_name).into(), keys))
+                    }
+                    None => self.unpack(Shape::Struct((**type_name).into(), keys)),
+                }
+                for (field, _) in fields {
+                    match field {
+                        FieldPattern::Name(s) => self.pattern(&Pattern::Name(s.clone()), binding),
+                        FieldPattern::Named(_, _, p) | FieldPattern::Positional(p) => {
+                            self.pattern(p, binding)
+                        }
+                    }
+                }
+            }
+            Pattern::Literal(_) | Pattern::Range(..) if self.fails.is_none() => {
+                let span = first_span(pattern);
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        diag::REFUTABLE_PATTERN,
+                        format!("`{pattern}` does not match every value"),
+                    )
+                    .with_primary(span, "")
+                    .with_help("use `match` to test a value against it"),
+                );
+                self.emit(Op::Pop);
+            }
+            Pattern::Literal(expr) => {
+                self.emit(Op::Dup);
+                self.expr(expr);
+                self.emit(Op::Eq);
+                self.fail_unless_true();
+                self.emit(Op::Pop);
+            }
+            Pattern::Range(lo, _, hi) => {
+                self.emit(Op::Dup);
+                self.expr(lo);
+                self.expr(hi);
+                self.emit(Op::InRange);
+                self.fail_unless_true();
+                self.emit(Op::Pop);
+            }
+            // Changing part of a value is not assigning to its name.
+            Pattern::Place(expr) => match self.place(expr) {
+                Some(Last::Index) => self.emit(Op::SetIndex),
+                Some(Last::Field(name)) => self.emit(Op::SetDot(name.as_str().into())),
+                None => self.emit(Op::Pop),
+            },
+        }
+    }
+
+    // Replace the value on the stack with its parts, the first on top.
+    fn unpack(&mut self, shape: Shape) {
+        if self.fails.is_some() {
+            self.emit(Op::TryUnpack(shape));
+            self.fail_unless_true();
+        } else {
+            self.emit(Op::Unpack(shape));
+        }
+    }
+
+    // Leave the arm being matched unless the value on the stack is true.
+    fn fail_unless_true(&mut self) {
+        let jump = self.emit_jump(Op::JumpIfFalse(0));
+        self.fails.as_mut().unwrap().push(jump);
+    }
+
+    fn push(&mut self, function: bool) {
+        self.scopes.push(Scope {
+            bindings: vec![],
+            function,
+            owner: self.functions.len() - 1,
+        });
+    }
+
+    fn pop(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        // One `let mut` can bind several names and is needed if any of
+        // them is assigned.
+        let same = |a: &Span, b: &Span| a.as_ptr() == b.as_ptr();
+        let mut seen: Vec<Span<'a>> = vec![];
+        for binding in &scope.bindings {
+            let Some(mut_) = &binding.mut_ else {
+                continue;
+            };
+            if seen.iter().any(|s| same(s, mut_)) {
+                continue;
+            }
+            seen.push(mut_.clone());
+            let bound = scope
+                .bindings
+                .iter()
+                .filter(|b| b.mut_.as_ref().is_some_and(|m| same(m, mut_)))
+                .collect::<Vec<_>>();
+            if bound.iter().any(|b| b.assigned) {
+                continue;
+            }
+            let names = bound
+                .iter()
+                .map(|b| format!("`{}`", b.name))
+                .collect::<Vec<_>>();
+            let message = match names.as_slice() {
+                [name] => format!("variable {name} does not need to be mutable"),
+                names => format!("variables {} do not need to be mutable", names.join(", ")),
+            };
+            let mut diagnostic = Diagnostic::warning(diag::UNUSED_MUT, message)
+                .with_primary(mut_.clone(), "")
+                .with_help("remove `mut`");
+            for b in bound {
+                diagnostic = diagnostic.with_secondary(b.name.clone(), "never assigned after this");
+            }
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    // Report names bound twice by the parameters of the closure whose
+    // scope this is.
+    fn duplicate_params(&mut self) {
+        let bindings = &self.scopes.last().unwrap().bindings;
+        for (i, binding) in bindings.iter().enumerate() {
+            let Some(first) = bindings[..i].iter().find(|b| *b.name == *binding.name) else {
+                continue;
+            };
+            self.diagnostics.push(
+                Diagnostic::error(
+                    diag::DUPLICATE_PARAM,
+                    format!("parameter `{}` is named more than once", binding.name),
+                )
+                .with_primary(binding.name.clone(), "")
+                .with_secondary(first.name.clone(), "first named here"),
+            );
+        }
+    }
+
+    fn push_loop(&mut self, depth: u32, exit_depth: u32, start: u32) {
+        self.function().loops.push(Loop {
+            depth,
+            exit_depth,
+            start,
+            breaks: vec![],
+        });
+    }
+
+    // Send every `break` of the innermost loop here.
+    fn pop_loop(&mut self) {
+        let inner = self.function().loops.pop().unwrap();
+        inner.breaks.into_iter().for_each(|b| self.patch(b));
+    }
+
+    // Bind a name in the innermost scope, in a new local unless given
+    // one.
+    fn bind(
+        &mut self,
+        name: Span<'a>,
+        kind: BindingKind,
+        mut_: Option<Span<'a>>,
+        slot: Option<u32>,
+    ) {
+        let slot = match slot {
+            Some(slot) => Slot::Local(slot),
+            None if self.scopes.len() == 1 => Slot::Global,
+            None => Slot::Local(self.new_local()),
+        };
+        self.scopes.last_mut().unwrap().bindings.push(Binding {
+            name,
+            kind,
+            mut_,
+            assigned: false,
+            slot,
+            params: None,
+        });
+    }
+
+    // Bind a name to the value on the stack.
+    fn define(&mut self, name: Span<'a>, kind: BindingKind, mut_: Option<Span<'a>>) {
+        self.bind(name.clone(), kind, mut_, None);
+        match self.access(&name) {
+            Access::Local(slot) => self.emit(Op::InitLoc(slot)),
+            access => self.store(access),
+        }
+    }
+
+    fn new_local(&mut self) -> u32 {
+        let function = self.function();
+        function.locals += 1;
+        function.locals - 1
+    }
+
+    // The innermost binding of a name, so later ones shadow earlier ones.
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding<'a>> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.bindings.iter_mut().rev())
+            .find(|binding| *binding.name == name)
+    }
+
+    // Where a name is, capturing it from the functions around this one if
+    // need be. Names never bound are globals defined elsewhere.
+    fn access(&mut self, name: &str) -> Access {
+        let found = self.scopes.iter().rev().find_map(|scope| {
+            let binding = scope.bindings.iter().rev().find(|b| *b.name == name)?;
+            Some((scope.owner, binding.slot))
+        });
+        match found {
+            Some((owner, Slot::Local(slot))) if owner == self.functions.len() - 1 => {
+                Access::Local(slot)
+            }
+            Some((owner, Slot::Local(slot))) => {
+                Access::Cap(self.capture(self.functions.len() - 1, owner, slot))
+            }
+            Some((_, Slot::Global)) | None => Access::Global(name.to_string()),
+        }
+    }
+
+    // The capture of `function` holding local `slot` of `owner`.
+    fn capture(&mut self, function: usize, owner: usize, slot: u32) -> u32 {
+        let captures = &self.functions[function].captures;
+        if let Some(i) = captures.iter().position(|(key, _)| *key == (owner, slot)) {
+            return i as u32;
+        }
+        let from = if function - 1 == owner {
+            Capture::Local(slot)
+        } else {
+            Capture::Cap(self.capture(function - 1, owner, slot))
+        };
+        let captures = &mut self.functions[function].captures;
+        captures.push(((owner, slot), from));
+        captures.len() as u32 - 1
+    }
+
+    fn load(&mut self, name: &str) {
+        match self.access(name) {
+            Access::Local(slot) => self.emit(Op::Loc(slot)),
+            Access::Cap(i) => self.emit(Op::Cap(i)),
+            Access::Global(name) => self.emit(Op::Global(name.as_str().into())),
+        }
+    }
+
+    fn store(&mut self, access: Access) {
+        match access {
+            Access::Local(slot) => self.emit(Op::SetLoc(slot)),
+            Access::Cap(i) => self.emit(Op::SetCap(i)),
+            Access::Global(name) => self.emit(Op::SetGlobal(name.as_str().into())),
+        }
+    }
+
+    // Assign the value on the stack to a name.
+    fn assign(&mut self, name: &Span<'a>) {
+        let Some(binding) = self.lookup(name) else {
+            // `x = 1` without `let` binds x for the rest of the function.
+            let scope = self.scopes.iter().rposition(|s| s.function).unwrap();
+            let slot = match scope {
+                0 => Slot::Global,
+                _ => Slot::Local(self.new_local()),
+            };
+            self.scopes[scope].bindings.push(Binding {
+                name: name.clone(),
+                kind: BindingKind::Implicit,
+                mut_: None,
+                assigned: false,
+                slot,
+                params: None,
+            });
+            let access = self.access(name);
+            self.store(access);
+            return;
+        };
+        binding.assigned = true;
+        if binding.kind == BindingKind::Let {
+            let first = binding.name.clone();
+            self.diagnostics.push(
+                Diagnostic::error(
+                    diag::ASSIGN_TO_IMMUTABLE,
+                    format!("cannot assign twice to immutable variable `{name}`"),
+                )
+                .with_primary(name.clone(), "")
+                .with_secondary(first, "first assigned here")
+                .with_help(format!("make the variable mutable: `let mut {name}`")),
+            );
+        }
+        let access = self.access(name);
+        self.store(access);
+    }
+
+    fn function(&mut self) -> &mut Function {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn here(&mut self) -> u32 {
+        self.function().code.len() as u32
+    }
+
+    fn emit(&mut self, op: Op) {
+        let function = self.function();
+        function.depth = match &op {
+            Op::Truncate(depth) => *depth,
+            op => (function.depth as i64 + effect(op)).max(0) as u32,
+        };
+        function.code.push(op);
+    }
+
+    // Emit a jump to be sent somewhere by `patch`.
+    fn emit_jump(&mut self, op: Op) -> usize {
+        self.emit(op);
+        self.function().code.len() - 1
+    }
+
+    // Send the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.function().code[at] {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::JumpIfTrue(to)
+            | Op::JumpIfGiven(_, to)
+            | Op::Next(to) => *to = here,
+            op => panic!("not a jump: {op:?}"),
+        }
+    }
+
+    fn constant(&mut self, value: Variant) {
+        let op = match value {
+            Variant::Int(n) => Op::Int(n.0),
+            Variant::Float(x) => Op::Float(x.0),
+            Variant::BigInt(bytes) => Op::BigInt(bytes),
+            Variant::Str(s) => Op::Str(s),
+            Variant::Bytes(bytes) => Op::Bytes(bytes),
+            value => panic!("not a constant: {value:?}"),
+        };
+        self.emit(op);
+    }
+}
+
+impl Function {
+    fn new(name: &str, params: Vec<Param>) -> Self {
+        Self {
+            name: name.to_string(),
+            locals: params.len() as u32,
+            params,
+            captures: vec![],
+            code: vec![],
+            depth: 0,
+            loops: vec![],
+        }
+    }
+
+    fn finish(self) -> Ref<Proto> {
+        Ref::new(Proto {
+            name: self.name,
+            params: self.params.into(),
+            locals: self.locals,
+            bytecode: self.code.into(),
+        })
+    }
+}
+
+// Whether a closure parameter is given a local: all but a bare `*`.
+fn has_local(arg: &FormalArg) -> bool {
+    !matches!(arg, FormalArg::Rest(_, None) | FormalArg::Bad(_))
+}
+
+// How callers see the parameters that have locals.
+fn params(args: &[&FormalArg]) -> Vec<Param> {
+    let mut keyword = false;
+    args.iter()
+        .filter_map(|arg| {
+            let kind = match arg {
+                FormalArg::Rest(..) => ParamKind::Rest,
+                _ if keyword => ParamKind::Keyword,
+                _ => ParamKind::Positional,
+            };
+            keyword |= kind == ParamKind::Rest;
+            has_local(arg).then(|| Param {
+                name: arg.name().map(|name| (**name).into()),
+                kind,
+                default: matches!(arg, FormalArg::NameWithDefault(..)),
+            })
+        })
+        .collect()
+}
+
+fn binary_op(op: &str) -> Op {
+    match op {
+        "+" => Op::Add,
+        "-" => Op::Sub,
+        "*" => Op::Mul,
+        "/" => Op::Div,
+        "%" => Op::Rem,
+        "**" => Op::Pow,
+        "<<" => Op::Shl,
+        ">>" => Op::Shra,
+        ">>>" => Op::Shr,
+        "&" => Op::BitAnd,
+        "|" => Op::BitOr,
+        "^" => Op::BitXor,
+        "==" => Op::Eq,
+        "!=" => Op::Ne,
+        "<" => Op::Lt,
+        "<=" => Op::Le,
+        ">" => Op::Gt,
+        ">=" => Op::Ge,
+        ".." => Op::Range,
+        op => panic!("not a binary operator: {op}"),
+    }
+}
+
+// How many values an instruction adds to the stack, or takes off it.
+fn effect(op: &Op) -> i64 {
+    match op {
+        Op::Unit
+        | Op::Bool(_)
+        | Op::Int(_)
+        | Op::Float(_)
+        | Op::BigInt(_)
+        | Op::Bytes(_)
+        | Op::Str(_)
+        | Op::Fn(..)
+        | Op::Loc(_)
+        | Op::Cap(_)
+        | Op::Global(_)
+        | Op::Type(_)
+        | Op::Trait(_)
+        | Op::Dup
+        | Op::Next(_) => 1,
+        Op::Dup2 => 2,
+        Op::Add
+        | Op::Sub
+        | Op::Mul
+        | Op::Div
+        | Op::Rem
+        | Op::Pow
+        | Op::Shl
+        | Op::Shr
+        | Op::Shra
+        | Op::BitAnd
+        | Op::BitOr
+        | Op::BitXor
+        | Op::Eq
+        | Op::Ne
+        | Op::Lt
+        | Op::Le
+        | Op::Gt
+        | Op::Ge
+        | Op::Range
+        | Op::Index
+        | Op::SetLoc(_)
+        | Op::InitLoc(_)
+        | Op::SetCap(_)
+        | Op::SetGlobal(_)
+        | Op::Pop
+        | Op::Return
+        | Op::JumpIfFalse(_)
+        | Op::JumpIfTrue(_)
+        | Op::Extend
+        | Op::Impl(_) => -1,
+        Op::SetDot(_) | Op::InRange | Op::Implement => -2,
+        Op::SetIndex => -3,
+        Op::Method(_, n) | Op::MethodKw(_, n, _) | Op::Call(n) | Op::CallKw(n, _) => -(*n as i64),
+        Op::Array(n) | Op::Tuple(n) | Op::Concat(n) => 1 - *n as i64,
+        Op::Map(n) => 1 - 2 * *n as i64,
+        Op::Unpack(shape) => parts(shape) - 1,
+        Op::TryUnpack(shape) => parts(shape),
+        Op::Neg
+        | Op::Not
+        | Op::Dot(_)
+        | Op::Ok
+        | Op::Err
+        | Op::Under(_)
+        | Op::Jump(_)
+        | Op::Iter
+        | Op::Truncate(_)
+        | Op::JumpIfGiven(..)
+        | Op::Switch(_)
+        | Op::NoMatch
+        | Op::Format(_) => 0,
+    }
+}
+
+// The number of values `Unpack` leaves for a shape.
+fn parts(shape: &Shape) -> i64 {
+    match shape {
+        Shape::Tuple(n) => *n as i64,
+        Shape::Array(before, after, rest) => (before + after + *rest as u32) as i64,
+        Shape::Struct(_, fields) | Shape::Case(_, _, fields) => fields.len() as i64,
+    }
+}
+
+// The value of a literal pattern.
+fn constant(expr: &Expr) -> Option<Variant> {
+    match expr {
+        Expr::Int(_) | Expr::Float(_) => literal_value(expr, &mut Diagnostics::new()),
+        Expr::Str(span) => Some(decode_str(span, &mut Diagnostics::new()).into()),
+        Expr::Unary(_, expr) => match constant(expr)? {
+            Variant::Int(n) => n.0.checked_neg().map(Variant::from),
+            Variant::Float(x) => Some((-x.0).into()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// A number from a literal pattern, for comparing with ranges.
+fn number(expr: &Expr) -> Option<f64> {
+    match constant(expr)? {
+        Variant::Int(n) => Some(n.0 as f64),
+        Variant::Float(x) => Some(x.0),
+        _ => None,
+    }
+}
+
+// The enum and variant a pattern names, as `Rect` or `Shape.Rect`.
+fn variant<'v>(
+    variants: &'v HashMap<String, Ref<Type>>,
+    name: &str,
+) -> Option<(&'v Ref<Type>, &'v Case)> {
+    let ty = variants.get(name)?;
+    let name = name.rsplit('.').next().unwrap();
+    Some((ty, ty.cases().iter().find(|case| case.name == name)?))
+}
+
+// Whether `name` is a variant that holds nothing.
+fn is_unit(variants: &HashMap<String, Ref<Type>>, name: &str) -> bool {
+    variant(variants, name).is_some_and(|(_, case)| case.fields == Fields::Unit)
+}
+
+// Whether two names in patterns are the same, `Rect` being `Shape.Rect`.
+fn same(variants: &HashMap<String, Ref<Type>>, a: &str, b: &str) -> bool {
+    match (variant(variants, a), variant(variants, b)) {
+        (Some((ta, ca)), Some((tb, cb))) => ta.name == tb.name && ca == cb,
+        _ => a == b,
+    }
+}
+
+// An `enum` as the type its declaration makes.
+fn enum_type(name: &Span, cases: &[ast::Case]) -> Type {
+    let case = |case: &ast::Case| Case {
+        name: case.name.to_string(),
+        fields: match &case.brackets {
+            None => Fields::Unit,
+            Some((open, _)) if **open == "(" => Fields::Tuple(case.fields.len() as u32),
+            Some(_) => Fields::Named(
+                (case.fields.iter())
+                    .map(|(name, _)| Field {
+                        name: name.to_string(),
+                    })
+                    .collect(),
+            ),
+        },
+    };
+    Type {
+        name: name.to_string(),
+        field: Box::new([]),
+        kind: TypeKind::Enum(cases.iter().map(case).collect()),
+        methods: Default::default(),
+    }
+}
+
+// Whether every value `later` matches is matched by `earlier`.
+fn covers(variants: &HashMap<String, Ref<Type>>, earlier: &Pattern, later: &Pattern) -> bool {
+    let covers = |a: &Pattern, b: &Pattern| covers(variants, a, b);
+    let same = |a: &str, b: &str| same(variants, a, b);
+    let all = |a: &[(Pattern, Option<Span>)], b: &[(Pattern, Option<Span>)]| {
+        a.len() == b.len() && a.iter().zip(b).all(|((a, _), (b, _))| covers(a, b))
+    };
+    match (earlier, later) {
+        (_, Pattern::Bad(_)) | (Pattern::Bad(_), _) => false,
+        // A variant that holds nothing, which may be written `None()`.
+        (Pattern::Name(a), _) if is_unit(variants, a) => match later {
+            Pattern::Name(b) => same(a, b),
+            Pattern::Struct(b, _, fields, _) => same(a, b) && fields.is_empty(),
+            _ => false,
+        },
+        (Pattern::Struct(a, _, fields, _), Pattern::Name(b)) if is_unit(variants, b) => {
+            same(a, b) && fields.is_empty()
+        }
+        // Not a variant that holds nothing, which is an error in a `match`.
+        (Pattern::Name(a), _) if a.starts_with(char::is_uppercase) => false,
+        (Pattern::Name(_) | Pattern::Wildcard(_), _) => true,
+        (Pattern::Tuple(_, a, _), Pattern::Tuple(_, b, _)) => all(a, b),
+        (Pattern::Array(_, a, _), Pattern::Array(_, b, _)) => {
+            let is_rest = |(p, _): &(Pattern, Option<Span>)| matches!(p, Pattern::Rest(..));
+            match (a.iter().position(is_rest), b.iter().any(is_rest)) {
+                (None, false) => all(a, b),
+                // [x, ..] covers [1, 2, 3].
+                (Some(rest), false) => {
+                    let after = a.len() - rest - 1;
+                    b.len() >= rest + after
+                        && all(&a[..rest], &b[..rest])
+                        && all(&a[rest + 1..], &b[b.len() - after..])
+                }
+                (Some(_), true) => all(a, b),
+                (None, true) => false,
+            }
+        }
+        (Pattern::Struct(a, _, fa, _), Pattern::Struct(b, _, fb, _)) => {
+            same(a, b)
+                && fa.iter().enumerate().all(|(i, (f, _))| match f {
+                    FieldPattern::Name(_) => true,
+                    FieldPattern::Positional(p) => match fb.get(i) {
+                        Some((FieldPattern::Positional(q), _)) => covers(p, q),
+                        _ => covers(p, &Pattern::Wildcard(a.clone())),
+                    },
+                    FieldPattern::Named(name, _, p) => {
+                        let q = fb.iter().find_map(|(f, _)| match f {
+                            FieldPattern::Named(s, _, q) if **s == **name => Some(q),
+                            _ => None,
+                        });
+                        // Fields left out, or just named, match anything.
+                        match q {
+                            Some(q) => covers(p, q),
+                            None => covers(p, &Pattern::Wildcard(name.clone())),
+                        }
+                    }
+                })
+        }
+        (Pattern::Literal(a), Pattern::Literal(b)) => {
+            constant(a).is_some() && constant(a) == constant(b)
+                || number(a).is_some() && number(a) == number(b)
+        }
+        (Pattern::Range(lo, _, hi), Pattern::Literal(b)) => {
+            matches!((number(lo), number(hi), number(b)), (Some(lo), Some(hi), Some(b)) if lo <= b && b < hi)
+        }
+        (Pattern::Range(lo, _, hi), Pattern::Range(lo2, _, hi2)) => matches!(
+            (number(lo), number(hi), number(lo2), number(hi2)),
+            (Some(lo), Some(hi), Some(lo2), Some(hi2)) if lo <= lo2 && hi2 <= hi
+        ),
+        _ => false,
+    }
+}
+
+// Where a pattern starts, to point at it.
+fn first_span<'a>(pattern: &Pattern<'a>) -> Span<'a> {
+    let mut first = None;
+    pattern.map_spans(&mut |span| {
+        let better = first
+            .as_ref()
+            .is_none_or(|f: &Span| span.as_ptr() < f.as_ptr());
+        if better {
+            first = Some(span.clone());
+        }
+        span.clone()
+    });
+    first.unwrap()
+}
@@ -0,0 +1,313 @@
+use std::ops::Range;
+
+use crate::{
+    ast::{parse_programme, Expr, Programme},
+    diag::Diagnostics,
+    lex::{Lex, Token, Trivia},
+};
+
+/// What a syntax node is. The expression kinds match the `Expr`
+/// variants.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyntaxKind {
+    Programme,
+    Ident,
+    Int,
+    Float,
+    Str,
+    Interpolated,
+    Closure,
+    FormalArg,
+    Block,
+    Array,
+    Map,
+    /// `key: value` or `..other` in a map.
+    Entry,
+    Spread,
+    Binary,
+    Unary,
+    Paren,
+    Tuple,
+    Call,
+    Index,
+    Dot,
+    Assign,
+    If,
+    While,
+    For,
+    Break,
+    Continue,
+    Return,
+    Let,
+    Match,
+    Arm,
+    Struct,
+    Enum,
+    Impl,
+    Trait,
+    Fn,
+    Signature,
+    /// `Circle(Float)` or `Rect{w, h}` in an `enum`.
+    Case,
+    /// A pattern other than a plain name.
+    Pattern,
+    /// Tokens that could not be parsed.
+    Error,
+}
+
+impl SyntaxKind {
+    pub fn of(expr: &Expr) -> Self {
+        match expr {
+            Expr::Ident(_) => SyntaxKind::Ident,
+            Expr::Int(_) => SyntaxKind::Int,
+            Expr::Float(_) => SyntaxKind::Float,
+            Expr::Str(_) => SyntaxKind::Str,
+            Expr::Interpolated(..) => SyntaxKind::Interpolated,
+            Expr::Closure(_) => SyntaxKind::Closure,
+            Expr::Block(_) => SyntaxKind::Block,
+            Expr::Array(..) => SyntaxKind::Array,
+            Expr::Map(..) => SyntaxKind::Map,
+            Expr::Spread(..) => SyntaxKind::Spread,
+            Expr::Binary(..) => SyntaxKind::Binary,
+            Expr::Unary(..) => SyntaxKind::Unary,
+            Expr::Paren(..) => SyntaxKind::Paren,
+            Expr::Tuple(..) => SyntaxKind::Tuple,
+            Expr::Call(..) => SyntaxKind::Call,
+            Expr::Index(..) => SyntaxKind::Index,
+            Expr::Dot(..) => SyntaxKind::Dot,
+            Expr::Assign(..) => SyntaxKind::Assign,
+            Expr::If(..) => SyntaxKind::If,
+            Expr::While(..) => SyntaxKind::While,
+            Expr::For(..) => SyntaxKind::For,
+            Expr::Break(_) => SyntaxKind::Break,
+            Expr::Continue(_) => SyntaxKind::Continue,
+            Expr::Return(..) => SyntaxKind::Return,
+            Expr::Let(..) => SyntaxKind::Let,
+            Expr::Match(..) => SyntaxKind::Match,
+            Expr::Struct(..) => SyntaxKind::Struct,
+            Expr::Enum(..) => SyntaxKind::Enum,
+            Expr::Impl(..) => SyntaxKind::Impl,
+            Expr::Trait(..) => SyntaxKind::Trait,
+            Expr::Fn(..) => SyntaxKind::Fn,
+            Expr::Signature(..) => SyntaxKind::Signature,
+            Expr::Bad(_) => SyntaxKind::Error,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Element<'a> {
+    Node(Node<'a>),
+    Token(Token<'a>),
+    Trivia(Trivia<'a>),
+}
+
+/// A node of the concrete syntax tree. Its children hold every token
+/// and every piece of trivia in order, so printing a node gives back
+/// exactly the source it was parsed from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node<'a> {
+    kind: SyntaxKind,
+    children: Vec<Element<'a>>,
+}
+
+/// A lossless parse of a source file: the concrete syntax tree together
+/// with the `Programme` that is a typed view over it.
+///
+/// ```text
+/// let tree = SyntaxTree::parse(src);
+/// assert_eq!(tree.to_string(), src);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxTree<'a> {
+    src: &'a str,
+    root: Node<'a>,
+    programme: Programme<'a>,
+    diagnostics: Diagnostics<'a>,
+}
+
+impl<'a> Node<'a> {
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn children(&self) -> &[Element<'a>] {
+        &self.children
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node<'a>> {
+        self.children.iter().filter_map(|e| match e {
+            Element::Node(node) => Some(node),
+            _ => None,
+        })
+    }
+
+    /// Every token in the node, without trivia.
+    pub fn tokens(&self) -> Vec<&Token<'a>> {
+        let mut tokens = vec![];
+        self.visit(&mut |e| {
+            if let Element::Token(token) = e {
+                tokens.push(token);
+            }
+        });
+        tokens
+    }
+
+    // Call `f` on every token and piece of trivia in order.
+    fn visit<'n>(&'n self, f: &mut impl FnMut(&'n Element<'a>)) {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.visit(f),
+                leaf => f(leaf),
+            }
+        }
+    }
+
+    // The byte range from the start of the first token to the end of the
+    // last, leaving out leading trivia.
+    fn range(&self, src: &str) -> Option<Range<usize>> {
+        let tokens = self.tokens();
+        let offset = |text: &str| text.as_ptr() as usize - src.as_ptr() as usize;
+        let first = tokens.first()?.text();
+        let last = tokens.last()?.text();
+        Some(offset(first)..offset(last) + last.len())
+    }
+}
+
+impl<'a> std::fmt::Display for Node<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut result = Ok(());
+        self.visit(&mut |e| {
+            let text = match e {
+                Element::Token(token) => token.text(),
+                Element::Trivia(trivia) => trivia.text(),
+                Element::Node(_) => "",
+            };
+            result = result.and_then(|_| f.write_str(text));
+        });
+        result
+    }
+}
+
+impl<'a> SyntaxTree<'a> {
+    pub fn parse(src: &'a str) -> Self {
+        let mut lex = Lex::with_trivia(src);
+        let programme = parse_programme(&mut lex);
+        let diagnostics = lex.take_diagnostics();
+
+        // Keep whatever the parser gave up on, and the trivia at the end.
+        let rest = lex.checkpoint();
+        while !lex.is_eof() {
+            lex.advance();
+        }
+        lex.finish_node(SyntaxKind::Error, rest);
+        lex.advance();
+        lex.finish_node(SyntaxKind::Programme, 0);
+
+        let tokens = lex.take_tokens();
+        // Parents are finished after their children, so for equal ranges
+        // the later node is the outer one.
+        let mut nodes = lex
+            .take_nodes()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (kind, range))| *kind != SyntaxKind::Error || !range.is_empty())
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|(i, (_, range))| {
+            (
+                range.start,
+                std::cmp::Reverse(range.end),
+                std::cmp::Reverse(*i),
+            )
+        });
+        let nodes = nodes.into_iter().map(|(_, node)| node).collect::<Vec<_>>();
+        let mut builder = Builder {
+            tokens: tokens.into_iter(),
+            nodes: nodes.into_iter().peekable(),
+            pos: 0,
+        };
+        let (kind, range) = builder.nodes.next().unwrap();
+        let root = builder.node(kind, range.end);
+        Self {
+            src,
+            root,
+            programme,
+            diagnostics,
+        }
+    }
+
+    pub fn src(&self) -> &'a str {
+        self.src
+    }
+
+    pub fn root(&self) -> &Node<'a> {
+        &self.root
+    }
+
+    pub fn programme(&self) -> &Programme<'a> {
+        &self.programme
+    }
+
+    /// The same diagnostics as `parse_programme` reports.
+    pub fn diagnostics(&self) -> &Diagnostics<'a> {
+        &self.diagnostics
+    }
+
+    /// The syntax node an expression of `programme` was parsed from.
+    pub fn node(&self, expr: &Expr<'a>) -> Option<&Node<'a>> {
+        let mut range: Option<Range<usize>> = None;
+        expr.map_spans(&mut |span| {
+            let start = span.as_ptr() as usize - self.src.as_ptr() as usize;
+            let r = range.get_or_insert(start..start);
+            r.start = r.start.min(start);
+            r.end = r.end.max(start + span.len());
+            span.clone()
+        });
+        let (range, kind) = (range?, SyntaxKind::of(expr));
+        let mut node = &self.root;
+        loop {
+            node = node.nodes().find(|n| {
+                n.range(self.src)
+                    .is_some_and(|r| r.start <= range.start && range.end <= r.end)
+            })?;
+            if node.kind == kind && node.range(self.src) == Some(range.clone()) {
+                return Some(node);
+            }
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for SyntaxTree<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.root.fmt(f)
+    }
+}
+
+// Nests the nodes, sorted by where they start, around the tokens they
+// cover.
+struct Builder<'a> {
+    tokens: std::vec::IntoIter<(Vec<Trivia<'a>>, Token<'a>)>,
+    nodes: std::iter::Peekable<std::vec::IntoIter<(SyntaxKind, Range<usize>)>>,
+    pos: usize,
+}
+
+impl<'a> Builder<'a> {
+    fn node(&mut self, kind: SyntaxKind, end: usize) -> Node<'a> {
+        let mut children = vec![];
+        loop {
+            if let Some((kind, range)) = self
+                .nodes
+                .next_if(|(_, r)| r.start == self.pos && r.end <= end)
+            {
+                children.push(Element::Node(self.node(kind, range.end)));
+            } else if self.pos < end {
+                let (trivia, token) = self.tokens.next().unwrap();
+                children.extend(trivia.into_iter().map(Element::Trivia));
+                children.push(Element::Token(token));
+                self.pos += 1;
+            } else {
+                return Node { kind, children };
+            }
+        }
+    }
+}
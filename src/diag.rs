@@ -0,0 +1,190 @@
+use crate::lex::Span;
+
+// Stable error codes. Never renumber these, only add new ones.
+
+// Lexer.
+pub const UNKNOWN_TOKEN: &str = "E0001";
+pub const UNTERMINATED_STRING: &str = "E0002";
+pub const UNTERMINATED_COMMENT: &str = "E0003";
+pub const INCONSISTENT_DEDENT: &str = "E0004";
+pub const TAB_INDENT: &str = "E0005";
+pub const INVALID_ESCAPE: &str = "E0006";
+pub const NON_ASCII_BYTES: &str = "E0007";
+pub const UNTERMINATED_PLACEHOLDER: &str = "E0008";
+pub const UNMATCHED_BRACE: &str = "E0009";
+pub const EMPTY_PLACEHOLDER: &str = "E0010";
+pub const INVALID_NUMBER: &str = "E0011";
+pub const INVALID_UTF8: &str = "E0012";
+
+// Parser.
+pub const EXPECTED_EXPR: &str = "E0100";
+pub const EXPECTED_SEPARATOR: &str = "E0101";
+pub const EXPECTED_CLOSE: &str = "E0102";
+pub const UNTERMINATED_BLOCK: &str = "E0103";
+pub const EXPECTED_TOKEN: &str = "E0104";
+pub const INVALID_FORMAT_SPEC: &str = "E0105";
+pub const INVALID_ASSIGN_TARGET: &str = "E0106";
+pub const CHAINED_OPERATOR: &str = "E0107";
+pub const EXPECTED_BLOCK: &str = "E0108";
+pub const MULTIPLE_REST: &str = "E0109";
+pub const POSITIONAL_AFTER_KEYWORD: &str = "E0110";
+
+// Literal values.
+pub const FLOAT_OUT_OF_RANGE: &str = "E0200";
+
+// Compiler.
+pub const ASSIGN_TO_IMMUTABLE: &str = "E0300";
+pub const REFUTABLE_PATTERN: &str = "E0301";
+pub const OUTSIDE_LOOP: &str = "E0302";
+pub const EXPECTED_FIELD: &str = "E0303";
+pub const DUPLICATE_PARAM: &str = "E0304";
+pub const DUPLICATE_ARG: &str = "E0305";
+pub const ARGUMENT_MISMATCH: &str = "E0306";
+pub const EMPTY_SPREAD: &str = "E0307";
+pub const DUPLICATE_NAME: &str = "E0308";
+pub const UNKNOWN_VARIANT: &str = "E0309";
+
+// Compiler warnings.
+pub const UNUSED_MUT: &str = "W0300";
+pub const UNREACHABLE_ARM: &str = "W0301";
+pub const NON_EXHAUSTIVE: &str = "W0302";
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label<'a> {
+    pub span: Span<'a>,
+    pub message: String,
+    pub primary: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic<'a> {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub labels: Vec<Label<'a>>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+/// Diagnostics collected while lexing, parsing or compiling.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Diagnostics<'a> {
+    items: Vec<Diagnostic<'a>>,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            labels: vec![],
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    pub fn with_primary(mut self, span: Span<'a>, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span<'a>, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    pub fn primary_span(&self) -> Option<&Span<'a>> {
+        self.labels.iter().find(|l| l.primary).map(|l| &l.span)
+    }
+}
+
+impl<'a> Diagnostics<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic<'a>) {
+        self.items.push(diagnostic);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic<'a>> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    pub fn codes(&self) -> Vec<&'static str> {
+        self.items.iter().map(|d| d.code).collect()
+    }
+
+    pub fn take(&mut self) -> Vec<Diagnostic<'a>> {
+        std::mem::take(&mut self.items)
+    }
+}
+
+impl<'a> IntoIterator for Diagnostics<'a> {
+    type Item = Diagnostic<'a>;
+    type IntoIter = std::vec::IntoIter<Diagnostic<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a> FromIterator<Diagnostic<'a>> for Diagnostics<'a> {
+    fn from_iter<T: IntoIterator<Item = Diagnostic<'a>>>(iter: T) -> Self {
+        Self {
+            items: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> Extend<Diagnostic<'a>> for Diagnostics<'a> {
+    fn extend<T: IntoIterator<Item = Diagnostic<'a>>>(&mut self, iter: T) {
+        self.items.extend(iter)
+    }
+}
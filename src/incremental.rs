@@ -0,0 +1,240 @@
+use std::ops::Range;
+
+use crate::{
+    ast::{parse_top_level, Expr, Programme},
+    diag::{Diagnostic, Diagnostics, Label},
+    lex::{Lex, Span},
+};
+
+/// Replace `range` of the source with `text`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Self {
+            range,
+            text: text.into(),
+        }
+    }
+
+    /// The source after the edit.
+    pub fn apply(&self, src: &str) -> String {
+        let mut out = src.to_string();
+        out.replace_range(self.range.clone(), &self.text);
+        out
+    }
+}
+
+// Top level expressions that start at a lexer restart point, with the
+// diagnostics reported while parsing them. Nothing in a chunk depends on
+// the source before it and only its first token depends on the source
+// after it.
+#[derive(Debug, PartialEq, Clone)]
+struct Chunk<'a> {
+    start: usize,
+    exprs: Vec<Expr<'a>>,
+    diagnostics: Vec<Diagnostic<'a>>,
+    bad: bool,
+}
+
+/// A parsed programme that can be updated after an edit.
+///
+/// Only the top level expressions around the edit are lexed and parsed
+/// again. The rest are moved over from the previous parse, so typing in
+/// one function of a large script does not re-parse the others.
+///
+/// ```text
+/// let doc = Document::new(src);
+/// let edit = Edit::new(4..5, "2");
+/// let new_src = edit.apply(src);
+/// let doc = doc.reparse(&edit, &new_src);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Document<'a> {
+    src: &'a str,
+    chunks: Vec<Chunk<'a>>,
+    reused: usize,
+}
+
+impl<'a> Chunk<'a> {
+    fn new(start: usize) -> Self {
+        Self {
+            start,
+            exprs: vec![],
+            diagnostics: vec![],
+            bad: false,
+        }
+    }
+
+    fn map_spans<'b>(&self, start: usize, f: &mut impl FnMut(&Span<'a>) -> Span<'b>) -> Chunk<'b> {
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .map(|d| Diagnostic {
+                severity: d.severity,
+                code: d.code,
+                message: d.message.clone(),
+                labels: d
+                    .labels
+                    .iter()
+                    .map(|l| Label {
+                        span: f(&l.span),
+                        message: l.message.clone(),
+                        primary: l.primary,
+                    })
+                    .collect(),
+                notes: d.notes.clone(),
+                help: d.help.clone(),
+            })
+            .collect();
+        Chunk {
+            start,
+            exprs: self.exprs.iter().map(|e| e.map_spans(f)).collect(),
+            diagnostics,
+            bad: self.bad,
+        }
+    }
+}
+
+impl<'a> Document<'a> {
+    pub fn new(src: &'a str) -> Self {
+        let chunks = parse_chunks(&mut Lex::new(src), 0, |_| false);
+        Self {
+            src,
+            chunks,
+            reused: 0,
+        }
+    }
+
+    pub fn src(&self) -> &'a str {
+        self.src
+    }
+
+    /// The same programme as `parse_programme` gives for the source.
+    pub fn programme(&self) -> Programme<'a> {
+        if self.chunks.iter().any(|c| c.bad) {
+            Programme::Bad()
+        } else {
+            Programme::Good(self.chunks.iter().flat_map(|c| c.exprs.clone()).collect())
+        }
+    }
+
+    /// The diagnostics a `Lex` would have after `parse_programme`.
+    pub fn diagnostics(&self) -> Diagnostics<'a> {
+        self.chunks
+            .iter()
+            .flat_map(|c| c.diagnostics.clone())
+            .collect()
+    }
+
+    /// How many top level chunks the last `reparse` took from the
+    /// previous parse.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    /// Parse `src`, which must be this document's source with `edit`
+    /// applied.
+    pub fn reparse<'b>(&self, edit: &Edit, src: &'b str) -> Document<'b> {
+        let old_end = edit.range.end;
+        let new_end = edit.range.start + edit.text.len();
+        debug_assert_eq!(src.len(), self.src.len() - old_end + new_end);
+
+        // The chunk before the edit may have ended by looking at the
+        // first token of the chunk holding the edit, so start there.
+        let containing = self
+            .chunks
+            .partition_point(|c| c.start <= edit.range.start)
+            .saturating_sub(1);
+        let first = containing.saturating_sub(1);
+        let start = self.chunks.get(first).map_or(0, |c| c.start);
+
+        // Old offsets before the edit stay put, those after it move by the
+        // change in length.
+        let moved = |offset: usize| {
+            if offset < edit.range.start {
+                offset
+            } else {
+                offset - old_end + new_end
+            }
+        };
+        let base = self.src.as_ptr() as usize;
+        let mut rebase = |span: &Span<'a>| {
+            let offset = moved(span.as_ptr() as usize - base);
+            Span::new(&src[offset..offset + span.len()])
+        };
+
+        let mut chunks = self.chunks[..first]
+            .iter()
+            .map(|c| c.map_spans(c.start, &mut rebase))
+            .collect::<Vec<_>>();
+        let mut resume = None;
+        let parsed = parse_chunks(&mut Lex::starting_at(src, start), start, |at| {
+            // Chunks that start after the edit are unchanged if a chunk
+            // starts there in the new source too.
+            resume = (at >= new_end)
+                .then(|| {
+                    let old = at - new_end + old_end;
+                    self.chunks.binary_search_by_key(&old, |c| c.start).ok()
+                })
+                .flatten();
+            resume.is_some()
+        });
+        chunks.extend(parsed);
+        let mut reused = first;
+        if let Some(resume) = resume {
+            reused += self.chunks.len() - resume;
+            chunks.extend(
+                self.chunks[resume..]
+                    .iter()
+                    .map(|c| c.map_spans(moved(c.start), &mut rebase)),
+            );
+        }
+        Document {
+            src,
+            chunks,
+            reused,
+        }
+    }
+}
+
+// Parse chunks from `start` to the end of the source, the same way as
+// `parse_programme`, or until `resume` says that the chunk at a restart
+// point can be taken from an earlier parse.
+fn parse_chunks<'a>(
+    lex: &mut Lex<'a>,
+    start: usize,
+    mut resume: impl FnMut(usize) -> bool,
+) -> Vec<Chunk<'a>> {
+    let mut chunks = vec![];
+    let mut chunk = Chunk::new(start);
+    loop {
+        while lex.is_newline() {
+            lex.advance();
+        }
+        if lex.is_eof() {
+            break;
+        }
+        if let Some(at) = lex.restart_point().filter(|_| !chunk.exprs.is_empty()) {
+            chunk.diagnostics = lex.take_diagnostics().into_iter().collect();
+            chunks.push(std::mem::replace(&mut chunk, Chunk::new(at)));
+            if resume(at) {
+                return chunks;
+            }
+        }
+        match parse_top_level(lex) {
+            Some(expr) => chunk.exprs.push(expr),
+            None => {
+                chunk.bad = true;
+                break;
+            }
+        }
+    }
+    chunk.diagnostics = lex.take_diagnostics().into_iter().collect();
+    chunks.push(chunk);
+    chunks
+}
@@ -1,11 +1,18 @@
-use std::{fmt::Debug, ops::Deref};
+use std::{
+    fmt::Debug,
+    ops::{Deref, Range},
+};
+
+use crate::{
+    cst::SyntaxKind,
+    diag::{self, Diagnostic, Diagnostics},
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token<'a> {
     Punct(&'a str),
     Int(&'a str),
     Float(&'a str),
-    Hex(&'a str),
     Keyword(&'a str),
     Ident(&'a str),
     Str(&'a str),
@@ -17,6 +24,65 @@ pub enum Token<'a> {
     UnterminatedString(&'a str),
 }
 
+impl<'a> core::fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Newline(_) => write!(f, "newline"),
+            Token::Begin(_) => write!(f, "indented block"),
+            Token::End(_) => write!(f, "end of block"),
+            Token::Eof(_) => write!(f, "end of file"),
+            Token::UnterminatedString(_) => write!(f, "unterminated string"),
+            Token::Punct(span)
+            | Token::Int(span)
+            | Token::Float(span)
+            | Token::Keyword(span)
+            | Token::Ident(span)
+            | Token::Str(span)
+            | Token::UnknownToken(span) => write!(f, "`{span}`"),
+        }
+    }
+}
+
+impl<'a> Token<'a> {
+    /// The source text of the token, empty for the layout tokens.
+    pub fn text(&self) -> &'a str {
+        match self {
+            Token::Punct(text)
+            | Token::Int(text)
+            | Token::Float(text)
+            | Token::Keyword(text)
+            | Token::Ident(text)
+            | Token::Str(text)
+            | Token::Newline(text)
+            | Token::Begin(text)
+            | Token::End(text)
+            | Token::Eof(text)
+            | Token::UnknownToken(text)
+            | Token::UnterminatedString(text) => text,
+        }
+    }
+}
+
+/// Source text between tokens that the parser never sees.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Trivia<'a> {
+    Whitespace(&'a str),
+    Newline(&'a str),
+    LineComment(&'a str),
+    BlockComment(&'a str),
+}
+
+impl<'a> Trivia<'a> {
+    pub fn text(&self) -> &'a str {
+        match self {
+            Trivia::Whitespace(text)
+            | Trivia::Newline(text)
+            | Trivia::LineComment(text)
+            | Trivia::BlockComment(text) => text,
+        }
+    }
+}
+
 #[derive(PartialEq, Clone)]
 pub struct Span<'a>(&'a str);
 
@@ -32,6 +98,14 @@ impl<'a> std::fmt::Debug for Span<'a> {
     }
 }
 
+impl<'a> Span<'a> {
+    /// A span must be a slice of the source text to be located in a
+    /// `SourceMap`.
+    pub fn new(text: &'a str) -> Self {
+        Span(text)
+    }
+}
+
 impl<'a> Deref for Span<'a> {
     type Target = &'a str;
 
@@ -46,22 +120,81 @@ pub struct Lex<'a> {
     indent: Vec<usize>,
     pos: usize,
     peek: Token<'a>,
+    diagnostics: Diagnostics<'a>,
+    keep_trivia: bool,
+    trivia: Vec<Trivia<'a>>,
+    prev_end: usize,
+    pending_ends: usize,
+    pending_begin: bool,
+    pending_at: usize,
+    follows_block: bool,
+    brackets: Vec<(u8, usize)>,
+    prev_operand: bool,
+    closure_header: bool,
+    restart: Option<usize>,
+    consumed: Vec<(Vec<Trivia<'a>>, Token<'a>)>,
+    nodes: Vec<(SyntaxKind, Range<usize>)>,
 }
 
 impl<'a> Lex<'a> {
     pub fn new(src: &'a str) -> Self {
+        Self::with_options(src, 0, false)
+    }
+
+    /// A lexer that also collects the comments and whitespace before
+    /// each token, see `leading_trivia`. It keeps every token advanced
+    /// over and the nodes the parser finishes for a `cst::SyntaxTree`.
+    pub fn with_trivia(src: &'a str) -> Self {
+        Self::with_options(src, 0, true)
+    }
+
+    /// A lexer that starts part way through `src` at a `restart_point`
+    /// of an earlier lexer over the same text.
+    pub fn starting_at(src: &'a str, pos: usize) -> Self {
+        Self::with_options(src, pos, false)
+    }
+
+    fn with_options(src: &'a str, pos: usize, keep_trivia: bool) -> Self {
         let mut s = Self {
             src,
             indent: vec![],
-            pos: 0,
+            // Skip any byte order mark.
+            pos: if pos == 0 && src.starts_with('\u{feff}') { 3 } else { pos },
             peek: Token::UnknownToken(""),
+            diagnostics: Diagnostics::new(),
+            keep_trivia,
+            trivia: vec![],
+            prev_end: 0,
+            pending_ends: 0,
+            pending_begin: false,
+            pending_at: 0,
+            follows_block: false,
+            brackets: vec![],
+            prev_operand: false,
+            closure_header: false,
+            restart: None,
+            consumed: vec![],
+            nodes: vec![],
         };
-        s.peek = s.next();
+        s.bump();
         s
     }
 
-    pub fn error(&mut self, span: Span<'a>, text: String) {
-        eprintln!("error {text} at {span:?}");
+    /// Report an error whose primary label is `span`.
+    pub fn error(&mut self, code: &'static str, span: Span<'a>, text: String) {
+        self.report(Diagnostic::error(code, text).with_primary(span, ""));
+    }
+
+    pub fn report(&mut self, diagnostic: Diagnostic<'a>) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics<'a> {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Diagnostics<'a> {
+        std::mem::take(&mut self.diagnostics)
     }
 
     pub fn peek(&self) -> &Token<'a> {
@@ -78,32 +211,304 @@ impl<'a> Lex<'a> {
 
     pub fn advance(&mut self) -> Span<'a> {
         let span = self.span();
-        self.peek = self.next();
-        println!("{:?}", self.peek());
+        self.follows_block = matches!(self.peek, Token::End(_));
+        if self.keep_trivia {
+            let trivia = std::mem::take(&mut self.trivia);
+            self.consumed.push((trivia, self.peek.clone()));
+        }
+        self.bump();
         span
     }
 
+    /// The number of tokens advanced over, to mark the start of a node.
+    pub(crate) fn checkpoint(&self) -> usize {
+        self.consumed.len()
+    }
+
+    /// Record a syntax node holding the tokens since `checkpoint`.
+    pub(crate) fn finish_node(&mut self, kind: SyntaxKind, checkpoint: usize) {
+        if self.keep_trivia {
+            self.nodes.push((kind, checkpoint..self.consumed.len()));
+        }
+    }
+
+    /// The tokens advanced over, each with its leading trivia.
+    pub(crate) fn take_tokens(&mut self) -> Vec<(Vec<Trivia<'a>>, Token<'a>)> {
+        std::mem::take(&mut self.consumed)
+    }
+
+    /// The nodes finished, as ranges of `take_tokens`.
+    pub(crate) fn take_nodes(&mut self) -> Vec<(SyntaxKind, Range<usize>)> {
+        std::mem::take(&mut self.nodes)
+    }
+
+    /// True if the last token advanced over closed an indented block.
+    /// A block ends its line, so nothing else is needed to separate it
+    /// from what follows.
+    pub fn follows_block(&self) -> bool {
+        self.follows_block
+    }
+
+    /// Comments and whitespace between the previous token and the
+    /// current one. Always empty unless made `with_trivia`.
+    pub fn leading_trivia(&self) -> &[Trivia<'a>] {
+        &self.trivia
+    }
+
+    /// Where a new lexer could start to produce the current token and
+    /// everything after it. This is only known at the top level, outside
+    /// any block or bracket.
+    pub fn restart_point(&self) -> Option<usize> {
+        self.restart
+    }
+
+    fn bump(&mut self) {
+        let top_level = self.indent.is_empty()
+            && self.brackets.is_empty()
+            && self.pending_ends == 0
+            && !self.pending_begin
+            && !self.prev_operand;
+        self.restart = top_level.then_some(self.pos);
+        self.peek = self.next();
+        if self.keep_trivia {
+            let start = self.offset(&self.span());
+            self.trivia = split_trivia(&self.src[self.prev_end..start]);
+            self.prev_end = start + self.span().len();
+        }
+        match self.peek {
+            Token::UnknownToken(span) => {
+                self.error(diag::UNKNOWN_TOKEN, Span(span), format!("unknown token `{span}`"))
+            }
+            Token::UnterminatedString(span) => self.report(
+                Diagnostic::error(diag::UNTERMINATED_STRING, "unterminated string")
+                    .with_primary(Span(&span[..1]), "string starts here"),
+            ),
+            Token::Str(span) if is_interpolated(span) => {
+                decode_interpolated(span, &mut self.diagnostics);
+            }
+            Token::Str(span) => {
+                decode_str(span, &mut self.diagnostics);
+            }
+            _ => (),
+        }
+    }
+
     pub fn span(&self) -> Span<'a> {
-        Span(match self.peek() {
-            Token::Punct(span) |
-            Token::Int(span) |
-            Token::Float(span) |
-            Token::Hex(span) |
-            Token::Keyword(span) |
-            Token::Ident(span) |
-            Token::Str(span) |
-            Token::Newline(span) |
-            Token::Begin(span) |
-            Token::End(span) |
-            Token::Eof(span) |
-            Token::UnknownToken(span) |
-            Token::UnterminatedString(span) => span,
-        })
+        Span(self.peek.text())
+    }
+
+    /// The smallest span of the source holding all of `spans`.
+    pub fn cover(&self, spans: impl IntoIterator<Item = Span<'a>>) -> Option<Span<'a>> {
+        let (start, end) = spans
+            .into_iter()
+            .map(|s| (self.offset(&s), self.offset(&s) + s.len()))
+            .reduce(|(s0, e0), (s1, e1)| (s0.min(s1), e0.max(e1)))?;
+        Some(Span(&self.src[start..end]))
+    }
+
+    // Byte offset of a span taken from this source.
+    fn offset(&self, span: &Span<'a>) -> usize {
+        span.as_ptr() as usize - self.src.as_ptr() as usize
+    }
+
+    // Skip spaces and comments, but not newlines outside block comments.
+    fn skip_trivia(&mut self, mut pos: usize) -> usize {
+        let bytes = self.src.as_bytes();
+        loop {
+            pos = next_pos(bytes, pos, |b| !matches!(b, b' ' | b'\t' | b'\r'));
+            let rest = &bytes[pos..];
+            if rest.starts_with(b"#") || rest.starts_with(b"//") {
+                pos = next_pos(bytes, pos, |b| b == b'\n');
+            } else if rest.starts_with(b"/*") {
+                match block_comment_len(rest) {
+                    Some(len) => pos += len,
+                    None => {
+                        self.report(
+                            Diagnostic::error(diag::UNTERMINATED_COMMENT, "unterminated block comment")
+                                .with_primary(Span(&self.src[pos..pos + 2]), "comment starts here"),
+                        );
+                        pos = bytes.len();
+                    }
+                }
+            } else {
+                break pos;
+            }
+        }
+    }
+
+    fn current_indent(&self) -> usize {
+        self.indent.last().copied().unwrap_or_default()
+    }
+
+    // The width of the indentation in src[start..end], tabs are an error
+    // but count to the next multiple of eight so that we can carry on.
+    fn indent_width(&mut self, start: usize, end: usize) -> usize {
+        let indent = &self.src[start..end];
+        if let Some(tab) = indent.find('\t') {
+            let tab = start + tab;
+            self.report(
+                Diagnostic::error(diag::TAB_INDENT, "tab in indentation")
+                    .with_primary(Span(&self.src[tab..tab + 1]), "")
+                    .with_help("indent with spaces"),
+            );
+        }
+        indent
+            .bytes()
+            .fold(0, |w, b| if b == b'\t' { w / 8 * 8 + 8 } else { w + 1 })
+    }
+
+    // Scan 12, 1_000, 0x1f, 0o17, 0b101, 1.5, 1e10, 2.5e-3.
+    // A `.` must be followed by a digit to be part of a float so that
+    // 0..n and 1.max(2) work.
+    fn scan_number(&mut self, start: usize) -> Token<'a> {
+        let bytes = self.src.as_bytes();
+        let digits = |pos| next_pos(bytes, pos, |b| !b.is_ascii_digit() && b != b'_');
+        let radix = match &bytes[start..] {
+            [b'0', b'x' | b'X', ..] => 16,
+            [b'0', b'o' | b'O', ..] => 8,
+            [b'0', b'b' | b'B', ..] => 2,
+            _ => 10,
+        };
+        let mut is_float = false;
+        let mut pos = if radix == 10 {
+            digits(start)
+        } else {
+            next_pos(bytes, start + 2, |b| !b.is_ascii_hexdigit() && b != b'_')
+        };
+        if radix == 10 {
+            if bytes.get(pos) == Some(&b'.') && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit) {
+                pos = digits(pos + 1);
+                is_float = true;
+            }
+            if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+                let mut exp = pos + 1;
+                if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+                    exp += 1;
+                }
+                if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+                    pos = digits(exp);
+                    is_float = true;
+                }
+            }
+        }
+
+        // A number may not run straight into a name, as in 12abc.
+        let end = ident_end(self.src, pos);
+        self.pos = end;
+        let text = &self.src[start..end];
+        let kind = match radix {
+            16 => "hexadecimal",
+            8 => "octal",
+            2 => "binary",
+            _ => "number",
+        };
+        let body = &self.src[start + if radix == 10 { 0 } else { 2 }..pos];
+        if end > pos {
+            let msg = format!("invalid suffix `{}` on {kind} literal", &self.src[pos..end]);
+            self.error(diag::INVALID_NUMBER, Span(&self.src[pos..end]), msg);
+        } else if let Some((i, c)) = body
+            .char_indices()
+            .find(|(_, c)| radix != 10 && *c != '_' && !c.is_digit(radix))
+        {
+            let at = pos - body.len() + i;
+            let msg = format!("invalid digit `{c}` in {kind} literal");
+            self.error(diag::INVALID_NUMBER, Span(&self.src[at..at + 1]), msg);
+        } else if !body.bytes().any(|b| b != b'_') {
+            let msg = format!("{kind} literal has no digits");
+            self.error(diag::INVALID_NUMBER, Span(text), msg);
+        }
+        if is_float {
+            Token::Float(text)
+        } else {
+            Token::Int(text)
+        }
     }
 
+    // Scan a string whose prefix (r, b, br) is src[start..body].
+    // "x", 'x', r"x", r#"x"#, b"x", """x"""
+    fn scan_string(&mut self, start: usize, body: usize) -> Token<'a> {
+        let bytes = self.src.as_bytes();
+        let raw = self.src[start..body].contains('r');
+        let hashes = next_pos(bytes, body, |b| b != b'#') - body;
+        let mut pos = body + hashes;
+        let quote = bytes[pos];
+        let delim = if bytes[pos..].starts_with(&[quote; 3]) { 3 } else { 1 };
+        pos += delim;
+        let end = loop {
+            match bytes.get(pos) {
+                None => break None,
+                Some(b'\\') if !raw => pos = (pos + 2).min(bytes.len()),
+                Some(b'\n') if delim == 1 => break None,
+                Some(&b) if b == quote
+                    && bytes[pos..].starts_with(&[quote; 3][..delim])
+                    && next_pos(bytes, pos + delim, |b| b != b'#') - (pos + delim) >= hashes =>
+                {
+                    break Some(pos + delim + hashes)
+                }
+                _ => pos += 1,
+            }
+        };
+        match end {
+            Some(end) => {
+                self.pos = end;
+                Token::Str(&self.src[start..end])
+            }
+            None => {
+                self.pos = pos;
+                Token::UnterminatedString(&self.src[start..pos])
+            }
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Token<'a> {
+        let token = self.scan();
+        self.closure_header = false;
+        match token {
+            Token::Punct(p @ ("(" | "[" | "{")) => self.brackets.push((p.as_bytes()[0], self.indent.len())),
+            Token::Punct(p @ (")" | "]" | "}")) => {
+                if matches!(self.brackets.last(), Some((open, _)) if closes(*open, p.as_bytes()[0])) {
+                    self.brackets.pop();
+                }
+            }
+            Token::Punct("|") => {
+                if matches!(self.brackets.last(), Some((b'|', _))) {
+                    self.brackets.pop();
+                    self.closure_header = true;
+                } else if !self.prev_operand {
+                    self.brackets.push((b'|', self.indent.len()));
+                }
+            }
+            _ => (),
+        }
+        self.prev_operand = matches!(
+            token,
+            Token::Int(_)
+                | Token::Float(_)
+                | Token::Ident(_)
+                | Token::Str(_)
+                | Token::Punct(")" | "]" | "}")
+        );
+        token
+    }
+
+    // Newlines are ignored inside brackets unless a block has been opened
+    // since the bracket.
+    fn in_brackets(&self) -> bool {
+        matches!(self.brackets.last(), Some((_, depth)) if *depth == self.indent.len())
+    }
+
+    fn scan(&mut self) -> Token<'a> {
+        if self.pending_ends != 0 {
+            self.pending_ends -= 1;
+            return Token::End(&self.src[self.pending_at..self.pending_at]);
+        }
+        if self.pending_begin {
+            self.pending_begin = false;
+            return Token::Begin(&self.src[self.pending_at..self.pending_at]);
+        }
         let bytes = self.src.as_bytes();
-        let mut pos = next_pos(bytes, self.pos, |b| b != b' ');
+        let mut pos = self.skip_trivia(self.pos);
         let start = pos;
         if pos == bytes.len() {
             self.pos = pos;
@@ -116,107 +521,130 @@ impl<'a> Lex<'a> {
         } else {
             self.pos = pos + 1;
             match bytes[pos] {
-                b if b.is_ascii_digit() => {
-                    if bytes[pos..].starts_with(b"0x") {
-                        self.pos = next_pos(bytes, pos+2, |b| !b.is_ascii_hexdigit());
-                        Token::Hex(&self.src[start..self.pos])
-                    } else {
-                        pos = next_pos(bytes, pos+1, |b| !b.is_ascii_digit());
-                        let mut is_float = false;
-                        if bytes.get(pos) == Some(&b'.') {
-                            pos = next_pos(bytes, pos+1, |b| !b.is_ascii_digit());
-                            is_float = true;
-                        }
-                        if bytes.get(pos).map(u8::to_ascii_lowercase) == Some(b'e') {
-                            is_float = true;
-                            if bytes.get(pos) == Some(&b'+') || bytes.get(pos) == Some(&b'-') {
-                                pos += 1;
-                            }
-                            pos = next_pos(bytes, pos+1, |b| !b.is_ascii_digit());
-                        }
-                        self.pos = pos;
-                        if is_float {
-                            Token::Float(&self.src[start..pos])
-                        } else {
-                            Token::Int(&self.src[start..pos])
-                        }
-                    }
+                b if b.is_ascii_digit() => self.scan_number(start),
+                b'r' | b'b' if string_prefix(&bytes[pos..]).is_some() => {
+                    let len = string_prefix(&bytes[pos..]).unwrap_or_default();
+                    self.scan_string(start, start + len)
                 }
-                b if b.is_ascii_alphabetic() => {
-                    pos = next_pos(bytes, pos+1, |b| !b.is_ascii_alphanumeric() && b != b'_');
+                _ if self.src[pos..].starts_with(is_ident_start) => {
+                    pos = ident_end(self.src, pos);
                     self.pos = pos;
                     let span = &self.src[start..pos];
-                    let is_keyword = match span {
-                        "fn" => true,
-                        "if" => true,
-                        "else" => true,
-                        "for" => true,
-                        "let" => true,
-                        "mut" => true,
-                        _ => false,
-                    };
-                    // eprintln!("k({span}, {is_keyword})");
+                    let is_keyword = matches!(
+                        span,
+                        "fn" | "if"
+                            | "elif"
+                            | "else"
+                            | "for"
+                            | "in"
+                            | "while"
+                            | "break"
+                            | "continue"
+                            | "return"
+                            | "let"
+                            | "mut"
+                            | "match"
+                            | "struct"
+                            | "enum"
+                            | "impl"
+                            | "trait"
+                            | "and"
+                            | "or"
+                    );
                     if is_keyword {
                         Token::Keyword(span)
                     } else {
                         Token::Ident(span)
                     }
                 }
-                b'"'| b'\'' => {
-                    let terminator = bytes[pos];
-                    println!("t={:02x}", terminator);
-                    if bytes[pos+1] == terminator {
-                        self.pos += 2;
-                        Token::Str(&self.src[start..self.pos])
-                    } else if let Some(pos) = bytes[pos+1..].windows(2).position(|s| s[0] != b'\\' && s[1] == terminator) {
-                        println!("pos={pos}");
-                        self.pos = self.pos + pos + 2;
-                        Token::Str(&self.src[start..self.pos])
-                    } else {
-                        self.pos = bytes.len();
-                        Token::UnterminatedString(&self.src[start..self.pos])
-                    }
-                }
+                b'"'| b'\'' => self.scan_string(start, start),
 
                 b'\n' => {
-                    pos += 1;
-                    let start = pos;
-                    pos = next_pos(bytes, pos, |b| b != b' ');
-                    let new_indent = pos - start;
-                    let old_indent = self.indent.last().copied().unwrap_or_default();
-                    eprintln!("{:?} old={old_indent} new={new_indent}", self.indent);
-                    if pos == bytes.len() {
-                        self.pos = pos;
-                        Token::Newline(&self.src[pos..pos])
-                    } else if bytes[pos] == b'\n' {
-                        Token::Newline(&self.src[pos-1..pos])
-                    } else {
-                        self.pos = pos;
-                        if new_indent > old_indent {
-                            self.indent.push(new_indent);
-                            Token::Begin(&self.src[start..start])
-                        } else if new_indent < old_indent {
-                            self.indent.pop();
-                            Token::End(&self.src[start..start])
+                    // Blank and comment only lines are skipped so that
+                    // they never change the indentation.
+                    let mut start = pos + 1;
+                    let indent_end = loop {
+                        let indent_end = next_pos(bytes, start, |b| b != b' ' && b != b'\t');
+                        pos = self.skip_trivia(indent_end);
+                        if bytes.get(pos) == Some(&b'\n') {
+                            start = pos + 1;
                         } else {
-                            Token::Newline(&self.src[start..start])
+                            break indent_end;
                         }
+                    };
+                    self.pos = pos;
+                    let new_indent = self.indent_width(start, indent_end);
+                    let old_indent = self.current_indent();
+                    if self.in_brackets() {
+                        // Only an indented closure body breaks the line
+                        // joining inside brackets.
+                        if pos == bytes.len() || !self.closure_header || new_indent <= old_indent {
+                            return self.scan();
+                        }
+                    } else if pos == bytes.len() {
+                        return Token::Newline(&self.src[pos..pos]);
                     }
+                    if new_indent > old_indent {
+                        self.indent.push(new_indent);
+                        Token::Begin(&self.src[start..start])
+                    } else if new_indent < old_indent {
+                        // Close every block deeper than this line, one End
+                        // each, but not the bracket the blocks are inside.
+                        let floor = self.brackets.last().map_or(0, |(_, depth)| *depth);
+                        self.indent.pop();
+                        while self.indent.len() > floor && self.current_indent() > new_indent {
+                            self.indent.pop();
+                            self.pending_ends += 1;
+                        }
+                        if !self.in_brackets() && self.current_indent() < new_indent {
+                            self.report(
+                                Diagnostic::error(
+                                    diag::INCONSISTENT_DEDENT,
+                                    "unindent does not match any outer indentation level",
+                                )
+                                .with_primary(Span(&self.src[start..indent_end]), "")
+                                .with_note(format!(
+                                    "the enclosing block is indented by {} spaces",
+                                    self.current_indent()
+                                )),
+                            );
+                            // Carry on as if the line started a new block.
+                            self.indent.push(new_indent);
+                            self.pending_begin = true;
+                        }
+                        self.pending_at = start;
+                        Token::End(&self.src[start..start])
+                    } else {
+                        Token::Newline(&self.src[start..start])
+                    }
+                }
+
+                // A closing bracket ends any blocks opened inside it.
+                b @ (b')' | b']' | b'}' | b'|')
+                    if matches!(
+                        self.brackets.last(),
+                        Some((open, depth)) if closes(*open, b) && *depth < self.indent.len()
+                    ) =>
+                {
+                    self.pos = pos;
+                    self.indent.pop();
+                    Token::End(&self.src[pos..pos])
                 }
 
                 _ => {
                     let bp = &bytes[pos..];
-                    const PUNCT : &'static [&'static [u8]] = &[
-                        b">>>", 
-                        b"**", b"<<", b">>", b"+=", b"-=", b"*=", b"/=", b"%=", b"==", b"!=", b"<=", b">=",
-                        b"|", b"&", b"^", b"<", b">", b"!", b"+", b"-", b"*", b"/", b"%", b"=", b"[", b"]", b"(", b")", b":", b",", b";", b".",
+                    const PUNCT : &[&[u8]] = &[
+                        b">>>",
+                        b"**", b"..", b"<<", b">>", b"+=", b"-=", b"*=", b"/=", b"%=", b"==", b"=>", b"!=", b"<=", b">=",
+                        b"|", b"&", b"^", b"<", b">", b"!", b"+", b"-", b"*", b"/", b"%", b"=", b"[", b"]", b"(", b")", b"{", b"}", b":", b",", b";", b".",
                     ];
             
                     if let Some(p) = PUNCT.iter().find(|p| bp.starts_with(p)) {
                         self.pos = pos + p.len();
                         Token::Punct(&self.src[start..self.pos])
                     } else {
-                        self.pos = pos + 1;
+                        let len = self.src[pos..].chars().next().map_or(1, char::len_utf8);
+                        self.pos = pos + len;
                         Token::UnknownToken(&self.src[start..self.pos])
                     }
                 }
@@ -228,3 +656,352 @@ impl<'a> Lex<'a> {
 fn next_pos<F : Fn(u8) -> bool>(bytes: &[u8], pos: usize, pred: F) -> usize {
     pos + bytes[pos..].iter().position(|&b| pred(b)).unwrap_or(bytes[pos..].len())
 }
+
+// The length of a string prefix such as r, b, br or r## if a string
+// follows it.
+fn string_prefix(bytes: &[u8]) -> Option<usize> {
+    let letters = bytes.iter().take_while(|b| matches!(b, b'r' | b'b')).count();
+    let prefix = &bytes[..letters];
+    if !matches!(prefix, b"r" | b"b" | b"br" | b"rb") {
+        return None;
+    }
+    let hashes = if prefix.contains(&b'r') {
+        bytes[letters..].iter().take_while(|&&b| b == b'#').count()
+    } else {
+        0
+    };
+    matches!(bytes.get(letters + hashes), Some(b'"' | b'\'')).then_some(letters)
+}
+
+/// The value of a string literal.
+#[derive(Debug, PartialEq, Clone)]
+pub enum StrValue {
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// Decode the text of a `Token::Str`, reporting bad escapes.
+///
+/// Triple quoted strings drop the newline after the opening quotes and,
+/// when the closing quotes are on a line of their own, that line's
+/// indentation from every line:
+///
+/// ```text
+/// x = """
+///     hello
+///       world
+///     """
+/// ```
+///
+/// is `"hello\n  world"`.
+pub fn decode_str<'a>(text: &'a str, diagnostics: &mut Diagnostics<'a>) -> StrValue {
+    let (fragments, is_bytes) = decode(text, false, diagnostics);
+    let out = match fragments.into_iter().next() {
+        Some(Piece::Text(out)) => out,
+        _ => vec![],
+    };
+    if is_bytes {
+        StrValue::Bytes(out)
+    } else {
+        StrValue::Str(String::from_utf8(out).unwrap_or_default())
+    }
+}
+
+/// A piece of an interpolated string, `"a{b:>4}c"` is `Text("a")`,
+/// `Placeholder("b", Some(">4"))` and `Text("c")`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Fragment<'a> {
+    Text(String),
+    Placeholder(&'a str, Option<&'a str>),
+}
+
+/// Plain and triple quoted strings with a `{..}` placeholder in them are
+/// interpolated, raw and byte strings never are. `{{` does not start a
+/// placeholder, so a string with only doubled or lone braces keeps them
+/// as they are.
+pub fn is_interpolated(text: &str) -> bool {
+    if text.find(['"', '\'', '#']).unwrap_or(0) != 0 {
+        return false;
+    }
+    let mut rest = text;
+    while let Some(i) = rest.find('{') {
+        rest = &rest[i + 1..];
+        match rest.strip_prefix('{') {
+            Some(after) => rest = after,
+            None if placeholder_len(rest).is_some() => return true,
+            None => (),
+        }
+    }
+    false
+}
+
+/// Decode an interpolated string, `{{` and `}}` are literal braces.
+/// Only strings `is_interpolated` picks out are decoded this way.
+pub fn decode_interpolated<'a>(text: &'a str, diagnostics: &mut Diagnostics<'a>) -> Vec<Fragment<'a>> {
+    decode(text, true, diagnostics)
+        .0
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(out) => Fragment::Text(String::from_utf8(out).unwrap_or_default()),
+            Piece::Placeholder(expr, spec) => Fragment::Placeholder(expr, spec),
+        })
+        .collect()
+}
+
+enum Piece<'a> {
+    Text(Vec<u8>),
+    Placeholder(&'a str, Option<&'a str>),
+}
+
+fn decode<'a>(text: &'a str, interpolate: bool, diagnostics: &mut Diagnostics<'a>) -> (Vec<Piece<'a>>, bool) {
+    let letters = text.find(['"', '\'', '#']).unwrap_or(0);
+    let raw = text[..letters].contains('r');
+    let is_bytes = text[..letters].contains('b');
+    let hashes = text[letters..].bytes().take_while(|&b| b == b'#').count();
+    let quoted = &text[letters + hashes..text.len() - hashes];
+    let quote = &quoted[..1];
+    let delim = if quoted.len() >= 6 && quoted.starts_with(&quote.repeat(3)) { 3 } else { 1 };
+    let mut body = &quoted[delim..quoted.len() - delim];
+
+    // How much indentation to strip from each line.
+    let mut strip = 0;
+    if delim == 3 {
+        body = body.strip_prefix('\n').unwrap_or(body);
+        if let Some(nl) = body.rfind('\n') {
+            if body[nl + 1..].bytes().all(|b| b == b' ' || b == b'\t') {
+                strip = body.len() - nl - 1;
+                body = &body[..nl];
+            }
+        }
+    }
+
+    let mut error = |code, span: &'a str, message: &str| {
+        diagnostics.push(Diagnostic::error(code, message).with_primary(Span(span), ""));
+    };
+
+    let mut pieces = vec![];
+    let mut out = vec![];
+    let mut chars = body.char_indices().peekable();
+    let mut line_start = true;
+    loop {
+        if line_start {
+            for _ in 0..strip {
+                if chars.next_if(|(_, c)| *c == ' ' || *c == '\t').is_none() {
+                    break;
+                }
+            }
+            line_start = false;
+        }
+        let Some((i, c)) = chars.next() else {
+            break;
+        };
+        let c = match c {
+            '\\' if !raw => {
+                let Some((_, e)) = chars.next() else {
+                    error(diag::INVALID_ESCAPE, &body[i..], "unterminated escape");
+                    break;
+                };
+                match e {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    '\\' | '\'' | '"' => e,
+                    '\n' => {
+                        // A backslash at the end of a line joins it to the next.
+                        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                        continue;
+                    }
+                    'x' => {
+                        let end = body[i + 2..]
+                            .char_indices()
+                            .nth(2)
+                            .map_or(body.len(), |(j, _)| i + 2 + j);
+                        let digits = &body[i + 2..end];
+                        while chars.next_if(|(j, _)| *j < end).is_some() {}
+                        match u8::from_str_radix(digits, 16) {
+                            Ok(b) if digits.len() == 2 && (is_bytes || b < 0x80) => {
+                                out.push(b);
+                                continue;
+                            }
+                            Ok(_) if digits.len() == 2 => {
+                                let msg = "hex escape must be at most \\x7f in a string";
+                                error(diag::INVALID_ESCAPE, &body[i..end], msg);
+                            }
+                            _ => {
+                                let msg = "hex escape needs two hex digits";
+                                error(diag::INVALID_ESCAPE, &body[i..end], msg);
+                            }
+                        }
+                        continue;
+                    }
+                    'u' if !is_bytes => {
+                        // \\u{...} with at most six digits.
+                        let end = Some(&body[i + 2..])
+                            .filter(|s| s.starts_with('{'))
+                            .and_then(|s| s.char_indices().take(8).find(|(_, c)| *c == '}'))
+                            .map_or(i + 2, |(j, _)| i + 2 + j + 1);
+                        while chars.next_if(|(j, _)| *j < end).is_some() {}
+                        let value = body[i..end]
+                            .strip_prefix("\\u{")
+                            .and_then(|s| s.strip_suffix('}'))
+                            .filter(|s| (1..=6).contains(&s.len()))
+                            .and_then(|s| u32::from_str_radix(s, 16).ok())
+                            .and_then(char::from_u32);
+                        match value {
+                            Some(c) => c,
+                            None => {
+                                error(diag::INVALID_ESCAPE, &body[i..end], "invalid unicode escape");
+                                continue;
+                            }
+                        }
+                    }
+                    _ => {
+                        let len = 1 + e.len_utf8();
+                        error(diag::INVALID_ESCAPE, &body[i..i + len], "unknown escape");
+                        continue;
+                    }
+                }
+            }
+            '\n' => {
+                line_start = true;
+                c
+            }
+            '{' if interpolate && chars.next_if(|(_, c)| *c == '{').is_some() => c,
+            '}' if interpolate && chars.next_if(|(_, c)| *c == '}').is_some() => c,
+            '{' if interpolate => {
+                let Some((len, colon)) = placeholder_len(&body[i + 1..]) else {
+                    error(diag::UNTERMINATED_PLACEHOLDER, &body[i..i + 1], "unterminated `{` in string");
+                    break;
+                };
+                let inner = &body[i + 1..i + 1 + len];
+                let (expr, spec) = match colon {
+                    Some(colon) => (&inner[..colon], Some(&inner[colon + 1..])),
+                    None => (inner, None),
+                };
+                if expr.trim().is_empty() {
+                    error(diag::EMPTY_PLACEHOLDER, &body[i..i + len + 2], "empty `{}` in string");
+                }
+                if !out.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut out)));
+                }
+                pieces.push(Piece::Placeholder(expr, spec));
+                while chars.next_if(|(j, _)| *j <= i + 1 + len).is_some() {}
+                continue;
+            }
+            '}' if interpolate => {
+                let msg = "unmatched `}` in string, use `}}` for a brace";
+                error(diag::UNMATCHED_BRACE, &body[i..i + 1], msg);
+                continue;
+            }
+            c if is_bytes && !c.is_ascii() => {
+                let msg = "non-ASCII character in byte string";
+                error(diag::NON_ASCII_BYTES, &body[i..i + c.len_utf8()], msg);
+                continue;
+            }
+            c => c,
+        };
+        let mut buf = [0; 4];
+        out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    if !out.is_empty() || pieces.is_empty() {
+        pieces.push(Piece::Text(out));
+    }
+    (pieces, is_bytes)
+}
+
+// The length of the expression and format spec in a placeholder up to its
+// closing brace and the offset of the `:` before the format spec.
+fn placeholder_len(text: &str) -> Option<(usize, Option<usize>)> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut colon = None;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' => depth -= 1,
+            b'}' if depth == 0 => return Some((pos, colon)),
+            b'}' => depth -= 1,
+            b':' if depth == 0 && colon.is_none() => colon = Some(pos),
+            q @ (b'"' | b'\'') => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != q {
+                    pos += if bytes[pos] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'\n' => return None,
+            _ => (),
+        }
+        pos += 1;
+    }
+    None
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c)
+}
+
+// The end of the identifier characters starting at pos.
+fn ident_end(src: &str, pos: usize) -> usize {
+    src[pos..]
+        .find(|c| !unicode_ident::is_xid_continue(c))
+        .map_or(src.len(), |len| pos + len)
+}
+
+fn closes(open: u8, close: u8) -> bool {
+    matches!((open, close), (b'(', b')') | (b'[', b']') | (b'{', b'}') | (b'|', b'|'))
+}
+
+// Length of a possibly nested /* */ comment at the start of `bytes`.
+fn block_comment_len(bytes: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut pos = 0;
+    while pos + 1 < bytes.len() {
+        match &bytes[pos..pos + 2] {
+            b"/*" => {
+                depth += 1;
+                pos += 2;
+            }
+            b"*/" => {
+                depth -= 1;
+                pos += 2;
+                if depth == 0 {
+                    return Some(pos);
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+/// Split the text between two tokens into comments, whitespace and newlines.
+pub fn split_trivia(text: &str) -> Vec<Trivia<'_>> {
+    let bytes = text.as_bytes();
+    let mut trivia = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        let end = if rest.starts_with(b"#") || rest.starts_with(b"//") {
+            next_pos(bytes, pos, |b| b == b'\n')
+        } else if rest.starts_with(b"/*") {
+            pos + block_comment_len(rest).unwrap_or(rest.len())
+        } else if rest[0] == b'\n' {
+            pos + 1
+        } else {
+            next_pos(bytes, pos, |b| matches!(b, b'\n' | b'#' | b'/'))
+                .max(pos + 1)
+        };
+        let piece = &text[pos..end];
+        trivia.push(match rest[0] {
+            b'\n' => Trivia::Newline(piece),
+            b'#' => Trivia::LineComment(piece),
+            b'/' if rest.starts_with(b"//") => Trivia::LineComment(piece),
+            b'/' if rest.starts_with(b"/*") => Trivia::BlockComment(piece),
+            _ => Trivia::Whitespace(piece),
+        });
+        pos = end;
+    }
+    trivia
+}
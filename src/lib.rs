@@ -1,15 +1,82 @@
-
-pub mod runtime;
 pub mod ast;
+pub mod compile;
+pub mod cst;
+pub mod diag;
+pub mod incremental;
 pub mod lex;
+pub mod literal;
+pub mod render;
+pub mod runtime;
+pub mod source;
+pub mod vm;
 
+use crate::{
+    ast::parse_programme, compile::Compiler, lex::Lex, render::Renderer, runtime::Variant,
+    source::SourceMap, vm::Vm,
+};
+
+/// Why a programme could not be compiled or stopped running.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Error {
+    pub message: String,
+}
 
+impl Error {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
 
-#[derive(Debug, PartialEq, Clone)]
+impl std::error::Error for Error {}
+
+/// Compiles and runs programmes, keeping their globals between runs.
+///
+/// ```text
+/// let mut sqwipt = Sqwipt::new();
+/// sqwipt.run("x = 20")?;
+/// assert_eq!(sqwipt.run("x + 1")?, 21.into());
+/// ```
+#[derive(Debug, Clone, Default)]
 pub struct Sqwipt {
+    vm: Vm,
+}
+
+impl Sqwipt {
+    pub fn new() -> Self {
+        Self { vm: Vm::new() }
+    }
+
+    pub fn vm(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
 
+    /// Run a programme, giving the value of its last expression. Compile
+    /// errors are rendered into the error's message.
+    pub fn run(&mut self, src: &str) -> Result<Variant, Error> {
+        let mut lex = Lex::new(src);
+        let programme = parse_programme(&mut lex);
+        let mut diagnostics = lex.take_diagnostics();
+        let mut compiler = Compiler::new();
+        compiler.add_enums(self.vm.enums());
+        let main = compiler.compile(&programme);
+        for d in compiler.take_diagnostics().take() {
+            diagnostics.push(d);
+        }
+        match main {
+            Some(main) if !diagnostics.has_errors() => self.vm.run(main),
+            _ => {
+                let mut map = SourceMap::new();
+                map.add("main.sqw", src);
+                Err(Error::new(Renderer::new(&map).render_all(&diagnostics)))
+            }
+        }
+    }
 }
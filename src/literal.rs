@@ -0,0 +1,87 @@
+use crate::{
+    ast::Expr,
+    diag::{self, Diagnostic, Diagnostics},
+    runtime::Variant,
+};
+
+/// The value of an `Int` or `Float` literal.
+///
+/// Integers too big for an i128 are promoted to a `BigInt`, floats too
+/// big for an f64 are reported.
+pub fn literal_value<'a>(expr: &Expr<'a>, diagnostics: &mut Diagnostics<'a>) -> Option<Variant> {
+    match expr {
+        Expr::Int(span) => Some(int_value(span)),
+        Expr::Float(span) => {
+            // The lexer has already reported malformed numbers.
+            let value = span.replace('_', "").parse::<f64>().unwrap_or(f64::NAN);
+            if value.is_infinite() {
+                diagnostics.push(
+                    Diagnostic::error(diag::FLOAT_OUT_OF_RANGE, "float literal is out of range")
+                        .with_primary(span.clone(), "")
+                        .with_note(format!("the largest float is {:e}", f64::MAX)),
+                );
+            }
+            Some(value.into())
+        }
+        _ => None,
+    }
+}
+
+/// Check the value of every number literal in a programme.
+pub fn check_literals<'a>(exprs: &[Expr<'a>], diagnostics: &mut Diagnostics<'a>) {
+    for expr in exprs {
+        expr.walk(&mut |e| {
+            literal_value(e, diagnostics);
+        });
+    }
+}
+
+fn int_value(text: &str) -> Variant {
+    let (radix, digits) = match text.get(..2) {
+        Some("0x" | "0X") => (16, &text[2..]),
+        Some("0o" | "0O") => (8, &text[2..]),
+        Some("0b" | "0B") => (2, &text[2..]),
+        _ => (10, text),
+    };
+    // Accumulate in an i128 while it fits and then in bytes.
+    let mut value: i128 = 0;
+    let mut big: Option<Vec<u8>> = None;
+    for digit in digits.chars().filter_map(|c| c.to_digit(radix)) {
+        if let Some(bytes) = &mut big {
+            mul_add(bytes, radix, digit);
+        } else if let Some(v) = value
+            .checked_mul(radix as i128)
+            .and_then(|v| v.checked_add(digit as i128))
+        {
+            value = v;
+        } else {
+            let mut bytes = value.to_le_bytes().to_vec();
+            mul_add(&mut bytes, radix, digit);
+            big = Some(bytes);
+        }
+    }
+    match big {
+        Some(mut bytes) => {
+            // Keep the sign bit clear, the literal is positive.
+            if bytes.last().is_some_and(|b| b & 0x80 != 0) {
+                bytes.push(0);
+            }
+            Variant::big_int(&bytes)
+        }
+        None => value.into(),
+    }
+}
+
+// bytes = bytes * radix + digit, little endian.
+fn mul_add(bytes: &mut Vec<u8>, radix: u32, digit: u32) {
+    let mut carry = digit;
+    for b in bytes.iter_mut() {
+        let v = *b as u32 * radix + carry;
+        *b = v as u8;
+        carry = v >> 8;
+    }
+    while carry != 0 {
+        bytes.push(carry as u8);
+        carry >>= 8;
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt::Write;
+
+use crate::{
+    diag::{Diagnostic, Diagnostics, Label, Severity},
+    source::{FileId, Location, SourceMap},
+};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics as rustc-style reports with source snippets.
+///
+/// ```text
+/// error[E0102]: expected `)`, found end of file
+///  --> main.sqw:1:4
+///   |
+/// 1 | f(1
+///   |  - ^
+///   |  |
+///   |  unclosed `(`
+/// ```
+pub struct Renderer<'m, 'a> {
+    map: &'m SourceMap<'a>,
+    colour: bool,
+}
+
+impl<'m, 'a> Renderer<'m, 'a> {
+    pub fn new(map: &'m SourceMap<'a>) -> Self {
+        Self { map, colour: false }
+    }
+
+    /// Use ANSI escapes for colour.
+    pub fn colour(mut self, colour: bool) -> Self {
+        self.colour = colour;
+        self
+    }
+
+    pub fn render_all(&self, diagnostics: &Diagnostics<'a>) -> String {
+        diagnostics
+            .iter()
+            .map(|d| self.render(d))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn render(&self, d: &Diagnostic<'a>) -> String {
+        let mut out = String::new();
+        let (name, colour) = match d.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+            Severity::Note => ("note", GREEN),
+        };
+        let head = format!("{name}[{}]", d.code);
+        let message = format!(": {}", d.message);
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(colour, &head),
+            self.paint(BOLD, &message)
+        )
+        .unwrap();
+
+        let mut labels = d
+            .labels
+            .iter()
+            .filter_map(|l| Some((self.map.locate(&l.span)?, l)))
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|(loc, l)| (!l.primary, loc.file, loc.line, loc.column));
+        let width = labels
+            .iter()
+            .map(|(loc, _)| loc.line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let pad = " ".repeat(width);
+
+        if let Some((loc, _)) = labels.first() {
+            let file = loc.file;
+            writeln!(
+                out,
+                "{pad}{} {}",
+                self.paint(BLUE, "-->"),
+                self.position(loc)
+            )
+            .unwrap();
+            self.snippet(&mut out, &pad, file, &labels, colour);
+            // Labels in other files get their own header and snippet.
+            let mut others = labels
+                .iter()
+                .map(|(loc, _)| loc.file)
+                .filter(|f| *f != file)
+                .collect::<Vec<_>>();
+            others.dedup();
+            for other in others {
+                let (loc, _) = labels.iter().find(|(loc, _)| loc.file == other).unwrap();
+                writeln!(
+                    out,
+                    "{pad}{} {}",
+                    self.paint(BLUE, ":::"),
+                    self.position(loc)
+                )
+                .unwrap();
+                self.snippet(&mut out, &pad, other, &labels, colour);
+            }
+        }
+
+        if !d.notes.is_empty() || !d.help.is_empty() {
+            writeln!(out, "{pad} {}", self.paint(BLUE, "|")).unwrap();
+        }
+        for (kind, text) in d
+            .notes
+            .iter()
+            .map(|n| ("note", n))
+            .chain(d.help.iter().map(|h| ("help", h)))
+        {
+            writeln!(
+                out,
+                "{pad} {} {}: {text}",
+                self.paint(BLUE, "="),
+                self.paint(BOLD, kind)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    fn position(&self, loc: &Location) -> String {
+        let name = self.map.file(loc.file).name();
+        format!("{name}:{}:{}", loc.line, loc.column)
+    }
+
+    // Print the source lines of `file` that carry labels with underlines
+    // and label messages beneath them.
+    fn snippet(
+        &self,
+        out: &mut String,
+        pad: &str,
+        file: FileId,
+        labels: &[(Location, &Label<'a>)],
+        colour: &str,
+    ) {
+        let source = self.map.file(file);
+        let mut lines = labels
+            .iter()
+            .filter(|(loc, _)| loc.file == file)
+            .map(|(loc, _)| loc.line)
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.dedup();
+
+        let gutter = self.paint(BLUE, "|");
+        writeln!(out, "{pad} {gutter}").unwrap();
+        let mut prev = None;
+        for line in lines {
+            if matches!(prev, Some(p) if line > p + 1) {
+                writeln!(out, "{pad}{}", self.paint(BLUE, "...")).unwrap();
+            }
+            prev = Some(line);
+            let text = source.line(line).unwrap_or_default();
+            let number = format!("{line:>w$} |", w = pad.len());
+            let sep = if text.is_empty() { "" } else { " " };
+            writeln!(out, "{}{sep}{text}", self.paint(BLUE, &number)).unwrap();
+
+            // (start column, width, primary, message), all 0-based in chars.
+            let mut marks = labels
+                .iter()
+                .filter(|(loc, _)| loc.file == file && loc.line == line)
+                .map(|(loc, l)| {
+                    let start = loc.column - 1;
+                    let end = if loc.end_line == line {
+                        loc.end_column - 1
+                    } else {
+                        text.chars().count()
+                    };
+                    (
+                        start,
+                        end.saturating_sub(start).max(1),
+                        l.primary,
+                        l.message.as_str(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            marks.sort_by_key(|m| m.0);
+
+            let mut underline = String::new();
+            let mut col = 0;
+            for &(start, len, primary, _) in &marks {
+                if start < col {
+                    continue;
+                }
+                underline.push_str(&" ".repeat(start - col));
+                let (c, paint) = if primary { ("^", colour) } else { ("-", BLUE) };
+                underline.push_str(&self.paint(paint, &c.repeat(len)));
+                col = start + len;
+            }
+            let mut pending = marks.iter().filter(|m| !m.3.is_empty()).collect::<Vec<_>>();
+            // The rightmost message goes on the underline itself.
+            if let Some(last) = pending.last() {
+                if std::ptr::eq(*last, marks.last().unwrap()) {
+                    underline.push(' ');
+                    underline.push_str(&self.paint_label(last, colour));
+                    pending.pop();
+                }
+            }
+            writeln!(out, "{pad} {gutter} {underline}").unwrap();
+
+            while let Some(last) = pending.pop() {
+                let mut connectors = String::new();
+                let mut col = 0;
+                for m in pending.iter().chain(Some(&last)) {
+                    // Labels starting in the same column share a line.
+                    if m.0 < col {
+                        continue;
+                    }
+                    connectors.push_str(&" ".repeat(m.0 - col));
+                    connectors.push_str(&self.paint(BLUE, "|"));
+                    col = m.0 + 1;
+                }
+                writeln!(out, "{pad} {gutter} {connectors}").unwrap();
+                let mut row = String::new();
+                let mut col = 0;
+                for m in pending.iter().filter(|m| m.0 < last.0) {
+                    if m.0 < col {
+                        continue;
+                    }
+                    row.push_str(&" ".repeat(m.0 - col));
+                    row.push_str(&self.paint(BLUE, "|"));
+                    col = m.0 + 1;
+                }
+                row.push_str(&" ".repeat(last.0 - col));
+                row.push_str(&self.paint_label(last, colour));
+                writeln!(out, "{pad} {gutter} {row}").unwrap();
+            }
+        }
+    }
+
+    fn paint_label(&self, mark: &(usize, usize, bool, &str), colour: &str) -> String {
+        self.paint(if mark.2 { colour } else { BLUE }, mark.3)
+    }
+
+    fn paint(&self, colour: &str, text: &str) -> String {
+        if self.colour {
+            format!("{colour}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -1,62 +1,627 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{hash_map, HashMap},
+    rc::Rc,
+};
+
+use crate::{ast::Align, lex::StrValue, vm::Vm, Error};
 
 pub type Ref<T> = Rc<T>;
 pub type Mut<T> = Rc<RefCell<T>>;
 
+/// A closure: the code it runs and the variables it captured.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Fn {
-    bytecode: Ref<[Op]>,
+    pub(crate) proto: Ref<Proto>,
+    pub(crate) captures: Ref<[Mut<Variant>]>,
+}
+
+/// The compiled form of a closure or a whole programme.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Proto {
+    pub(crate) name: String,
+    pub(crate) params: Ref<[Param]>,
+    /// Parameters are the first locals.
+    pub(crate) locals: u32,
+    pub(crate) bytecode: Ref<[Op]>,
+}
+
+/// A closure parameter as callers see it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+    /// None for a parameter taken apart by a pattern.
+    pub(crate) name: Option<Str>,
+    pub(crate) kind: ParamKind,
+    /// Whether the closure works out a value when it is left out.
+    pub(crate) default: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParamKind {
+    Positional,
+    /// `*rest`.
+    Rest,
+    /// After a `*`, only passed by name.
+    Keyword,
+}
+
+/// Where a new closure finds a variable it captures.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Capture {
+    Local(u32),
+    Cap(u32),
+}
+
+pub type NativeFn = dyn std::ops::Fn(&mut Vm, Args) -> Result<Variant, Error>;
+
+/// The arguments of a call, as a native function gets them.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Args {
+    pub positional: Vec<Variant>,
+    /// `name=value` arguments, in the order they were written.
+    pub keywords: Vec<(Ref<str>, Variant)>,
+}
+
+impl Args {
+    /// Take the keyword argument `name`, if it was passed.
+    pub fn keyword(&mut self, name: &str) -> Option<Variant> {
+        let i = self.keywords.iter().position(|(n, _)| &**n == name)?;
+        Some(self.keywords.remove(i).1)
+    }
+
+    /// Fail if any keyword arguments are left, once a function named
+    /// `func` has taken those it knows.
+    pub fn finish(&self, func: &str) -> Result<(), Error> {
+        match self.keywords.first() {
+            Some((name, _)) => Err(Error::new(format!(
+                "`{func}` has no parameter named `{name}`"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<Vec<Variant>> for Args {
+    fn from(positional: Vec<Variant>) -> Self {
+        Args {
+            positional,
+            keywords: vec![],
+        }
+    }
 }
 
+/// What a call gives a parameter, see `match_args`.
 #[derive(Debug, PartialEq, Clone)]
-pub struct Bytes(Ref<[u8]>);
+pub(crate) enum Given {
+    Positional(usize),
+    /// The positional arguments after the last positional parameter.
+    Rest(std::ops::Range<usize>),
+    Keyword(usize),
+    /// Left out, so the closure works out its default.
+    Default,
+}
+
+/// Match the arguments of a call to the parameters of `func`:
+/// positional ones in order, then keyword ones by name. Used by the
+/// machine, and by the compiler when it knows what is being called.
+pub(crate) fn match_args(
+    func: &str,
+    params: &[Param],
+    positional: usize,
+    keywords: &[&str],
+) -> Result<Vec<Given>, String> {
+    let kind = |kind| params.iter().filter(move |p: &&Param| p.kind == kind);
+    let count = kind(ParamKind::Positional).count();
+    let required = kind(ParamKind::Positional).filter(|p| !p.default).count();
+    let rest = kind(ParamKind::Rest).next().is_some();
+    if (positional < required && keywords.is_empty()) || (!rest && positional > count) {
+        let (takes, s) = match (rest, required == count) {
+            (true, _) => (format!("at least {required}"), required != 1),
+            (false, true) => (required.to_string(), required != 1),
+            (false, false) => (format!("{required} to {count}"), true),
+        };
+        let s = if s { "s" } else { "" };
+        let were = if positional == 1 { "was" } else { "were" };
+        return Err(format!(
+            "`{func}` takes {takes} argument{s} but {positional} {were} given"
+        ));
+    }
+    let mut given = params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| match param.kind {
+            ParamKind::Positional => (i < positional).then_some(Given::Positional(i)),
+            ParamKind::Rest => Some(Given::Rest(count.min(positional)..positional)),
+            ParamKind::Keyword => None,
+        })
+        .collect::<Vec<_>>();
+    for (k, &keyword) in keywords.iter().enumerate() {
+        let Some(i) = params.iter().position(|p| {
+            p.kind != ParamKind::Rest && p.name.as_ref().is_some_and(|n| &*n.0 == keyword)
+        }) else {
+            return Err(format!("`{func}` has no parameter named `{keyword}`"));
+        };
+        if given[i].is_some() {
+            return Err(format!("`{func}` got more than one value for `{keyword}`"));
+        }
+        given[i] = Some(Given::Keyword(k));
+    }
+    params
+        .iter()
+        .zip(given)
+        .map(|(param, given)| match (given, &param.name) {
+            (Some(given), _) => Ok(given),
+            (None, _) if param.default => Ok(Given::Default),
+            (None, Some(name)) if param.kind == ParamKind::Keyword => {
+                Err(format!("`{func}` is missing keyword argument `{}`", name.0))
+            }
+            (None, Some(name)) => Err(format!("`{func}` is missing argument `{}`", name.0)),
+            (None, None) => Err(format!("`{func}` is missing an argument")),
+        })
+        .collect()
+}
+
+/// A function written in Rust.
+#[derive(Clone)]
+pub struct Native {
+    pub(crate) name: Ref<str>,
+    pub(crate) func: Ref<NativeFn>,
+}
+
+impl std::fmt::Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Native({:?})", self.name)
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Int(i128);
+pub struct Bytes(pub(crate) Ref<[u8]>);
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Float(f64);
+pub struct Int(pub(crate) i128);
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Str(Ref<str>);
+pub struct Float(pub(crate) f64);
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Tuple(Ref<[Variant]>);
+pub struct Str(pub(crate) Ref<str>);
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Array(Mut<[Variant]>);
+pub struct Tuple(pub(crate) Ref<[Variant]>);
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Struct(Mut<StructInstance>);
+pub struct Array(pub(crate) Mut<Vec<Variant>>);
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct StructInstance {
-    fields: Ref<Type>,
-    values: Box<[Variant]>,
+pub struct Map(pub(crate) Mut<Entries>);
+
+/// The entries of a map in the order their keys were first inserted.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Entries {
+    index: HashMap<Key, usize>,
+    items: Vec<(Variant, Variant)>,
 }
 
+/// A map key in a form that can be hashed. Only values compared by
+/// contents that cannot change can be keys.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Key {
+    Unit,
+    Bool(bool),
+    Int(i128),
+    BigInt(Ref<[u8]>),
+    Bytes(Ref<[u8]>),
+    Str(Ref<str>),
+    Tuple(Box<[Key]>),
+}
+
+impl Key {
+    fn of(value: &Variant) -> Result<Key, Error> {
+        Ok(match value {
+            Variant::Unit => Key::Unit,
+            Variant::Bool(b) => Key::Bool(*b),
+            Variant::Int(n) => Key::Int(n.0),
+            Variant::BigInt(bytes) => Key::BigInt(bytes.0.clone()),
+            Variant::Bytes(bytes) => Key::Bytes(bytes.0.clone()),
+            Variant::Str(s) => Key::Str(s.0.clone()),
+            Variant::Tuple(t) => Key::Tuple(t.0.iter().map(Key::of).collect::<Result<_, _>>()?),
+            value => {
+                return Err(Error::new(format!(
+                    "cannot use a value of type `{}` as a map key",
+                    value.type_name()
+                )))
+            }
+        })
+    }
+}
+
+impl Entries {
+    pub(crate) fn get(&self, key: &Variant) -> Result<Option<&Variant>, Error> {
+        let i = self.index.get(&Key::of(key)?);
+        Ok(i.map(|&i| &self.items[i].1))
+    }
+
+    /// Set the value of `key`, keeping its place if it is already there.
+    pub(crate) fn insert(&mut self, key: Variant, value: Variant) -> Result<(), Error> {
+        match self.index.entry(Key::of(&key)?) {
+            hash_map::Entry::Occupied(i) => self.items[*i.get()].1 = value,
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(self.items.len());
+                self.items.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &(Variant, Variant)> {
+        self.items.iter()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Struct(pub(crate) Mut<StructInstance>);
+
 #[derive(Debug, PartialEq, Clone)]
+pub struct StructInstance {
+    pub(crate) fields: Ref<Type>,
+    pub(crate) values: Box<[Variant]>,
+}
+
+/// A type declared by `struct` or `enum`, or the type of a built in
+/// value, with the methods `impl` blocks have added to it. Types are
+/// equal only to themselves.
 pub struct Type {
-    field: Box<[Field]>,
+    pub(crate) name: String,
+    pub(crate) field: Box<[Field]>,
+    pub(crate) kind: TypeKind,
+    /// The type's own methods and those of the traits it implements, by
+    /// which its values' methods are found.
+    pub(crate) methods: RefCell<HashMap<Ref<str>, Variant>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeKind {
+    Struct,
+    /// An enum and its variants.
+    Enum(Box<[Case]>),
+    /// `Int`, `Str` and the other types values are built from.
+    Builtin,
+}
+
+/// A trait declared by `trait`, the methods a type must have to
+/// implement it and those it gives the type if it has not. Traits are
+/// equal only to themselves.
+pub struct Trait {
+    pub(crate) name: String,
+    pub(crate) required: Box<[String]>,
+    /// The default methods.
+    pub(crate) methods: RefCell<HashMap<Ref<str>, Variant>>,
+}
+
+/// A variant of an enum type.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Case {
+    pub(crate) name: String,
+    pub(crate) fields: Fields,
+}
+
+/// What a value of an enum variant holds.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Fields {
+    Unit,
+    /// This many values, in order.
+    Tuple(u32),
+    /// Values by name.
+    Named(Box<[Field]>),
+}
+
+/// A value of an enum type, which of its variants it is and the values
+/// that variant holds.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Enum {
+    pub(crate) ty: Ref<Type>,
+    pub(crate) tag: u32,
+    pub(crate) payload: Ref<[Variant]>,
+}
+
+impl Enum {
+    pub(crate) fn case(&self) -> &Case {
+        &self.ty.cases()[self.tag as usize]
+    }
+}
+
+impl Case {
+    /// The parameters of the variant's constructor, one for each value
+    /// it holds.
+    pub(crate) fn params(&self) -> Vec<Param> {
+        let param = |name: Option<Str>| Param {
+            name,
+            kind: ParamKind::Positional,
+            default: false,
+        };
+        match &self.fields {
+            Fields::Unit => vec![],
+            Fields::Tuple(n) => (0..*n).map(|_| param(None)).collect(),
+            Fields::Named(fields) => fields
+                .iter()
+                .map(|field| param(Some(field.name.as_str().into())))
+                .collect(),
+        }
+    }
+}
+
+/// The enums every programme can use without declaring them, `Option`
+/// with `Some(value)` and `None`, and `Result` with `Ok(value)` and
+/// `Err(error)`.
+pub(crate) fn prelude() -> [Type; 2] {
+    let enum_ = |name: &str, cases: [(&str, Fields); 2]| Type {
+        name: name.to_string(),
+        field: Box::new([]),
+        kind: TypeKind::Enum(
+            cases
+                .into_iter()
+                .map(|(name, fields)| Case {
+                    name: name.to_string(),
+                    fields,
+                })
+                .collect(),
+        ),
+        methods: Default::default(),
+    };
+    [
+        enum_(
+            "Option",
+            [("Some", Fields::Tuple(1)), ("None", Fields::Unit)],
+        ),
+        enum_(
+            "Result",
+            [("Ok", Fields::Tuple(1)), ("Err", Fields::Tuple(1))],
+        ),
+    ]
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Field {
-    name: String,
+    pub(crate) name: String,
+}
+
+impl Type {
+    /// The type of built in values called `name`, with no methods yet.
+    pub(crate) fn builtin(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            field: Box::new([]),
+            kind: TypeKind::Builtin,
+            methods: Default::default(),
+        }
+    }
+
+    /// The variants of an enum, none for other types.
+    pub(crate) fn cases(&self) -> &[Case] {
+        match &self.kind {
+            TypeKind::Enum(cases) => cases,
+            _ => &[],
+        }
+    }
+
+    /// The parameters of the type's constructor, one for each field.
+    pub(crate) fn params(&self) -> Vec<Param> {
+        self.field
+            .iter()
+            .map(|field| Param {
+                name: Some(field.name.as_str().into()),
+                kind: ParamKind::Positional,
+                default: false,
+            })
+            .collect()
+    }
+
+    pub(crate) fn method(&self, name: &str) -> Option<Variant> {
+        self.methods.borrow().get(name).cloned()
+    }
+}
+
+impl std::fmt::Debug for Type {
+    // Methods can refer back to the type, so only the name is shown.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Type({:?})", self.name)
+    }
+}
+
+impl PartialEq for Type {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Trait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Trait({:?})", self.name)
+    }
+}
+
+impl PartialEq for Trait {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// The state of a `for` loop.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Iter {
+    Range(i128, i128),
+    Items(Variant, usize),
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Bytes(bytes.into())
+    }
+}
+
+impl From<&str> for Str {
+    fn from(s: &str) -> Self {
+        Str(s.into())
+    }
+}
+
+impl From<i128> for Variant {
+    fn from(value: i128) -> Self {
+        Variant::Int(Int(value))
+    }
+}
+
+impl From<f64> for Variant {
+    fn from(value: f64) -> Self {
+        Variant::Float(Float(value))
+    }
+}
+
+impl From<bool> for Variant {
+    fn from(value: bool) -> Self {
+        Variant::Bool(value)
+    }
+}
+
+impl From<&str> for Variant {
+    fn from(value: &str) -> Self {
+        Variant::Str(value.into())
+    }
+}
+
+impl From<StrValue> for Variant {
+    fn from(value: StrValue) -> Self {
+        match value {
+            StrValue::Str(s) => Variant::Str(s.as_str().into()),
+            StrValue::Bytes(b) => Variant::Bytes(b.as_slice().into()),
+        }
+    }
+}
+
+impl From<Vec<Variant>> for Variant {
+    fn from(items: Vec<Variant>) -> Self {
+        Variant::Array(Array(Rc::new(RefCell::new(items))))
+    }
+}
+
+impl Variant {
+    /// A map of the pairs, later keys replacing earlier ones.
+    pub fn map(pairs: Vec<(Variant, Variant)>) -> Result<Self, Error> {
+        let mut entries = Entries::default();
+        for (key, value) in pairs {
+            entries.insert(key, value)?;
+        }
+        Ok(Variant::Map(Map(Rc::new(RefCell::new(entries)))))
+    }
+
+    /// An integer too big for an i128 as little endian two's complement.
+    pub fn big_int(bytes: &[u8]) -> Self {
+        Variant::BigInt(bytes.into())
+    }
+
+    pub fn tuple(items: Vec<Variant>) -> Self {
+        Variant::Tuple(Tuple(items.into()))
+    }
+
+    /// The name of the value's type, for error messages.
+    pub fn type_name(&self) -> String {
+        match self {
+            Variant::Unit => "Unit",
+            Variant::Bool(_) => "Bool",
+            Variant::Fn(_) | Variant::Native(_) => "Fn",
+            Variant::Int(_) | Variant::BigInt(_) => "Int",
+            Variant::Float(_) => "Float",
+            Variant::Bytes(_) => "Bytes",
+            Variant::Str(_) => "Str",
+            Variant::Tuple(_) => "Tuple",
+            Variant::Array(_) => "Array",
+            Variant::Map(_) => "Map",
+            Variant::Range(..) => "Range",
+            Variant::Iter(_) => "Iter",
+            Variant::Type(_) => "Type",
+            Variant::Trait(_) => "Trait",
+            Variant::Struct(s) => return s.0.borrow().fields.name.clone(),
+            Variant::Enum(e) => return e.ty.name.clone(),
+            Variant::NotImplemented => "NotImplemented",
+        }
+        .to_string()
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Variant {
+    Unit,
+    Bool(bool),
     Fn(Fn),
+    Native(Native),
     Int(Int),
-    Float(Int),
+    Float(Float),
     BigInt(Bytes),
     Bytes(Bytes),
     Str(Str),
+    Tuple(Tuple),
     Array(Array),
+    Map(Map),
+    /// `lo..hi`.
+    Range(i128, i128),
+    Iter(Mut<Iter>),
+    Type(Ref<Type>),
+    Trait(Ref<Trait>),
     Struct(Struct),
+    Enum(Enum),
+    /// What a method overloading an operator gives for an operand it
+    /// does not handle, so that the other operand's method or the built
+    /// in operator is tried instead.
+    NotImplemented,
+}
+
+/// How `{x:spec}` formats a value, see `ast::FormatSpec`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FormatSpec {
+    pub(crate) fill: char,
+    pub(crate) align: Option<Align>,
+    pub(crate) sign: bool,
+    pub(crate) alternate: bool,
+    pub(crate) zero: bool,
+    pub(crate) width: Option<usize>,
+    pub(crate) precision: Option<usize>,
+    pub(crate) kind: Option<char>,
+}
+
+/// What a value must look like for a pattern to take it apart.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Shape {
+    Tuple(u32),
+    /// Elements before and after any `..`, and whether there is one.
+    Array(u32, u32, bool),
+    /// A struct of the named type, and the fields taken, in order.
+    /// Fields without a name are taken by place.
+    Struct(Str, Ref<[Option<Str>]>),
+    /// A value of the named enum and variant, and the fields taken as
+    /// for a struct. All the fields of a tuple variant are taken by
+    /// place.
+    Case(Str, Str, Ref<[Option<Str>]>),
+}
+
+/// Jump targets for the integer arms at the start of a `match`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Switch {
+    pub(crate) cases: HashMap<i128, u32>,
+    pub(crate) default: u32,
 }
 
+/// Stack machine instructions. Jump targets are indices into the
+/// bytecode, local and capture numbers index the current frame.
 #[derive(Debug, PartialEq, Clone)]
 #[repr(u8)]
 pub enum Op {
@@ -64,20 +629,275 @@ pub enum Op {
     Sub,
     Mul,
     Div,
+    Rem,
+    Pow,
     Shl,
+    /// `>>>`, shifting in zeros.
     Shr,
+    /// `>>`, keeping the sign.
     Shra,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Range,
+    Neg,
+    Not,
     Index,
-    Dot,
-    Call,
+    SetIndex,
+    Dot(Str),
+    SetDot(Str),
+    /// Call the named method with this many arguments.
+    Method(Str, u32),
+    /// As `Method`, the last arguments passed by these names.
+    MethodKw(Str, u32, Ref<[Str]>),
+    Call(u32),
+    /// As `Call`, the last arguments passed by these names.
+    CallKw(u32, Ref<[Str]>),
+    Return,
     Ok,
     Err,
-    Fn,
-    Int,
-    Bytes,
-    Str,
-    Array,
-    Tuple,
-    Map,
-    Loc,
+    Fn(Ref<Proto>, Ref<[Capture]>),
+    Unit,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    BigInt(Bytes),
+    Bytes(Bytes),
+    Str(Str),
+    /// Push a type declared by `struct` or `enum`.
+    Type(Ref<Type>),
+    /// Push a trait declared by `trait`.
+    Trait(Ref<Trait>),
+    /// Add the closure on top to the methods of the type or trait under
+    /// it.
+    Impl(Str),
+    /// Check the type on top has the methods the trait under it
+    /// requires, and give it the trait's default methods it lacks.
+    Implement,
+    Array(u32),
+    Tuple(u32),
+    /// A map of this many keys and values.
+    Map(u32),
+    /// Add the items of the top value to the array or map under it.
+    Extend,
+    Loc(u32),
+    SetLoc(u32),
+    /// Store in a new variable, leaving closures with the old one.
+    InitLoc(u32),
+    Cap(u32),
+    SetCap(u32),
+    Global(Str),
+    SetGlobal(Str),
+    Pop,
+    Dup,
+    Dup2,
+    /// Move the top value below the n under it.
+    Under(u32),
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    /// Jump if the caller passed the parameter in this local.
+    JumpIfGiven(u32, u32),
+    Iter,
+    /// Push the next item of the iterator on top, or drop it and jump.
+    Next(u32),
+    /// Drop values down to this depth of the frame's stack.
+    Truncate(u32),
+    /// Take a value apart, failing if it has the wrong shape.
+    Unpack(Shape),
+    /// As `Unpack` followed by `true`, or just `false`.
+    TryUnpack(Shape),
+    /// Whether a number is in `lo..hi`.
+    InRange,
+    Switch(Ref<Switch>),
+    NoMatch,
+    Format(Option<Ref<FormatSpec>>),
+    Concat(u32),
+}
+
+impl Op {
+    /// The method a struct or enum defines to overload this operator:
+    /// `a + b` calls `a.add(b)`, `-a` calls `a.neg()` and `a[i] = x`
+    /// calls `a.set_index(i, x)`. `a != b` is the opposite of `a.eq(b)`.
+    pub(crate) fn method(&self) -> Option<&'static str> {
+        Some(match self {
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Mul => "mul",
+            Op::Div => "div",
+            Op::Rem => "rem",
+            Op::Pow => "pow",
+            Op::Shl => "shl",
+            Op::Shr => "shr",
+            Op::Shra => "shra",
+            Op::BitAnd => "bitand",
+            Op::BitOr => "bitor",
+            Op::BitXor => "bitxor",
+            Op::Eq | Op::Ne => "eq",
+            Op::Lt => "lt",
+            Op::Le => "le",
+            Op::Gt => "gt",
+            Op::Ge => "ge",
+            Op::Neg => "neg",
+            Op::Not => "not",
+            Op::Index => "index",
+            Op::SetIndex => "set_index",
+            _ => return None,
+        })
+    }
+
+    /// The method of the right operand of a binary operator called, with
+    /// the left operand, when the left operand has no method for it or
+    /// its method gives `NotImplemented`: `2 * v` calls `v.rmul(2)`. Comparisons turn around, so `2 < v`
+    /// calls `v.gt(2)`.
+    pub(crate) fn reflected(&self) -> Option<&'static str> {
+        Some(match self {
+            Op::Add => "radd",
+            Op::Sub => "rsub",
+            Op::Mul => "rmul",
+            Op::Div => "rdiv",
+            Op::Rem => "rrem",
+            Op::Pow => "rpow",
+            Op::Shl => "rshl",
+            Op::Shr => "rshr",
+            Op::Shra => "rshra",
+            Op::BitAnd => "rbitand",
+            Op::BitOr => "rbitor",
+            Op::BitXor => "rbitxor",
+            Op::Eq | Op::Ne => "eq",
+            Op::Lt => "gt",
+            Op::Le => "ge",
+            Op::Gt => "lt",
+            Op::Ge => "le",
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Strings inside other values are quoted.
+        let item = |f: &mut std::fmt::Formatter<'_>, i: usize, v: &Variant| {
+            let sep = if i == 0 { "" } else { ", " };
+            match v {
+                Variant::Str(s) => write!(f, "{sep}{:?}", &*s.0),
+                v => write!(f, "{sep}{v}"),
+            }
+        };
+        match self {
+            Variant::Unit => write!(f, "()"),
+            Variant::Bool(b) => write!(f, "{b}"),
+            Variant::Fn(func) => write!(f, "<fn {}>", func.proto.name),
+            Variant::Native(native) => write!(f, "<fn {}>", native.name),
+            Variant::Int(n) => write!(f, "{}", n.0),
+            Variant::Float(x) => write!(f, "{:?}", x.0),
+            Variant::BigInt(bytes) => write!(f, "{}", big_int_to_string(&bytes.0)),
+            Variant::Bytes(bytes) => write!(f, "{:?}", bytes.0.escape_ascii().to_string()),
+            Variant::Str(s) => write!(f, "{}", s.0),
+            Variant::Tuple(items) => {
+                write!(f, "(")?;
+                for (i, v) in items.0.iter().enumerate() {
+                    item(f, i, v)?;
+                }
+                if items.0.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Variant::Array(items) => {
+                write!(f, "[")?;
+                for (i, v) in items.0.borrow().iter().enumerate() {
+                    item(f, i, v)?;
+                }
+                write!(f, "]")
+            }
+            Variant::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in map.0.borrow().iter().enumerate() {
+                    item(f, i, k)?;
+                    write!(f, ": ")?;
+                    item(f, 0, v)?;
+                }
+                write!(f, "}}")
+            }
+            Variant::Range(lo, hi) => write!(f, "{lo}..{hi}"),
+            Variant::Iter(_) => write!(f, "<iter>"),
+            Variant::Type(ty) => write!(f, "<type {}>", ty.name),
+            Variant::Trait(t) => write!(f, "<trait {}>", t.name),
+            Variant::NotImplemented => write!(f, "NotImplemented"),
+            Variant::Enum(e) => {
+                let case = e.case();
+                write!(f, "{}", case.name)?;
+                match &case.fields {
+                    Fields::Unit => Ok(()),
+                    Fields::Tuple(_) => {
+                        write!(f, "(")?;
+                        for (i, v) in e.payload.iter().enumerate() {
+                            item(f, i, v)?;
+                        }
+                        write!(f, ")")
+                    }
+                    Fields::Named(fields) => {
+                        write!(f, "(")?;
+                        for (i, (field, v)) in fields.iter().zip(e.payload.iter()).enumerate() {
+                            let sep = if i == 0 { "" } else { ", " };
+                            write!(f, "{sep}{}=", field.name)?;
+                            item(f, 0, v)?;
+                        }
+                        write!(f, ")")
+                    }
+                }
+            }
+            Variant::Struct(s) => {
+                let s = s.0.borrow();
+                write!(f, "{}(", s.fields.name)?;
+                for (i, (field, v)) in s.fields.field.iter().zip(&s.values).enumerate() {
+                    let sep = if i == 0 { "" } else { ", " };
+                    write!(f, "{sep}{}=", field.name)?;
+                    item(f, 0, v)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+// The decimal digits of a little endian two's complement integer.
+fn big_int_to_string(bytes: &[u8]) -> String {
+    let negative = bytes.last().is_some_and(|b| b & 0x80 != 0);
+    let mut magnitude = bytes.to_vec();
+    if negative {
+        // Negate: invert and add one.
+        let mut carry = 1;
+        for b in magnitude.iter_mut() {
+            let v = (!*b) as u16 + carry;
+            *b = v as u8;
+            carry = v >> 8;
+        }
+    }
+    let mut digits = vec![];
+    while magnitude.iter().any(|&b| b != 0) {
+        // magnitude /= 10, most significant byte first.
+        let mut rem = 0u16;
+        for b in magnitude.iter_mut().rev() {
+            let v = (rem << 8) | *b as u16;
+            *b = (v / 10) as u8;
+            rem = v % 10;
+        }
+        digits.push(b'0' + rem as u8);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    }
+    if negative {
+        digits.push(b'-');
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
 }
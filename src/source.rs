@@ -0,0 +1,167 @@
+use std::ops::Range;
+
+use crate::{
+    diag::{self, Diagnostic},
+    lex::Span,
+};
+
+/// Identifies a file registered with a `SourceMap`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct FileId(usize);
+
+/// Where a span lives: the file, its byte range and 1-based line/column
+/// of both ends. Columns count characters, not bytes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Location {
+    pub file: FileId,
+    pub range: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceFile<'a> {
+    name: String,
+    src: &'a str,
+    line_starts: Vec<usize>,
+}
+
+/// All the source text a `Lex` may have been run over.
+///
+/// Spans are borrowed slices of the source, so a span is mapped back
+/// to its file by finding the file whose text contains it.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SourceMap<'a> {
+    files: Vec<SourceFile<'a>>,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(name: impl Into<String>, src: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            name: name.into(),
+            src,
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &'a str {
+        self.src
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The text of a 1-based line without its newline.
+    pub fn line(&self, line: usize) -> Option<&'a str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map(|&e| e - 1)
+            .unwrap_or(self.src.len());
+        Some(&self.src[start..end])
+    }
+
+    /// 1-based line and column of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&s| s <= offset);
+        let start = self.line_starts[line - 1];
+        let column = self.src[start..offset].chars().count() + 1;
+        (line, column)
+    }
+
+    /// The byte range of a span, if it was sliced from this file.
+    pub fn range_of(&self, span: &Span<'a>) -> Option<Range<usize>> {
+        let base = self.src.as_ptr() as usize;
+        let start = span.as_ptr() as usize;
+        if start < base || start + span.len() > base + self.src.len() {
+            return None;
+        }
+        let start = start - base;
+        Some(start..start + span.len())
+    }
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, src: &'a str) -> FileId {
+        self.files.push(SourceFile::new(name, src));
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile<'a> {
+        &self.files[id.0]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile<'a>)> {
+        self.files.iter().enumerate().map(|(i, f)| (FileId(i), f))
+    }
+
+    pub fn file_of(&self, span: &Span<'a>) -> Option<FileId> {
+        // A zero width span at the very end of one file could also sit at
+        // the start of another if the allocations are adjacent, so prefer
+        // a file that strictly contains the span.
+        let mut candidate = None;
+        for (id, file) in self.files() {
+            if let Some(range) = file.range_of(span) {
+                if range.start < file.src.len() {
+                    return Some(id);
+                }
+                candidate.get_or_insert(id);
+            }
+        }
+        candidate
+    }
+
+    pub fn locate(&self, span: &Span<'a>) -> Option<Location> {
+        let file = self.file_of(span)?;
+        let f = self.file(file);
+        let range = f.range_of(span)?;
+        let (line, column) = f.line_col(range.start);
+        let (end_line, end_column) = f.line_col(range.end);
+        Some(Location {
+            file,
+            range,
+            line,
+            column,
+            end_line,
+            end_column,
+        })
+    }
+}
+
+/// Check that raw file contents are UTF-8, dropping any byte order mark.
+pub fn decode_utf8<'b>(name: &str, bytes: &'b [u8]) -> Result<&'b str, Diagnostic<'static>> {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    std::str::from_utf8(bytes).map_err(|e| {
+        // The valid prefix is a str, so line and column come from that.
+        let offset = e.valid_up_to();
+        let valid = std::str::from_utf8(&bytes[..offset]).unwrap();
+        let (line, column) = SourceFile::new(name, valid).line_col(offset);
+        Diagnostic::error(diag::INVALID_UTF8, format!("{name} is not valid UTF-8")).with_note(
+            format!(
+                "invalid byte 0x{:02x} at {name}:{line}:{column}",
+                bytes[offset]
+            ),
+        )
+    })
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,1403 @@
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::Align,
+    runtime::{
+        match_args, prelude, Args, Capture, Enum, Field, Fields, Fn, FormatSpec, Given, Iter, Mut,
+        Native, Op, Param, Proto, Ref, Shape, Str, Struct, StructInstance, Type, TypeKind, Variant,
+    },
+    Error,
+};
+
+#[derive(Debug, Clone)]
+struct Frame {
+    func: Fn,
+    pc: usize,
+    /// Where the frame's values start on the stack.
+    base: usize,
+    locals: Vec<Mut<Variant>>,
+    /// Which parameters the caller passed, for `JumpIfGiven`.
+    given: Vec<bool>,
+    /// The operator to finish with the value the frame returns, if it
+    /// is running the method overloading one.
+    overload: Option<Overload>,
+}
+
+/// An operator applied by calling the method of one of its operands.
+#[derive(Debug, Clone)]
+struct Overload {
+    op: Op,
+    /// In the operator's order, whichever operand's method is called.
+    operands: Vec<Variant>,
+    /// Whether the method is the right operand's, as `rmul`.
+    reflected: bool,
+}
+
+// How deep closures can call each other.
+const MAX_FRAMES: usize = 10_000;
+
+// How deep calls from native code, such as the function passed to
+// `for_each`, can go. Each takes space on the Rust stack.
+const MAX_CALLS: usize = 100;
+
+// The types of built in values a programme can name to implement traits
+// for, as `Variant::type_name` gives them.
+const BUILTIN_TYPES: [&str; 10] = [
+    "Bool", "Int", "Float", "Bytes", "Str", "Tuple", "Array", "Map", "Range", "Fn",
+];
+
+/// Runs compiled programmes. Globals last as long as the machine, so a
+/// programme can be run a piece at a time.
+///
+/// ```text
+/// let mut vm = Vm::new();
+/// vm.register("double", |_, args| ..);
+/// vm.run(proto)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Vm {
+    stack: Vec<Variant>,
+    frames: Vec<Frame>,
+    globals: HashMap<Ref<str>, Variant>,
+    /// The types of built in values and the enums of the prelude, by
+    /// name.
+    types: HashMap<Ref<str>, Ref<Type>>,
+    /// What `print` has written, if it is being kept rather than sent to
+    /// stdout.
+    output: Option<String>,
+    /// How many calls from native code, each running on the Rust stack,
+    /// are under way.
+    calls: usize,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            types: HashMap::new(),
+            output: None,
+            calls: 0,
+        };
+        vm.set_global("true", true.into());
+        vm.set_global("false", false.into());
+        vm.set_global("NotImplemented", Variant::NotImplemented);
+        for name in BUILTIN_TYPES {
+            let ty = Ref::new(Type::builtin(name));
+            vm.types.insert(name.into(), ty.clone());
+            vm.set_global(name, Variant::Type(ty));
+        }
+        // The variants of the prelude's enums need no `Option.` or
+        // `Result.` before them, as with an enum a programme declares.
+        for ty in prelude() {
+            let ty = Ref::new(ty);
+            for (tag, case) in ty.cases().iter().enumerate() {
+                vm.set_global(&case.name, case_value(&ty, tag));
+            }
+            vm.types.insert(ty.name.as_str().into(), ty.clone());
+            vm.set_global(&ty.name, Variant::Type(ty.clone()));
+        }
+        vm.register_with_keywords("print", |vm, mut args| {
+            let sep = args.keyword("sep").map_or(" ".into(), |v| v.to_string());
+            let end = args.keyword("end").map_or("\n".into(), |v| v.to_string());
+            args.finish("print")?;
+            let text = args
+                .positional
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>();
+            vm.write(&text.join(&sep));
+            vm.write(&end);
+            Ok(Variant::Unit)
+        });
+        vm
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Variant> {
+        self.globals.get(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Variant) {
+        self.globals.insert(name.into(), value);
+    }
+
+    /// The enums bound to globals, whose variants later programmes can
+    /// name in patterns.
+    pub fn enums(&self) -> impl Iterator<Item = Ref<Type>> + '_ {
+        self.globals.values().filter_map(|value| match value {
+            Variant::Type(ty) if matches!(ty.kind, TypeKind::Enum(_)) => Some(ty.clone()),
+            _ => None,
+        })
+    }
+
+    /// Make a Rust function callable from programmes as `name`. It takes
+    /// only positional arguments.
+    pub fn register(
+        &mut self,
+        name: &str,
+        func: impl std::ops::Fn(&mut Vm, Vec<Variant>) -> Result<Variant, Error> + 'static,
+    ) {
+        let owned: Ref<str> = name.into();
+        self.register_with_keywords(name, move |vm, args| {
+            args.finish(&owned)?;
+            func(vm, args.positional)
+        });
+    }
+
+    /// As `register`, for a function that takes keyword arguments too.
+    pub fn register_with_keywords(
+        &mut self,
+        name: &str,
+        func: impl std::ops::Fn(&mut Vm, Args) -> Result<Variant, Error> + 'static,
+    ) {
+        let native = Native {
+            name: name.into(),
+            func: Rc::new(func),
+        };
+        self.set_global(name, Variant::Native(native));
+    }
+
+    /// Keep what is printed for `take_output` instead of writing it to
+    /// stdout.
+    pub fn capture_output(&mut self) {
+        self.output.get_or_insert_with(String::new);
+    }
+
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn write(&mut self, text: &str) {
+        match &mut self.output {
+            Some(output) => output.push_str(text),
+            None => print!("{text}"),
+        }
+    }
+
+    /// Run a compiled programme.
+    pub fn run(&mut self, main: Ref<Proto>) -> Result<Variant, Error> {
+        let func = Fn {
+            proto: main,
+            captures: Ref::new([]),
+        };
+        self.call(Variant::Fn(func), vec![])
+    }
+
+    /// Call a function, such as one passed to a native function.
+    pub fn call(&mut self, func: Variant, args: impl Into<Args>) -> Result<Variant, Error> {
+        if self.calls == MAX_CALLS {
+            return Err(recursion_limit());
+        }
+        let (frames, stack) = (self.frames.len(), self.stack.len());
+        self.calls += 1;
+        let result = match self.enter(func, args.into()) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => self.execute(frames),
+            Err(e) => Err(e),
+        };
+        self.calls -= 1;
+        if result.is_err() {
+            self.frames.truncate(frames);
+            self.stack.truncate(stack);
+        }
+        result
+    }
+
+    // Call a native function, giving its result, or start running a
+    // closure in a new frame.
+    fn enter(&mut self, func: Variant, args: Args) -> Result<Option<Variant>, Error> {
+        match func {
+            Variant::Native(native) => (native.func)(self, args).map(Some),
+            Variant::Fn(_) if self.frames.len() == MAX_FRAMES => Err(recursion_limit()),
+            Variant::Fn(func) => {
+                let (locals, given) = bind_args(&func.proto, args)?;
+                self.frames.push(Frame {
+                    func,
+                    pc: 0,
+                    base: self.stack.len(),
+                    locals,
+                    given,
+                    overload: None,
+                });
+                Ok(None)
+            }
+            Variant::Type(ty) => match ty.kind {
+                TypeKind::Struct => construct(&ty, args).map(Some),
+                TypeKind::Enum(_) => Err(Error::new(format!(
+                    "cannot call the enum `{}`, use one of its variants",
+                    ty.name
+                ))),
+                TypeKind::Builtin => Err(Error::new(format!(
+                    "cannot call the built in type `{}`",
+                    ty.name
+                ))),
+            },
+            value => Err(Error::new(format!(
+                "cannot call a value of type `{}`",
+                value.type_name()
+            ))),
+        }
+    }
+
+    // Run until the frame at `stop` returns.
+    fn execute(&mut self, stop: usize) -> Result<Variant, Error> {
+        // The code of the function on top, held here so the instruction
+        // can be borrowed while the frames change. It is only taken again
+        // when calling or returning reaches a different function.
+        let mut code = self.frame().func.proto.bytecode.clone();
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            if !Rc::ptr_eq(&code, &frame.func.proto.bytecode) {
+                code = frame.func.proto.bytecode.clone();
+            }
+            let pc = frame.pc;
+            frame.pc += 1;
+            match &code[pc] {
+                Op::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() == stop {
+                        return Ok(value);
+                    }
+                    match frame.overload {
+                        Some(overload) => self.returned(overload, value)?,
+                        None => self.stack.push(value),
+                    }
+                }
+                Op::Call(n) => {
+                    let args = self.split_off(*n);
+                    let func = self.pop();
+                    if let Some(value) = self.enter(func, args.into())? {
+                        self.stack.push(value);
+                    }
+                }
+                Op::CallKw(n, names) => {
+                    let args = self.args(*n, names);
+                    let func = self.pop();
+                    if let Some(value) = self.enter(func, args)? {
+                        self.stack.push(value);
+                    }
+                }
+                Op::Method(name, n) => {
+                    let args = self.split_off(*n);
+                    let receiver = self.pop();
+                    if let Some(value) = self.invoke(receiver, &name.0, args.into())? {
+                        self.stack.push(value);
+                    }
+                }
+                Op::MethodKw(name, n, names) => {
+                    let args = self.args(*n, names);
+                    let receiver = self.pop();
+                    if let Some(value) = self.invoke(receiver, &name.0, args)? {
+                        self.stack.push(value);
+                    }
+                }
+                Op::Fn(proto, captures) => {
+                    let frame = self.frame();
+                    let captures = captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(i) => frame.locals[*i as usize].clone(),
+                            Capture::Cap(i) => frame.func.captures[*i as usize].clone(),
+                        })
+                        .collect();
+                    let func = Fn {
+                        proto: proto.clone(),
+                        captures,
+                    };
+                    self.stack.push(Variant::Fn(func));
+                }
+                Op::Unit => self.stack.push(Variant::Unit),
+                Op::Bool(b) => self.stack.push((*b).into()),
+                Op::Int(n) => self.stack.push((*n).into()),
+                Op::Float(x) => self.stack.push((*x).into()),
+                Op::BigInt(bytes) => self.stack.push(Variant::BigInt(bytes.clone())),
+                Op::Bytes(bytes) => self.stack.push(Variant::Bytes(bytes.clone())),
+                Op::Str(s) => self.stack.push(Variant::Str(s.clone())),
+                Op::Type(ty) => self.stack.push(Variant::Type(ty.clone())),
+                Op::Trait(t) => self.stack.push(Variant::Trait(t.clone())),
+                Op::Impl(name) => {
+                    let method = self.pop();
+                    let methods = match self.stack.last() {
+                        Some(Variant::Type(ty)) => &ty.methods,
+                        Some(Variant::Trait(t)) => &t.methods,
+                        _ => return Err(not_a_type(self.pop())),
+                    };
+                    methods.borrow_mut().insert(name.0.clone(), method);
+                }
+                Op::Implement => {
+                    let ty = match self.pop() {
+                        Variant::Type(ty) => ty,
+                        value => return Err(not_a_type(value)),
+                    };
+                    let t = match self.pop() {
+                        Variant::Trait(t) => t,
+                        value => {
+                            return Err(Error::new(format!("`{value}` is not a trait")));
+                        }
+                    };
+                    if let Some(name) = t.required.iter().find(|n| ty.method(n).is_none()) {
+                        return Err(Error::new(format!(
+                            "`{}` does not implement `{name}`, which `{}` requires",
+                            ty.name, t.name
+                        )));
+                    }
+                    let mut methods = ty.methods.borrow_mut();
+                    for (name, method) in t.methods.borrow().iter() {
+                        methods
+                            .entry(name.clone())
+                            .or_insert_with(|| method.clone());
+                    }
+                }
+                Op::Array(n) => {
+                    let items = self.split_off(*n);
+                    self.stack.push(items.into());
+                }
+                Op::Tuple(n) => {
+                    let items = self.split_off(*n);
+                    self.stack.push(Variant::tuple(items));
+                }
+                Op::Map(n) => {
+                    let mut items = self.split_off(2 * n).into_iter();
+                    let pairs = std::iter::from_fn(|| Some((items.next()?, items.next()?)));
+                    self.stack.push(Variant::map(pairs.collect())?);
+                }
+                Op::Extend => {
+                    let value = self.pop();
+                    extend(self.stack.last().unwrap(), value)?;
+                }
+                Op::Loc(i) => {
+                    let value = self.frame().locals[*i as usize].borrow().clone();
+                    self.stack.push(value);
+                }
+                Op::SetLoc(i) => {
+                    let value = self.pop();
+                    *self.frame().locals[*i as usize].borrow_mut() = value;
+                }
+                Op::InitLoc(i) => {
+                    let value = self.pop();
+                    self.frame().locals[*i as usize] = cell(value);
+                }
+                Op::Cap(i) => {
+                    let value = self.frame().func.captures[*i as usize].borrow().clone();
+                    self.stack.push(value);
+                }
+                Op::SetCap(i) => {
+                    let value = self.pop();
+                    *self.frame().func.captures[*i as usize].borrow_mut() = value;
+                }
+                Op::Global(name) => {
+                    let value = self.globals.get(&name.0).cloned().ok_or_else(|| {
+                        Error::new(format!("cannot find `{}` in this scope", name.0))
+                    })?;
+                    self.stack.push(value);
+                }
+                Op::SetGlobal(name) => {
+                    let value = self.pop();
+                    self.globals.insert(name.0.clone(), value);
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => self.stack.push(self.stack.last().unwrap().clone()),
+                Op::Dup2 => {
+                    let top = self.stack[self.stack.len() - 2..].to_vec();
+                    self.stack.extend(top);
+                }
+                Op::Under(n) => {
+                    let value = self.pop();
+                    self.stack.insert(self.stack.len() - *n as usize, value);
+                }
+                Op::Jump(to) => self.frame().pc = *to as usize,
+                Op::JumpIfFalse(to) => {
+                    if !self.condition()? {
+                        self.frame().pc = *to as usize;
+                    }
+                }
+                Op::JumpIfGiven(i, to) => {
+                    let frame = self.frame();
+                    if frame.given[*i as usize] {
+                        frame.pc = *to as usize;
+                    }
+                }
+                Op::JumpIfTrue(to) => {
+                    if self.condition()? {
+                        self.frame().pc = *to as usize;
+                    }
+                }
+                Op::Iter => {
+                    let value = self.pop();
+                    let iter = iter(value)?;
+                    self.stack.push(Variant::Iter(Rc::new(RefCell::new(iter))));
+                }
+                Op::Next(to) => {
+                    let Some(Variant::Iter(iter)) = self.stack.last() else {
+                        panic!("`Next` without an iterator");
+                    };
+                    let item = next(&mut iter.borrow_mut());
+                    match item {
+                        Some(item) => self.stack.push(item),
+                        None => {
+                            self.pop();
+                            self.frame().pc = *to as usize;
+                        }
+                    }
+                }
+                Op::Truncate(depth) => {
+                    let base = self.frame().base;
+                    self.stack.truncate(base + *depth as usize);
+                }
+                Op::Unpack(shape) => {
+                    let value = self.pop();
+                    let Some(parts) = unpack(shape, &value) else {
+                        return Err(Error::new(format!(
+                            "expected {}, found `{value}`",
+                            describe(shape)
+                        )));
+                    };
+                    self.stack.extend(parts.into_iter().rev());
+                }
+                Op::TryUnpack(shape) => {
+                    let value = self.pop();
+                    let parts = unpack(shape, &value);
+                    let matched = parts.is_some();
+                    self.stack.extend(parts.into_iter().flatten().rev());
+                    self.stack.push(matched.into());
+                }
+                Op::InRange => {
+                    let hi = self.pop();
+                    let lo = self.pop();
+                    let value = self.pop();
+                    self.stack.push(in_range(&value, &lo, &hi).into());
+                }
+                Op::Switch(switch) => {
+                    let key = match self.stack.last() {
+                        Some(Variant::Int(n)) => Some(n.0),
+                        Some(Variant::Float(x)) if x.0.fract() == 0.0 => Some(x.0 as i128),
+                        _ => None,
+                    };
+                    let to = key
+                        .and_then(|key| switch.cases.get(&key))
+                        .unwrap_or(&switch.default);
+                    self.frame().pc = *to as usize;
+                }
+                Op::NoMatch => {
+                    let value = self.pop();
+                    return Err(Error::new(format!("no arm matches `{value}`")));
+                }
+                Op::Format(spec) => {
+                    let value = self.pop();
+                    let text = format(&value, spec.as_deref())?;
+                    self.stack.push(text.as_str().into());
+                }
+                Op::Concat(n) => {
+                    let parts = self.split_off(*n);
+                    let text = parts.iter().map(|p| p.to_string()).collect::<String>();
+                    self.stack.push(text.as_str().into());
+                }
+                op @ (Op::Neg | Op::Not) => {
+                    let value = self.pop();
+                    match overload(&value, op) {
+                        Some(method) => self.overloaded(method, op, vec![value], false)?,
+                        None => self.stack.push(unary(op, value)?),
+                    }
+                }
+                Op::Index => {
+                    let index = self.pop();
+                    let value = self.pop();
+                    match overload(&value, &Op::Index) {
+                        Some(method) => {
+                            self.overloaded(method, &Op::Index, vec![value, index], false)?
+                        }
+                        None => self.stack.push(get_index(&value, &index)?),
+                    }
+                }
+                Op::SetIndex => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    let value = self.pop();
+                    match overload(&target, &Op::SetIndex) {
+                        Some(method) => {
+                            let operands = vec![target, index, value];
+                            self.overloaded(method, &Op::SetIndex, operands, false)?
+                        }
+                        None => set_index(&target, &index, value)?,
+                    }
+                }
+                Op::Dot(name) => {
+                    let value = self.pop();
+                    self.stack.push(get_field(&value, &name.0)?);
+                }
+                Op::SetDot(name) => {
+                    let target = self.pop();
+                    let value = self.pop();
+                    set_field(&target, &name.0, value)?;
+                }
+                op @ (Op::Ok | Op::Err) => {
+                    let value = self.pop();
+                    let tag = if *op == Op::Ok { 0 } else { 1 };
+                    let ty = self.types["Result"].clone();
+                    let payload = Rc::new([value]);
+                    self.stack.push(Variant::Enum(Enum { ty, tag, payload }));
+                }
+                op => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let number = |v: &Variant| matches!(v, Variant::Int(_) | Variant::Float(_));
+                    // Numbers never overload operators.
+                    if number(&lhs) && number(&rhs) {
+                        self.stack.push(binary(op, lhs, rhs)?);
+                        continue;
+                    }
+                    match overload(&lhs, op) {
+                        Some(method) => self.overloaded(method, op, vec![lhs, rhs], false)?,
+                        None => self.reflect(op, lhs, rhs)?,
+                    }
+                }
+            }
+        }
+    }
+
+    // Start running the method overloading an operator, in a new frame
+    // like any other call so that one can use the operator again. What
+    // it returns goes to `returned`.
+    fn overloaded(
+        &mut self,
+        method: Variant,
+        op: &Op,
+        operands: Vec<Variant>,
+        reflected: bool,
+    ) -> Result<(), Error> {
+        let mut args = operands.clone();
+        if reflected {
+            args.swap(0, 1);
+        }
+        let overload = Overload {
+            op: op.clone(),
+            operands,
+            reflected,
+        };
+        match self.enter(method, args.into())? {
+            Some(value) => self.returned(overload, value),
+            None => {
+                self.frame().overload = Some(overload);
+                Ok(())
+            }
+        }
+    }
+
+    // Finish an operator with the value the method overloading it gave.
+    // If that is `NotImplemented`, try the right operand's method, and
+    // then the built in operator.
+    fn returned(&mut self, overload: Overload, value: Variant) -> Result<(), Error> {
+        let Overload {
+            op,
+            mut operands,
+            reflected,
+        } = overload;
+        match (&op, value) {
+            (_, Variant::NotImplemented) if !reflected && op.reflected().is_some() => {
+                let rhs = operands.pop().unwrap();
+                let lhs = operands.pop().unwrap();
+                return self.reflect(&op, lhs, rhs);
+            }
+            (_, Variant::NotImplemented) => return self.builtin(&op, operands),
+            (Op::SetIndex, _) => {}
+            (Op::Ne, Variant::Bool(b)) => self.stack.push((!b).into()),
+            (Op::Ne, value) => {
+                return Err(Error::new(format!(
+                    "`eq` must give a `Bool` for `!=`, not `{}`",
+                    value.type_name()
+                )))
+            }
+            (_, value) => self.stack.push(value),
+        }
+        Ok(())
+    }
+
+    // Apply a binary operator with the right operand's reflected method,
+    // or the built in operator if it has none.
+    fn reflect(&mut self, op: &Op, lhs: Variant, rhs: Variant) -> Result<(), Error> {
+        match reflected(&rhs, op) {
+            Some(method) => self.overloaded(method, op, vec![lhs, rhs], true),
+            None => {
+                self.stack.push(binary(op, lhs, rhs)?);
+                Ok(())
+            }
+        }
+    }
+
+    // Apply an operator to operands whose methods did not handle it.
+    fn builtin(&mut self, op: &Op, operands: Vec<Variant>) -> Result<(), Error> {
+        let mut operands = operands.into_iter();
+        let mut next = || operands.next().unwrap();
+        let value = match op {
+            Op::Neg | Op::Not => unary(op, next())?,
+            Op::Index => {
+                let (value, index) = (next(), next());
+                get_index(&value, &index)?
+            }
+            Op::SetIndex => {
+                let (target, index, value) = (next(), next(), next());
+                return set_index(&target, &index, value);
+            }
+            _ => {
+                let (lhs, rhs) = (next(), next());
+                binary(op, lhs, rhs)?
+            }
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // Call a method of the receiver's type with the receiver as the first
+    // argument, a function of a type itself, a callable field, or
+    // failing those a built in method.
+    fn invoke(
+        &mut self,
+        receiver: Variant,
+        name: &str,
+        mut args: Args,
+    ) -> Result<Option<Variant>, Error> {
+        let found = match &receiver {
+            Variant::Struct(s) => {
+                let s = s.0.borrow();
+                match s.fields.method(name) {
+                    Some(method) => Some((method, true)),
+                    None => (s.fields.field.iter())
+                        .position(|f| f.name == name)
+                        .map(|i| (s.values[i].clone(), false)),
+                }
+            }
+            Variant::Enum(e) => e.ty.method(name).map(|method| (method, true)),
+            Variant::Type(ty) => (ty.method(name))
+                .or_else(|| case(ty, name))
+                .map(|func| (func, false)),
+            Variant::Trait(t) => t.methods.borrow().get(name).map(|m| (m.clone(), false)),
+            value => (self.types.get(&*value.type_name()))
+                .and_then(|ty| ty.method(name))
+                .map(|method| (method, true)),
+        };
+        match found {
+            Some((func, with_self)) => {
+                if with_self {
+                    args.positional.insert(0, receiver);
+                }
+                self.enter(func, args)
+            }
+            None => self.method(receiver, name, args).map(Some),
+        }
+    }
+
+    // The methods every value of a built in type has.
+    fn method(&mut self, receiver: Variant, name: &str, args: Args) -> Result<Variant, Error> {
+        // None of the built in methods take keyword arguments.
+        args.finish(name)?;
+        match (name, &receiver, args.positional.as_slice()) {
+            ("len", _, []) => {
+                let len = match &receiver {
+                    Variant::Str(s) => s.0.chars().count(),
+                    Variant::Bytes(b) => b.0.len(),
+                    Variant::Tuple(t) => t.0.len(),
+                    Variant::Array(a) => a.0.borrow().len(),
+                    Variant::Map(m) => m.0.borrow().len(),
+                    // The length of a range may not fit in a usize, or
+                    // even an `Int`.
+                    Variant::Range(lo, hi) => {
+                        let len = hi.checked_sub(*lo).map(|len| len.max(0).into());
+                        return len.ok_or_else(|| {
+                            Error::new(format!(
+                                "the length of `{receiver}` does not fit in an `Int`"
+                            ))
+                        });
+                    }
+                    _ => return Err(no_method(&receiver, name)),
+                };
+                Ok((len as i128).into())
+            }
+            ("push", Variant::Array(a), [item]) => {
+                a.0.borrow_mut().push(item.clone());
+                Ok(Variant::Unit)
+            }
+            ("for_each", _, [f]) => {
+                let mut iter = iter(receiver)?;
+                while let Some(item) = next(&mut iter) {
+                    self.call(f.clone(), vec![item])?;
+                }
+                Ok(Variant::Unit)
+            }
+            _ => Err(no_method(&receiver, name)),
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn pop(&mut self) -> Variant {
+        self.stack.pop().unwrap()
+    }
+
+    fn split_off(&mut self, n: u32) -> Vec<Variant> {
+        self.stack.split_off(self.stack.len() - n as usize)
+    }
+
+    // The top `n` values, the last of them passed by `names`.
+    fn args(&mut self, n: u32, names: &[Str]) -> Args {
+        let mut positional = self.split_off(n);
+        let values = positional.split_off(positional.len() - names.len());
+        let keywords = names.iter().map(|n| n.0.clone()).zip(values).collect();
+        Args {
+            positional,
+            keywords,
+        }
+    }
+
+    // Conditions must be `Bool`, there is no truthiness.
+    fn condition(&mut self) -> Result<bool, Error> {
+        match self.pop() {
+            Variant::Bool(b) => Ok(b),
+            value => Err(Error::new(format!(
+                "expected a `Bool` condition, found `{}`",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+// The locals of a closure called with `args`, and which of its parameters
+// they fill.
+fn bind_args(proto: &Proto, args: Args) -> Result<(Vec<Mut<Variant>>, Vec<bool>), Error> {
+    let names = args.keywords.iter().map(|(n, _)| &**n).collect::<Vec<_>>();
+    let given = match_args(&proto.name, &proto.params, args.positional.len(), &names)
+        .map_err(Error::new)?;
+    let mut positional = args.positional.into_iter().map(Some).collect::<Vec<_>>();
+    let mut keywords = args
+        .keywords
+        .into_iter()
+        .map(|(_, v)| Some(v))
+        .collect::<Vec<_>>();
+    let mut locals = vec![];
+    for given in &given {
+        let value = match given {
+            Given::Positional(i) => positional[*i].take().unwrap(),
+            Given::Rest(range) => Variant::tuple(
+                positional[range.clone()]
+                    .iter_mut()
+                    .map(|v| v.take().unwrap())
+                    .collect(),
+            ),
+            Given::Keyword(k) => keywords[*k].take().unwrap(),
+            Given::Default => Variant::Unit,
+        };
+        locals.push(cell(value));
+    }
+    locals.resize_with(proto.locals as usize, || cell(Variant::Unit));
+    let given = given.iter().map(|g| *g != Given::Default).collect();
+    Ok((locals, given))
+}
+
+// The error for adding methods to something other than a type.
+fn not_a_type(value: Variant) -> Error {
+    Error::new(format!("cannot add methods to `{value}`, it is not a type"))
+}
+
+// A new value of a struct type, its fields given as arguments.
+fn construct(ty: &Ref<Type>, args: Args) -> Result<Variant, Error> {
+    let instance = StructInstance {
+        fields: ty.clone(),
+        values: fields(&ty.name, &ty.params(), args)?,
+    };
+    Ok(Variant::Struct(Struct(Rc::new(RefCell::new(instance)))))
+}
+
+// The values of the fields of a struct or enum variant, in order, from
+// the arguments to its constructor.
+fn fields<T: FromIterator<Variant>>(name: &str, params: &[Param], args: Args) -> Result<T, Error> {
+    let names = args.keywords.iter().map(|(n, _)| &**n).collect::<Vec<_>>();
+    let given = match_args(name, params, args.positional.len(), &names).map_err(Error::new)?;
+    let values = given.into_iter().map(|given| match given {
+        Given::Positional(i) => args.positional[i].clone(),
+        Given::Keyword(k) => args.keywords[k].1.clone(),
+        given => unreachable!("a field given as {given:?}"),
+    });
+    Ok(values.collect())
+}
+
+// The variant of an enum called `name`, if it has one.
+fn case(ty: &Ref<Type>, name: &str) -> Option<Variant> {
+    let tag = ty.cases().iter().position(|case| case.name == name)?;
+    Some(case_value(ty, tag))
+}
+
+// A value of a variant that holds nothing, or else the function making
+// values of it.
+fn case_value(ty: &Ref<Type>, tag: usize) -> Variant {
+    let case = &ty.cases()[tag];
+    if case.fields == Fields::Unit {
+        let ty = ty.clone();
+        let tag = tag as u32;
+        let payload = Rc::new([]);
+        return Variant::Enum(Enum { ty, tag, payload });
+    }
+    let name = format!("{}.{}", ty.name, case.name);
+    let ty = ty.clone();
+    let native = Native {
+        name: name.as_str().into(),
+        func: Rc::new(move |_, args| {
+            let case = &ty.cases()[tag];
+            let payload = fields(&case.name, &case.params(), args)?;
+            let (ty, tag) = (ty.clone(), tag as u32);
+            Ok(Variant::Enum(Enum { ty, tag, payload }))
+        }),
+    };
+    Variant::Native(native)
+}
+
+// The method of a struct or enum overloading an operator.
+fn overload(value: &Variant, op: &Op) -> Option<Variant> {
+    user_method(value, op.method()?)
+}
+
+// The method of a struct or enum on the right of a binary operator
+// overloading it.
+fn reflected(value: &Variant, op: &Op) -> Option<Variant> {
+    user_method(value, op.reflected()?)
+}
+
+fn user_method(value: &Variant, name: &str) -> Option<Variant> {
+    match value {
+        Variant::Struct(s) => s.0.borrow().fields.method(name),
+        Variant::Enum(e) => e.ty.method(name),
+        _ => None,
+    }
+}
+
+fn recursion_limit() -> Error {
+    Error::new("recursion limit exceeded")
+}
+
+fn cell(value: Variant) -> Mut<Variant> {
+    Rc::new(RefCell::new(value))
+}
+
+fn no_method(value: &Variant, name: &str) -> Error {
+    Error::new(format!("`{}` has no method `{name}`", value.type_name()))
+}
+
+fn unary(op: &Op, value: Variant) -> Result<Variant, Error> {
+    match (op, &value) {
+        (Op::Neg, Variant::Int(n)) => n.0.checked_neg().map(Variant::from),
+        (Op::Neg, Variant::Float(x)) => Some((-x.0).into()),
+        (Op::Not, Variant::Bool(b)) => Some((!b).into()),
+        (Op::Not, Variant::Int(n)) => Some((!n.0).into()),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        let symbol = if *op == Op::Neg { "-" } else { "!" };
+        Error::new(format!(
+            "cannot apply `{symbol}` to `{}`",
+            value.type_name()
+        ))
+    })
+}
+
+fn symbol(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Rem => "%",
+        Op::Pow => "**",
+        Op::Shl => "<<",
+        Op::Shr => ">>>",
+        Op::Shra => ">>",
+        Op::BitAnd => "&",
+        Op::BitOr => "|",
+        Op::BitXor => "^",
+        Op::Lt => "<",
+        Op::Le => "<=",
+        Op::Gt => ">",
+        Op::Ge => ">=",
+        Op::Range => "..",
+        op => panic!("not a binary operator: {op:?}"),
+    }
+}
+
+fn binary(op: &Op, lhs: Variant, rhs: Variant) -> Result<Variant, Error> {
+    let float = |v: &Variant| match v {
+        Variant::Int(n) => Some(n.0 as f64),
+        Variant::Float(x) => Some(x.0),
+        _ => None,
+    };
+    let result = match (op, &lhs, &rhs) {
+        (Op::Eq, _, _) => Some(equal(&lhs, &rhs).into()),
+        (Op::Ne, _, _) => Some((!equal(&lhs, &rhs)).into()),
+        (Op::Lt | Op::Le | Op::Gt | Op::Ge, _, _) => compare(&lhs, &rhs).map(|ordering| {
+            match op {
+                Op::Lt => ordering.is_lt(),
+                Op::Le => ordering.is_le(),
+                Op::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }
+            .into()
+        }),
+        (Op::Range, Variant::Int(lo), Variant::Int(hi)) => Some(Variant::Range(lo.0, hi.0)),
+        (_, Variant::Int(a), Variant::Int(b)) => return int_op(op, a.0, b.0),
+        (Op::Add, Variant::Str(a), Variant::Str(b)) => {
+            Some(format!("{}{}", a.0, b.0).as_str().into())
+        }
+        (Op::Add, Variant::Array(a), Variant::Array(b)) => {
+            let mut items = a.0.borrow().clone();
+            items.extend(b.0.borrow().iter().cloned());
+            Some(items.into())
+        }
+        _ => match (float(&lhs), float(&rhs)) {
+            (Some(a), Some(b)) => match op {
+                Op::Add => Some(a + b),
+                Op::Sub => Some(a - b),
+                Op::Mul => Some(a * b),
+                Op::Div => Some(a / b),
+                Op::Rem => Some(a % b),
+                Op::Pow => Some(a.powf(b)),
+                _ => None,
+            }
+            .map(Variant::from),
+            _ => None,
+        },
+    };
+    result.ok_or_else(|| {
+        Error::new(format!(
+            "cannot apply `{}` to `{}` and `{}`",
+            symbol(op),
+            lhs.type_name(),
+            rhs.type_name()
+        ))
+    })
+}
+
+fn int_op(op: &Op, a: i128, b: i128) -> Result<Variant, Error> {
+    let shift = || u32::try_from(b).ok().filter(|&b| b < 128);
+    let value = match op {
+        Op::Add => a.checked_add(b),
+        Op::Sub => a.checked_sub(b),
+        Op::Mul => a.checked_mul(b),
+        Op::Div | Op::Rem if b == 0 => return Err(Error::new("division by zero")),
+        Op::Div => a.checked_div(b),
+        Op::Rem => a.checked_rem(b),
+        // A negative power is a fraction.
+        Op::Pow if b < 0 => return Ok((a as f64).powf(b as f64).into()),
+        Op::Pow => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        Op::Shl | Op::Shr | Op::Shra if shift().is_none() => {
+            return Err(Error::new(format!("cannot shift by {b}")))
+        }
+        Op::Shl => Some(a << b),
+        Op::Shr => Some(((a as u128) >> b) as i128),
+        Op::Shra => Some(a >> b),
+        Op::BitAnd => Some(a & b),
+        Op::BitOr => Some(a | b),
+        Op::BitXor => Some(a ^ b),
+        op => panic!("not an integer operator: {op:?}"),
+    };
+    value
+        .map(Variant::from)
+        .ok_or_else(|| Error::new(format!("integer overflow in `{a} {} {b}`", symbol(op))))
+}
+
+// `==` compares numbers by value and everything else by contents.
+fn equal(a: &Variant, b: &Variant) -> bool {
+    let all = |a: &[Variant], b: &[Variant]| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+    };
+    match (a, b) {
+        (Variant::Int(a), Variant::Float(b)) | (Variant::Float(b), Variant::Int(a)) => {
+            a.0 as f64 == b.0
+        }
+        (Variant::Tuple(a), Variant::Tuple(b)) => all(&a.0, &b.0),
+        (Variant::Array(a), Variant::Array(b)) => all(&a.0.borrow(), &b.0.borrow()),
+        (Variant::Struct(a), Variant::Struct(b)) => {
+            let (a, b) = (a.0.borrow(), b.0.borrow());
+            a.fields == b.fields && all(&a.values, &b.values)
+        }
+        (Variant::Enum(a), Variant::Enum(b)) => {
+            a.ty == b.ty && a.tag == b.tag && all(&a.payload, &b.payload)
+        }
+        (Variant::Map(a), Variant::Map(b)) => {
+            let (a, b) = (a.0.borrow(), b.0.borrow());
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, v)| matches!(b.get(k), Ok(Some(other)) if equal(v, other)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+fn compare(a: &Variant, b: &Variant) -> Option<Ordering> {
+    match (a, b) {
+        (Variant::Int(a), Variant::Int(b)) => Some(a.0.cmp(&b.0)),
+        (Variant::Int(a), Variant::Float(b)) => (a.0 as f64).partial_cmp(&b.0),
+        (Variant::Float(a), Variant::Int(b)) => a.0.partial_cmp(&(b.0 as f64)),
+        (Variant::Float(a), Variant::Float(b)) => a.0.partial_cmp(&b.0),
+        (Variant::Str(a), Variant::Str(b)) => Some(a.0.cmp(&b.0)),
+        _ => None,
+    }
+}
+
+fn in_range(value: &Variant, lo: &Variant, hi: &Variant) -> bool {
+    compare(lo, value).is_some_and(Ordering::is_le)
+        && compare(value, hi).is_some_and(Ordering::is_lt)
+}
+
+// Negative indices count from the end.
+fn position(index: &Variant, len: usize) -> Result<usize, Error> {
+    let Variant::Int(i) = index else {
+        return Err(Error::new(format!(
+            "expected an `Int` index, found `{}`",
+            index.type_name()
+        )));
+    };
+    let at = if i.0 < 0 { i.0 + len as i128 } else { i.0 };
+    if (0..len as i128).contains(&at) {
+        Ok(at as usize)
+    } else {
+        Err(Error::new(format!(
+            "index {} is out of range for length {len}",
+            i.0
+        )))
+    }
+}
+
+fn get_index(value: &Variant, index: &Variant) -> Result<Variant, Error> {
+    match value {
+        Variant::Array(a) => {
+            let items = a.0.borrow();
+            Ok(items[position(index, items.len())?].clone())
+        }
+        Variant::Tuple(t) => Ok(t.0[position(index, t.0.len())?].clone()),
+        Variant::Map(m) => m.0.borrow().get(index)?.cloned().ok_or_else(|| {
+            Error::new(match index {
+                Variant::Str(s) => format!("no key {:?} in the map", &*s.0),
+                index => format!("no key {index} in the map"),
+            })
+        }),
+        Variant::Bytes(b) => Ok((b.0[position(index, b.0.len())?] as i128).into()),
+        Variant::Str(s) => {
+            let chars = s.0.chars().collect::<Vec<_>>();
+            let c = chars[position(index, chars.len())?];
+            Ok(c.to_string().as_str().into())
+        }
+        value => Err(Error::new(format!(
+            "cannot index into a value of type `{}`",
+            value.type_name()
+        ))),
+    }
+}
+
+fn set_index(target: &Variant, index: &Variant, value: Variant) -> Result<(), Error> {
+    match target {
+        Variant::Array(a) => {
+            let mut items = a.0.borrow_mut();
+            let at = position(index, items.len())?;
+            items[at] = value;
+            Ok(())
+        }
+        Variant::Map(m) => m.0.borrow_mut().insert(index.clone(), value),
+        target => Err(Error::new(format!(
+            "cannot assign to an element of `{}`",
+            target.type_name()
+        ))),
+    }
+}
+
+fn field_index(target: &Variant, name: &str) -> Result<usize, Error> {
+    if let Variant::Struct(s) = target {
+        let s = s.0.borrow();
+        if let Some(i) = s.fields.field.iter().position(|f| f.name == name) {
+            return Ok(i);
+        }
+    }
+    Err(Error::new(format!(
+        "`{}` has no field `{name}`",
+        target.type_name()
+    )))
+}
+
+fn get_field(target: &Variant, name: &str) -> Result<Variant, Error> {
+    // The variants and functions of a type.
+    if let Variant::Type(ty) = target {
+        return (case(ty, name)).or_else(|| ty.method(name)).ok_or_else(|| {
+            Error::new(format!("`{}` has no variant or function `{name}`", ty.name))
+        });
+    }
+    if let Variant::Enum(e) = target {
+        if let Fields::Named(fields) = &e.case().fields {
+            if let Some(i) = fields.iter().position(|f| f.name == name) {
+                return Ok(e.payload[i].clone());
+            }
+        }
+    }
+    let i = field_index(target, name)?;
+    let Variant::Struct(s) = target else {
+        unreachable!()
+    };
+    let value = s.0.borrow().values[i].clone();
+    Ok(value)
+}
+
+fn set_field(target: &Variant, name: &str, value: Variant) -> Result<(), Error> {
+    if let Variant::Enum(e) = target {
+        return Err(Error::new(format!(
+            "cannot assign to `{name}`, values of the enum `{}` cannot be changed",
+            e.ty.name
+        )));
+    }
+    let i = field_index(target, name)?;
+    let Variant::Struct(s) = target else {
+        unreachable!()
+    };
+    s.0.borrow_mut().values[i] = value;
+    Ok(())
+}
+
+// Add the items of `value` to an array, or its entries to a map.
+fn extend(target: &Variant, value: Variant) -> Result<(), Error> {
+    match (target, &value) {
+        (Variant::Map(target), Variant::Map(source)) => {
+            let mut target = target.0.borrow_mut();
+            for (k, v) in source.0.borrow().iter() {
+                target.insert(k.clone(), v.clone())?;
+            }
+            Ok(())
+        }
+        (Variant::Map(_), _) => Err(Error::new(format!(
+            "cannot spread a value of type `{}` into a map",
+            value.type_name()
+        ))),
+        (Variant::Array(target), _) => {
+            let mut iter = iter(value)?;
+            let items = std::iter::from_fn(|| next(&mut iter));
+            target.0.borrow_mut().extend(items);
+            Ok(())
+        }
+        _ => unreachable!("`Extend` without an array or map"),
+    }
+}
+
+fn iter(value: Variant) -> Result<Iter, Error> {
+    match value {
+        Variant::Range(lo, hi) => Ok(Iter::Range(lo, hi)),
+        Variant::Array(_)
+        | Variant::Tuple(_)
+        | Variant::Map(_)
+        | Variant::Str(_)
+        | Variant::Bytes(_) => Ok(Iter::Items(value, 0)),
+        value => Err(Error::new(format!(
+            "cannot iterate over `{}`",
+            value.type_name()
+        ))),
+    }
+}
+
+fn next(iter: &mut Iter) -> Option<Variant> {
+    match iter {
+        Iter::Range(lo, hi) if *lo < *hi => {
+            let item = Variant::from(*lo);
+            // Below `hi`, so this cannot overflow.
+            *lo += 1;
+            Some(item)
+        }
+        Iter::Range(..) => None,
+        Iter::Items(value, at) => {
+            let item = match value {
+                Variant::Array(a) => a.0.borrow().get(*at).cloned(),
+                Variant::Tuple(t) => t.0.get(*at).cloned(),
+                // Maps give (key, value) pairs.
+                Variant::Map(m) => {
+                    m.0.borrow()
+                        .iter()
+                        .nth(*at)
+                        .map(|(k, v)| Variant::tuple(vec![k.clone(), v.clone()]))
+                }
+                Variant::Bytes(b) => b.0.get(*at).map(|&b| (b as i128).into()),
+                // Strings count bytes, to step over a char at a time.
+                Variant::Str(s) => {
+                    let c = s.0[*at..].chars().next()?;
+                    *at += c.len_utf8() - 1;
+                    Some(c.to_string().as_str().into())
+                }
+                _ => None,
+            };
+            *at += 1;
+            item
+        }
+    }
+}
+
+// The parts of a value in pattern order, or None if it has the wrong
+// shape.
+fn unpack(shape: &Shape, value: &Variant) -> Option<Vec<Variant>> {
+    match (shape, value) {
+        (Shape::Tuple(n), Variant::Tuple(t)) if t.0.len() == *n as usize => Some(t.0.to_vec()),
+        // Array patterns take apart tuples too, the rest being a tuple.
+        (Shape::Array(before, after, rest), Variant::Array(_) | Variant::Tuple(_)) => {
+            let items = match value {
+                Variant::Array(a) => a.0.borrow().clone(),
+                Variant::Tuple(t) => t.0.to_vec(),
+                _ => unreachable!(),
+            };
+            let (before, after) = (*before as usize, *after as usize);
+            let fits = match rest {
+                true => items.len() >= before + after,
+                false => items.len() == before + after,
+            };
+            if !fits {
+                return None;
+            }
+            let mut parts = items[..before].to_vec();
+            if *rest {
+                let middle = items[before..items.len() - after].to_vec();
+                parts.push(match value {
+                    Variant::Tuple(_) => Variant::tuple(middle),
+                    _ => middle.into(),
+                });
+            }
+            parts.extend_from_slice(&items[items.len() - after..]);
+            Some(parts)
+        }
+        (Shape::Struct(name, keys), Variant::Struct(s)) => {
+            let s = s.0.borrow();
+            if s.fields.name != *name.0 {
+                return None;
+            }
+            take_fields(keys, &s.fields.field, &s.values)
+        }
+        (Shape::Case(ty, name, keys), Variant::Enum(e)) => {
+            let case = e.case();
+            if e.ty.name != *ty.0 || case.name != *name.0 {
+                return None;
+            }
+            match &case.fields {
+                Fields::Named(fields) => take_fields(keys, fields, &e.payload),
+                _ if keys.len() == e.payload.len() => Some(e.payload.to_vec()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The values of the fields a struct pattern takes, by name or by place.
+fn take_fields(keys: &[Option<Str>], fields: &[Field], values: &[Variant]) -> Option<Vec<Variant>> {
+    keys.iter()
+        .enumerate()
+        .map(|(place, key)| {
+            let i = match key {
+                Some(key) => fields.iter().position(|f| f.name == *key.0)?,
+                None => place,
+            };
+            values.get(i).cloned()
+        })
+        .collect()
+}
+
+fn describe(shape: &Shape) -> String {
+    match shape {
+        Shape::Tuple(n) => format!("a tuple of {n}"),
+        Shape::Array(before, after, false) => format!("an array of {}", before + after),
+        Shape::Array(before, after, true) => format!("an array of at least {}", before + after),
+        Shape::Struct(name, _) | Shape::Case(_, name, _) => format!("a `{}`", name.0),
+    }
+}
+
+// `{value:spec}` in an interpolated string.
+fn format(value: &Variant, spec: Option<&FormatSpec>) -> Result<String, Error> {
+    let Some(spec) = spec else {
+        return Ok(value.to_string());
+    };
+    let bad_kind = |kind| {
+        Error::new(format!(
+            "cannot format `{}` with `{kind}`",
+            value.type_name()
+        ))
+    };
+    let (sign, prefix, body, numeric) = match value {
+        Variant::Int(n) if matches!(spec.kind, Some('e' | 'E')) => {
+            return format(&(n.0 as f64).into(), Some(spec));
+        }
+        Variant::Int(n) => {
+            let sign = if n.0 < 0 {
+                "-"
+            } else if spec.sign {
+                "+"
+            } else {
+                ""
+            };
+            let m = n.0.unsigned_abs();
+            let (prefix, digits) = match spec.kind {
+                None | Some('?') => ("", m.to_string()),
+                Some('x') => ("0x", format!("{m:x}")),
+                Some('X') => ("0x", format!("{m:X}")),
+                Some('o') => ("0o", format!("{m:o}")),
+                Some('b') => ("0b", format!("{m:b}")),
+                Some(kind) => return Err(bad_kind(kind)),
+            };
+            (sign, if spec.alternate { prefix } else { "" }, digits, true)
+        }
+        Variant::Float(x) => {
+            let sign = if x.0 < 0.0 {
+                "-"
+            } else if spec.sign {
+                "+"
+            } else {
+                ""
+            };
+            let m = x.0.abs();
+            let body = match (spec.kind, spec.precision) {
+                (Some('e'), Some(p)) => format!("{m:.p$e}"),
+                (Some('e'), None) => format!("{m:e}"),
+                (Some('E'), Some(p)) => format!("{m:.p$E}"),
+                (Some('E'), None) => format!("{m:E}"),
+                (None | Some('?'), Some(p)) => format!("{m:.p$}"),
+                (None | Some('?'), None) => format!("{m:?}"),
+                (Some(kind), _) => return Err(bad_kind(kind)),
+            };
+            (sign, "", body, true)
+        }
+        value => {
+            let mut body = match (spec.kind, value) {
+                (None, value) => value.to_string(),
+                (Some('?'), Variant::Str(s)) => format!("{:?}", &*s.0),
+                (Some('?'), value) => value.to_string(),
+                (Some(kind), _) => return Err(bad_kind(kind)),
+            };
+            if let Some(p) = spec.precision {
+                body = body.chars().take(p).collect();
+            }
+            ("", "", body, false)
+        }
+    };
+    let len = sign.len() + prefix.len() + body.chars().count();
+    let pad = spec.width.unwrap_or(0).saturating_sub(len);
+    if spec.zero && spec.align.is_none() && numeric {
+        return Ok(format!("{sign}{prefix}{}{body}", "0".repeat(pad)));
+    }
+    let align = spec
+        .align
+        .unwrap_or(if numeric { Align::Right } else { Align::Left });
+    let (left, right) = match align {
+        Align::Left => (0, pad),
+        Align::Right => (pad, 0),
+        Align::Center => (pad / 2, pad - pad / 2),
+    };
+    let fill = |n| spec.fill.to_string().repeat(n);
+    Ok(format!("{}{sign}{prefix}{body}{}", fill(left), fill(right)))
+}
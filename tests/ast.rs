@@ -1,4 +1,18 @@
-use sqwipt::{ast::{Expr, Parse}, lex::{Lex, Token}};
+use sqwipt::{
+    ast::{parse_programme, Align, Expr, Parse, Programme, Segment},
+    lex::{Lex, Token},
+    source::SourceMap,
+};
+
+// Check the first diagnostic from parsing a programme.
+macro_rules! bad {
+    ($s : expr, $code : expr, $message : expr) => {
+        let mut lex = Lex::new($s);
+        parse_programme(&mut lex);
+        let d = lex.diagnostics().iter().next().expect($s);
+        assert_eq!((d.code, d.message.as_str()), ($code, $message));
+    };
+}
 
 #[test]
 fn test_expr() {
@@ -31,15 +45,35 @@ fn test_expr() {
     expr!("1 + 2 + 3", "Some(Binary(Binary(Int(\"1\"), \"+\", Int(\"2\")), \"+\", Int(\"3\")))");
 
     expr!("1[2]", r#"Some(Index(Int("1"), "[", Int("2"), "]"))"#);
-    expr!("1(2)", r#"Some(Call(Int("1"), "(", [(Int("2"), None)], ")"))"#);
-    expr!("1(2)(3)", r#"Some(Call(Call(Int("1"), "(", [(Int("2"), None)], ")"), "(", [(Int("3"), None)], ")"))"#);
-    expr!("1[2](3)", r#"Some(Call(Index(Int("1"), "[", Int("2"), "]"), "(", [(Int("3"), None)], ")"))"#);
-    expr!("(1)(2)", "Some(Call(Paren(\"(\", Int(\"1\"), \")\"), \"(\", [(Int(\"2\"), None)], \")\"))");
+    expr!("1(2)", r#"Some(Call(Int("1"), "(", [(Positional(Int("2")), None)], ")"))"#);
+    expr!("1(2)(3)", r#"Some(Call(Call(Int("1"), "(", [(Positional(Int("2")), None)], ")"), "(", [(Positional(Int("3")), None)], ")"))"#);
+    expr!("1[2](3)", r#"Some(Call(Index(Int("1"), "[", Int("2"), "]"), "(", [(Positional(Int("3")), None)], ")"))"#);
+    expr!("(1)(2)", "Some(Call(Paren(\"(\", Int(\"1\"), \")\"), \"(\", [(Positional(Int(\"2\")), None)], \")\"))");
+
+    expr!("a = 1", r#"Some(Assign(Name("a"), "=", Int("1")))"#);
+    expr!("t = a + b", r#"Some(Assign(Name("t"), "=", Binary(Ident("a"), "+", Ident("b"))))"#);
+    expr!("a = b = 1", r#"Some(Assign(Name("a"), "=", Assign(Name("b"), "=", Int("1"))))"#);
+    expr!("a[1] += 2", r#"Some(Assign(Place(Index(Ident("a"), "[", Int("1"), "]")), "+=", Int("2")))"#);
+    expr!("p.x %= 3", r#"Some(Assign(Place(Dot(Ident("p"), ".", Ident("x"))), "%=", Int("3")))"#);
+    expr!("f = |x| y = x", r#"Some(Assign(Name("f"), "=", Closure(Closure { open: "|", formal_args: [(Name("x"), None)], close: "|", body: Assign(Name("y"), "=", Ident("x")) })))"#);
 
     expr!("|x| x + 1", "Some(Closure(Closure { open: \"|\", formal_args: [(Name(\"x\"), None)], close: \"|\", body: Binary(Ident(\"x\"), \"+\", Int(\"1\")) }))");
     expr!("\n  1", "Some(Block(Block { begin: \"\", items: [Int(\"1\")], end: \"\" }))");
     expr!("\n  1\n  2", "Some(Block(Block { begin: \"\", items: [Int(\"1\"), Int(\"2\")], end: \"\" }))");
-    expr!("\n  1\n  (\n    2\n  )", "Some(Block(Block { begin: \"\", items: [Int(\"1\"), Paren(\"(\", Block(Block { begin: \"\", items: [Int(\"2\")], end: \"\" }), \")\")], end: \"\" }))");
+    expr!("\n  1\n  (\n    2\n  )", "Some(Block(Block { begin: \"\", items: [Int(\"1\"), Paren(\"(\", Int(\"2\"), \")\")], end: \"\" }))");
+    expr!("\n  |x|\n    |y|\n      y\n  4", "Some(Block(Block { begin: \"\", items: [Closure(Closure { open: \"|\", formal_args: [(Name(\"x\"), None)], close: \"|\", body: Block(Block { begin: \"\", items: [Closure(Closure { open: \"|\", formal_args: [(Name(\"y\"), None)], close: \"|\", body: Block(Block { begin: \"\", items: [Ident(\"y\")], end: \"\" }) })], end: \"\" }) }), Int(\"4\")], end: \"\" }))");
+
+    expr!(r#""a{b}c""#, r#"Some(Interpolated("\"a{b}c\"", [Text("a"), Expr(Ident("b"), None), Text("c")]))"#);
+    expr!(r#""{{{f(1, 2)}}}""#, r#"Some(Interpolated("\"{{{f(1, 2)}}}\"", [Text("{"), Expr(Call(Ident("f"), "(", [(Positional(Int("1")), Some(",")), (Positional(Int("2")), None)], ")"), None), Text("}")]))"#);
+    expr!(r#""{x:>8.3}""#, r#"Some(Interpolated("\"{x:>8.3}\"", [Expr(Ident("x"), Some(FormatSpec { span: ">8.3", fill: ' ', align: Some(Right), sign: false, alternate: false, zero: false, width: Some(8), precision: Some(3), kind: None }))]))"#);
+    expr!(r#"r"{x}""#, r#"Some(Str("r\"{x}\""))"#);
+
+    // Newlines inside brackets join lines, unless they start a closure body.
+    expr!("f(1,\n    2\n)", r#"Some(Call(Ident("f"), "(", [(Positional(Int("1")), Some(",")), (Positional(Int("2")), None)], ")"))"#);
+    expr!("(1 +\n  2\n     + 3)", "Some(Paren(\"(\", Binary(Binary(Int(\"1\"), \"+\", Int(\"2\")), \"+\", Int(\"3\")), \")\"))");
+    expr!("f(|x|\n    x\n    x\n)", "Some(Call(Ident(\"f\"), \"(\", [(Positional(Closure(Closure { open: \"|\", formal_args: [(Name(\"x\"), None)], close: \"|\", body: Block(Block { begin: \"\", items: [Ident(\"x\"), Ident(\"x\")], end: \"\" }) })), None)], \")\"))");
+    expr!("f(|x|\n    x)", "Some(Call(Ident(\"f\"), \"(\", [(Positional(Closure(Closure { open: \"|\", formal_args: [(Name(\"x\"), None)], close: \"|\", body: Block(Block { begin: \"\", items: [Ident(\"x\")], end: \"\" }) })), None)], \")\"))");
+    expr!("f(|x|\n  x + 1)", "Some(Call(Ident(\"f\"), \"(\", [(Positional(Closure(Closure { open: \"|\", formal_args: [(Name(\"x\"), None)], close: \"|\", body: Block(Block { begin: \"\", items: [Binary(Ident(\"x\"), \"+\", Int(\"1\"))], end: \"\" }) })), None)], \")\"))");
 }
 
 #[test]
@@ -79,3 +113,413 @@ fn test_expr_bad() {
 //     item!("def fred(a):\n  a+1", "Some(Def { def: \"def\", ident: \"fred\", function: Function { open: \"(\", formal_args: [(Name(\"a\"), None)], close: \")\", colon: \":\", scope: Scope { items: [Expr { expr: Binary(Ident(\"a\"), \"+\", Int(\"1\")) }] } } })", r#"Eof("")"#);
 //     item!("def fred(a):\n  a+1\n  a+2", "Some(Def { def: \"def\", ident: \"fred\", function: Function { open: \"(\", formal_args: [(Name(\"a\"), None)], close: \")\", colon: \":\", scope: Scope { items: [Expr { expr: Binary(Ident(\"a\"), \"+\", Int(\"1\")) }, Expr { expr: Binary(Ident(\"a\"), \"+\", Int(\"2\")) }] } } })", r#"Eof("")"#);
 // }
+
+#[test]
+fn test_interpolated() {
+    let src = r#""total: {a + b:*^+#012.4x} and {c['k']}""#;
+    let mut lex = Lex::new(src);
+    let item = Expr::parse(&mut lex);
+    assert!(lex.diagnostics().is_empty());
+    let Some(Expr::Interpolated(_, segments)) = item else {
+        panic!("{item:?}");
+    };
+    let [Segment::Text(t), Segment::Expr(Expr::Binary(a, _, _), Some(spec)), Segment::Text(u), Segment::Expr(Expr::Index(..), None)] = segments.as_slice() else {
+        panic!("{segments:?}");
+    };
+    assert_eq!((t.as_str(), u.as_str()), ("total: ", " and "));
+    // The sub-expressions have spans in the original source.
+    let map = {
+        let mut map = SourceMap::new();
+        map.add("x.sqw", src);
+        map
+    };
+    let Expr::Ident(a) = &**a else { panic!() };
+    assert_eq!(map.locate(a).unwrap().range, 9..10);
+    assert_eq!(map.locate(&spec.span).unwrap().range, 15..25);
+    assert_eq!(
+        (spec.fill, spec.align, spec.sign, spec.alternate, spec.zero, spec.width, spec.precision, spec.kind),
+        ('*', Some(Align::Center), true, true, true, Some(12), Some(4), Some('x'))
+    );
+
+    macro_rules! bad_codes {
+        ($s: expr, $codes: expr) => {
+            let mut lex = Lex::new($s);
+            Expr::parse(&mut lex);
+            assert_eq!(lex.diagnostics().codes(), $codes, "{}", $s);
+        };
+    }
+    bad_codes!(r#""{x} {y""#, ["E0008"]);
+    bad_codes!(r#""{x} }""#, ["E0009"]);
+    bad_codes!(r#""{ }""#, ["E0010"]);
+    bad_codes!(r#""{x y}""#, ["E0102"]);
+    bad_codes!(r#""{x:q}""#, ["E0105"]);
+    bad_codes!(r#""{x:.}""#, ["E0105"]);
+    bad_codes!(r#""{$}""#, ["E0001"]);
+}
+
+#[test]
+fn test_precedence() {
+    // Fully parenthesised, to show the grouping.
+    fn grouped(e: &Expr) -> String {
+        match e {
+            Expr::Binary(lhs, op, rhs) => format!("({} {op} {})", grouped(lhs), grouped(rhs)),
+            Expr::Unary(op, e) => format!("({op}{})", grouped(e)),
+            Expr::Assign(target, op, value) => format!("({} {op} {})", target, grouped(value)),
+            Expr::Index(e, _, index, _) => format!("{}[{}]", grouped(e), grouped(index)),
+            e => e.to_string(),
+        }
+    }
+    let check = |src: &str, res: &str| {
+        let mut lex = Lex::new(src);
+        let e = Expr::parse(&mut lex).unwrap();
+        assert!(lex.is_eof() && lex.diagnostics().is_empty(), "{src}");
+        assert_eq!(grouped(&e), res, "{src}");
+    };
+
+    // Each level against the next tighter one, both ways round.
+    check("a = b .. c", "(a = (b .. c))");
+    check("a .. b or c", "(a .. (b or c))");
+    check("a or b .. c", "((a or b) .. c)");
+    check("a or b and c", "(a or (b and c))");
+    check("a and b or c", "((a and b) or c)");
+    check("a and b == c", "(a and (b == c))");
+    check("a < b and c", "((a < b) and c)");
+    check("a == b | c", "(a == (b | c))");
+    check("a | b != c", "((a | b) != c)");
+    check("a | b ^ c", "(a | (b ^ c))");
+    check("a ^ b | c", "((a ^ b) | c)");
+    check("a ^ b & c", "(a ^ (b & c))");
+    check("a & b ^ c", "((a & b) ^ c)");
+    check("a & b << c", "(a & (b << c))");
+    check("a >>> b & c", "((a >>> b) & c)");
+    check("a << b + c", "(a << (b + c))");
+    check("a - b >> c", "((a - b) >> c)");
+    check("a + b * c", "(a + (b * c))");
+    check("a % b - c", "((a % b) - c)");
+    check("-a * b", "((-a) * b)");
+    check("a / -b", "(a / (-b))");
+    check("-a ** b", "(-(a ** b))");
+    check("!a ** -b", "(!(a ** (-b)))");
+
+    // Associativity.
+    check("a - b + c", "((a - b) + c)");
+    check("a / b * c % d", "(((a / b) * c) % d)");
+    check("a ** b ** c", "(a ** (b ** c))");
+    check("a or b or c", "((a or b) or c)");
+    check("a = b += c", "(a = (b += c))");
+
+    // Comparison operators all sit on one level.
+    for op in ["==", "!=", "<", ">", "<=", ">="] {
+        check(&format!("a {op} b | c"), &format!("(a {op} (b | c))"));
+    }
+
+    // Closures still open with `|` where an operand cannot go.
+    check("|x| x | 1", "|x| x | 1");
+    check("f(|x| x) | g", "(f(|x| x) | g)");
+    check("(0..n).len()", "(0..n).len()");
+
+    // An index is a whole expression.
+    check("a[i + 1] * 2", "(a[(i + 1)] * 2)");
+    check("a[i < n and j]", "a[((i < n) and j)]");
+    check("a[0..n - 1][i]", "a[(0 .. (n - 1))][i]");
+
+    // Ranges and comparisons do not chain.
+    for src in ["a .. b .. c", "a < b <= c", "a == b == c", "a < b > c"] {
+        let mut lex = Lex::new(src);
+        Expr::parse(&mut lex);
+        assert_eq!(lex.diagnostics().codes(), ["E0107"], "{src}");
+    }
+}
+
+#[test]
+fn test_control_flow() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("if c\n  1", r#"Some(If([("if", Ident("c"), Block(Block { begin: "", items: [Int("1")], end: "" }))], None))"#);
+    expr!(
+        "if a\n  1\nelif b\n  2\nelse\n  3",
+        r#"Some(If([("if", Ident("a"), Block(Block { begin: "", items: [Int("1")], end: "" })), ("elif", Ident("b"), Block(Block { begin: "", items: [Int("2")], end: "" }))], Some(("else", Block(Block { begin: "", items: [Int("3")], end: "" })))))"#
+    );
+    expr!("x = if c\n  1\nelse\n  2", r#"Some(Assign(Name("x"), "=", If([("if", Ident("c"), Block(Block { begin: "", items: [Int("1")], end: "" }))], Some(("else", Block(Block { begin: "", items: [Int("2")], end: "" }))))))"#);
+    expr!("while i < n\n  i += 1", r#"Some(While("while", Binary(Ident("i"), "<", Ident("n")), Block(Block { begin: "", items: [Assign(Name("i"), "+=", Int("1"))], end: "" })))"#);
+    expr!("for x in 0..n\n  print(x)", r#"Some(For("for", Name("x"), "in", Binary(Int("0"), "..", Ident("n")), Block(Block { begin: "", items: [Call(Ident("print"), "(", [(Positional(Ident("x")), None)], ")")], end: "" })))"#);
+    expr!("return", r#"Some(Return("return", None))"#);
+    expr!("return a + 1", r#"Some(Return("return", Some(Binary(Ident("a"), "+", Int("1")))))"#);
+    expr!("break", r#"Some(Break("break"))"#);
+    expr!("continue", r#"Some(Continue("continue"))"#);
+
+    // Blocks nest and close properly inside closures and other blocks.
+    let src = "|n|\n    for i in 0..n\n        if i % 2 == 0\n            continue\n        elif i > 10\n            break\n        print(i)\n    return n\n";
+    let mut lex = Lex::new(src);
+    let Programme::Good(exprs) = parse_programme(&mut lex) else {
+        panic!("{:?}", lex.diagnostics());
+    };
+    assert_eq!(
+        exprs[0].to_string(),
+        "|n|\n    for i in 0..n\n        if i % 2 == 0\n            continue\n        elif i > 10\n            break\n        print(i)\n    return n"
+    );
+
+    bad!("if c 1", "E0108", "expected an indented block, found `1`");
+    bad!("while\n  1", "E0100", "expected expression after `while`, found indented block");
+    bad!("for * in x\n  1", "E0104", "expected a pattern, found `*`");
+    bad!("for x of y\n  1", "E0104", "expected `in`, found `of`");
+    bad!("else\n  1", "E0100", "expected expression, found `else`");
+
+    // A line after a block starts a new expression, even one that begins
+    // like an operator.
+    let mut lex = Lex::new("for i in x\n  f(i)\n-1\n|a|\n  a\n(2, 3)\n");
+    let Programme::Good(exprs) = parse_programme(&mut lex) else {
+        panic!("{:?}", lex.diagnostics());
+    };
+    let exprs = exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(exprs, ["for i in x\n    f(i)", "-1", "|a|\n    a", "(2, 3)"]);
+}
+
+#[test]
+fn test_let() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("let x = 1", r#"Some(Let("let", None, Name("x"), "=", Int("1")))"#);
+    expr!("let mut x = a + 1", r#"Some(Let("let", Some("mut"), Name("x"), "=", Binary(Ident("a"), "+", Int("1"))))"#);
+    expr!("let f = |x| x", r#"Some(Let("let", None, Name("f"), "=", Closure(Closure { open: "|", formal_args: [(Name("x"), None)], close: "|", body: Ident("x") })))"#);
+
+    bad!("let * = 2", "E0104", "expected a pattern, found `*`");
+    bad!("let mut = 2", "E0104", "expected a pattern, found `=`");
+    bad!("let x 2", "E0104", "expected `=`, found `2`");
+    bad!("let x =", "E0100", "expected expression after `=`, found end of file");
+}
+
+#[test]
+fn test_patterns() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("(a, b) = f()", r#"Some(Assign(Tuple("(", [(Name("a"), Some(",")), (Name("b"), None)], ")"), "=", Call(Ident("f"), "(", [], ")")))"#);
+    expr!("(a, (_, c[0])) = x", r#"Some(Assign(Tuple("(", [(Name("a"), Some(",")), (Tuple("(", [(Wildcard("_"), Some(",")), (Place(Index(Ident("c"), "[", Int("0"), "]")), None)], ")"), None)], ")"), "=", Ident("x")))"#);
+    expr!("[first, ..rest] = xs", r#"Some(Assign(Array("[", [(Name("first"), Some(",")), (Rest("..", Some("rest")), None)], "]"), "=", Ident("xs")))"#);
+    expr!("let [_, .., last] = xs", r#"Some(Let("let", None, Array("[", [(Wildcard("_"), Some(",")), (Rest("..", None), Some(",")), (Name("last"), None)], "]"), "=", Ident("xs")))"#);
+    // Fields that are not plain names are taken by place.
+    expr!("let Some((a, _), None, P(1)) = p", r#"Some(Let("let", None, Struct("Some", "(", [(Positional(Tuple("(", [(Name("a"), Some(",")), (Wildcard("_"), None)], ")")), Some(",")), (Name("None"), Some(",")), (Positional(Struct("P", "(", [(Positional(Literal(Int("1"))), None)], ")")), None)], ")"), "=", Ident("p")))"#);
+    expr!("let Point(x, y=(a, b)) = p", r#"Some(Let("let", None, Struct("Point", "(", [(Name("x"), Some(",")), (Named("y", "=", Tuple("(", [(Name("a"), Some(",")), (Name("b"), None)], ")")), None)], ")"), "=", Ident("p")))"#);
+    // Struct-like variants take their fields in braces, and a variant may be named with its enum.
+    expr!("let Rect{w, h=1} = r", r#"Some(Let("let", None, Struct("Rect", "{", [(Name("w"), Some(",")), (Named("h", "=", Literal(Int("1"))), None)], "}"), "=", Ident("r")))"#);
+    expr!("let Some(Shape.Rect{w}) = s", r#"Some(Let("let", None, Struct("Some", "(", [(Positional(Struct("Shape.Rect", "{", [(Name("w"), None)], "}")), None)], ")"), "=", Ident("s")))"#);
+    expr!("let Shape.Empty = s", r#"Some(Let("let", None, Name("Shape.Empty"), "=", Ident("s")))"#);
+    expr!("for (k, v) in m\n    print(k)", r#"Some(For("for", Tuple("(", [(Name("k"), Some(",")), (Name("v"), None)], ")"), "in", Ident("m"), Block(Block { begin: "", items: [Call(Ident("print"), "(", [(Positional(Ident("k")), None)], ")")], end: "" })))"#);
+    expr!("|(k, v), [x]| k", r#"Some(Closure(Closure { open: "|", formal_args: [(Pattern(Tuple("(", [(Name("k"), Some(",")), (Name("v"), None)], ")")), Some(",")), (Pattern(Array("[", [(Name("x"), None)], "]")), None)], close: "|", body: Ident("k") }))"#);
+    // Brackets around a single pattern only group it.
+    expr!("let (a,) = t", r#"Some(Let("let", None, Tuple("(", [(Name("a"), Some(","))], ")"), "=", Ident("t")))"#);
+    let mut lex = Lex::new("let (a) = t");
+    assert_eq!(format!("{:?}", Expr::parse(&mut lex)), r#"Some(Let("let", None, Name("a"), "=", Ident("t")))"#);
+
+    bad!("let [a, ..b, ..c] = x", "E0109", "an array pattern can only have one `..`");
+    bad!("let (a, *) = x", "E0104", "expected a pattern, found `*`");
+    bad!("let P(*) = x", "E0104", "expected a pattern, found `*`");
+    bad!("let Shape.(w) = x", "E0104", "expected a variant after `.`, found `(`");
+    bad!("[a, ..b, ..c] = x", "E0109", "an array pattern can only have one `..`");
+    bad!("[a, ..b + 1] = x", "E0106", "cannot assign to `..b + 1`");
+    bad!("(a, 1) = x", "E0106", "cannot assign to `1`");
+    bad!("(a, b) += x", "E0106", "cannot assign to `(a, b)`");
+    bad!("for .. in x\n  1", "E0104", "expected a pattern, found `..`");
+
+    // Calls look like struct patterns but are rarely meant as one.
+    let mut lex = Lex::new("P(x) = p");
+    parse_programme(&mut lex);
+    let d = lex.diagnostics().iter().next().unwrap();
+    assert_eq!(d.help, ["to take a struct apart, use `let P(x) = ..`"]);
+}
+
+#[test]
+fn test_match() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("match x\n    1 => a\n    _ => b", r#"Some(Match("match", Ident("x"), [Arm { pattern: Literal(Int("1")), guard: None, arrow: "=>", body: Ident("a") }, Arm { pattern: Wildcard("_"), guard: None, arrow: "=>", body: Ident("b") }]))"#);
+    expr!("match p\n    (0, -1.5) => a\n    1..10 => b\n    (x, _) if x > 0 =>\n        x\n    \"s\" => c", r#"Some(Match("match", Ident("p"), [Arm { pattern: Tuple("(", [(Literal(Int("0")), Some(",")), (Literal(Unary("-", Float("1.5"))), None)], ")"), guard: None, arrow: "=>", body: Ident("a") }, Arm { pattern: Range(Int("1"), "..", Int("10")), guard: None, arrow: "=>", body: Ident("b") }, Arm { pattern: Tuple("(", [(Name("x"), Some(",")), (Wildcard("_"), None)], ")"), guard: Some(("if", Binary(Ident("x"), ">", Int("0")))), arrow: "=>", body: Block(Block { begin: "", items: [Ident("x")], end: "" }) }, Arm { pattern: Literal(Str("\"s\"")), guard: None, arrow: "=>", body: Ident("c") }]))"#);
+
+    bad!("match x", "E0108", "expected an indented block of arms, found end of file");
+    bad!("match x\n  1 a", "E0104", "expected `=>`, found `a`");
+    bad!("match x\n  * => 1", "E0104", "expected a pattern, found `*`");
+    bad!("match x\n  1 if => 1", "E0100", "expected expression after `if`, found `=>`");
+}
+
+#[test]
+fn test_formal_args() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("|| 1", r#"Some(Closure(Closure { open: "|", formal_args: [], close: "|", body: Int("1") }))"#);
+    expr!("|x, y=5, z: u64 = 3| x", r#"Some(Closure(Closure { open: "|", formal_args: [(Name("x"), Some(",")), (NameWithDefault(Name("y"), "=", Int("5")), Some(",")), (NameWithDefault(Typed("z", ":", "u64"), "=", Int("3")), None)], close: "|", body: Ident("x") }))"#);
+    expr!("|a, *rest, key=a & 1| a", r#"Some(Closure(Closure { open: "|", formal_args: [(Name("a"), Some(",")), (Rest("*", Some("rest")), Some(",")), (NameWithDefault(Name("key"), "=", Binary(Ident("a"), "&", Int("1"))), None)], close: "|", body: Ident("a") }))"#);
+    expr!("|*, key: Int| key", r#"Some(Closure(Closure { open: "|", formal_args: [(Rest("*", None), Some(",")), (Typed("key", ":", "Int"), None)], close: "|", body: Ident("key") }))"#);
+
+    bad!("|x=| x", "E0100", "expected expression after `=`, found `|`");
+    bad!("|x: 1| x", "E0104", "expected a type, found `1`");
+    bad!("|*a, *b| a", "E0109", "a closure can only have one `*` parameter");
+    bad!("|x", "E0104", "expected `|`, found end of file");
+}
+
+#[test]
+fn test_keyword_args() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("f(x, scale=2.0)", r#"Some(Call(Ident("f"), "(", [(Positional(Ident("x")), Some(",")), (Keyword("scale", "=", Float("2.0")), None)], ")"))"#);
+    expr!("a.b(c=d + 1)", r#"Some(Dot(Ident("a"), ".", Call(Ident("b"), "(", [(Keyword("c", "=", Binary(Ident("d"), "+", Int("1"))), None)], ")")))"#);
+    // Only a plain name makes a keyword argument.
+    expr!("f(a[0] = 1)", r#"Some(Call(Ident("f"), "(", [(Positional(Assign(Place(Index(Ident("a"), "[", Int("0"), "]")), "=", Int("1"))), None)], ")"))"#);
+
+    let mut lex = Lex::new("f(a=1, 2, b=3, 4)");
+    parse_programme(&mut lex);
+    let messages = lex
+        .diagnostics()
+        .iter()
+        .map(|d| (d.code, d.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(messages, [("E0110", "positional argument after a keyword argument"); 2]);
+}
+
+#[test]
+fn test_collections() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+            assert!(lex.is_eof());
+        };
+    }
+    expr!("[1, 2, 3]", r#"Some(Array("[", [(Int("1"), Some(",")), (Int("2"), Some(",")), (Int("3"), None)], "]"))"#);
+    expr!("[]", r#"Some(Array("[", [], "]"))"#);
+    expr!("[..xs, 4,]", r#"Some(Array("[", [(Spread("..", Some(Ident("xs"))), Some(",")), (Int("4"), Some(","))], "]"))"#);
+    expr!("{\"k\": v, ..m}", r#"Some(Map("{", [(Pair(Str("\"k\""), ":", Ident("v")), Some(",")), (Spread("..", Ident("m")), None)], "}"))"#);
+    expr!("{}[k]", r#"Some(Index(Map("{", [], "}"), "[", Ident("k"), "]"))"#);
+    // A `[` after a value indexes it.
+    expr!("a[0]", r#"Some(Index(Ident("a"), "[", Int("0"), "]"))"#);
+    // An array on the left of `=` is a pattern.
+    expr!("[a, .., _] = xs", r#"Some(Assign(Array("[", [(Name("a"), Some(",")), (Rest("..", None), Some(",")), (Wildcard("_"), None)], "]"), "=", Ident("xs")))"#);
+
+    // Newlines inside brackets are ignored.
+    let mut lex = Lex::new("{\n    \"a\": 1,\n    \"b\": [\n        2,\n    ],\n}");
+    let item = Expr::parse(&mut lex).unwrap();
+    assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+    assert_eq!(item.to_string(), "{\"a\": 1, \"b\": [2,],}");
+
+    bad!("{a 1}", "E0104", "expected `:` after a map key, found `1`");
+    bad!("{a: }", "E0100", "expected expression after `:`, found `}`");
+    bad!("{..}", "E0100", "expected expression after `..`, found `}`");
+    bad!("[1, 2", "E0102", "expected `]`, found end of file");
+}
+
+#[test]
+fn test_declarations() {
+    macro_rules! expr {
+        ($s: expr, $res: expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+            assert_eq!(format!("{item:?}"), $res);
+            assert_eq!(item.unwrap().to_string(), $s);
+        };
+    }
+    expr!("struct Point x y", r#"Some(Struct("struct", "Point", ["x", "y"]))"#);
+    expr!("enum Shape Empty Circle(Float) Rect{w, h}", r#"Some(Enum("enum", "Shape", [Case { name: "Empty", brackets: None, fields: [] }, Case { name: "Circle", brackets: Some(("(", ")")), fields: [("Float", None)] }, Case { name: "Rect", brackets: Some(("{", "}")), fields: [("w", Some(",")), ("h", None)] }]))"#);
+    expr!("enum Colour Red Green", r#"Some(Enum("enum", "Colour", [Case { name: "Red", brackets: None, fields: [] }, Case { name: "Green", brackets: None, fields: [] }]))"#);
+    expr!("impl Point\n    fn norm(self)\n        self.x", r#"Some(Impl("impl", None, "Point", [Fn("fn", "norm", Closure { open: "(", formal_args: [(Name("self"), None)], close: ")", body: Block(Block { begin: "", items: [Dot(Ident("self"), ".", Ident("x"))], end: "" }) })]))"#);
+    expr!("trait Shape\n    fn area(self)\n    fn double(self)\n        self.area() * 2", r#"Some(Trait("trait", "Shape", [Signature("fn", "area", "(", [(Name("self"), None)], ")"), Fn("fn", "double", Closure { open: "(", formal_args: [(Name("self"), None)], close: ")", body: Block(Block { begin: "", items: [Binary(Dot(Ident("self"), ".", Call(Ident("area"), "(", [], ")")), "*", Int("2"))], end: "" }) })]))"#);
+    expr!("impl Shape for Int", r#"Some(Impl("impl", Some(("Shape", "for")), "Int", []))"#);
+    expr!("Point(1, -2)", r#"Some(Call(Ident("Point"), "(", [(Positional(Int("1")), Some(",")), (Positional(Unary("-", Int("2"))), None)], ")"))"#);
+
+    bad!("struct 1", "E0104", "expected a name after `struct`, found `1`");
+    // Types are called like functions, not by writing arguments after them.
+    bad!("Point 1 -2", "E0101", "expected newline, found `1`");
+    bad!("enum E\n  A\n  1", "E0104", "expected a name, found `1`");
+    bad!("enum E A(1)", "E0104", "expected a name, found `1`");
+    bad!("impl P", "E0108", "expected an indented block of `fn`s, found end of file");
+    bad!("impl P\n  x = 1", "E0104", "expected `fn`, found `x`");
+    bad!("impl P\n  fn f\n    1", "E0104", "expected `(`, found indented block");
+    bad!("impl Shape for 1", "E0104", "expected a name after `for`, found `1`");
+    // Only a trait's methods may have no body.
+    bad!("impl P\n  fn f()", "E0108", "expected an indented block, found end of block");
+    // A `fn` is only allowed in an `impl` or `trait`.
+    bad!("fn f()\n  1", "E0100", "expected expression, found `fn`");
+}
+
+#[test]
+fn test_assign_target() {
+    macro_rules! bad_target {
+        ($s : expr, $res : expr, $message : expr, $target : expr) => {
+            let mut lex = Lex::new($s);
+            let item = Expr::parse(&mut lex);
+            assert_eq!(format!("{item:?}"), $res);
+            let d = lex.diagnostics().iter().next().unwrap();
+            assert_eq!((d.code, d.message.as_str(), **d.primary_span().unwrap()), ("E0106", $message, $target));
+        };
+    }
+    bad_target!("1 = x", r#"Some(Assign(Bad("1"), "=", Ident("x")))"#, "cannot assign to `1`", "1");
+    bad_target!("a + b -= 1", r#"Some(Assign(Bad("a + b"), "-=", Int("1")))"#, "cannot assign to `a + b`", "a + b");
+    bad_target!("f(x) = 2", r#"Some(Assign(Bad("f(x)"), "=", Int("2")))"#, "cannot assign to `f(x)`", "f(x)");
+}
+
+#[test]
+fn test_display() {
+    let display = |src| {
+        let mut lex = Lex::new(src);
+        let Programme::Good(exprs) = parse_programme(&mut lex) else {
+            panic!("{src}");
+        };
+        exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
+    };
+    assert_eq!(display("f(1,2,  x)"), "f(1, 2, x)");
+    assert_eq!(display("(1,2,)"), "(1, 2,)");
+    assert_eq!(display("-a.b[ 1 ]*2"), "-a.b[1] * 2");
+    assert_eq!(display("|a,b|a+b"), "|a, b| a + b");
+    assert_eq!(display("a[0]+=b=1"), "a[0] += b = 1");
+    assert_eq!(display("|a|\n  |b|\n      a+b\n  a"), "|a|\n    |b|\n        a + b\n    a");
+    assert_eq!(display("\"x = {x:>4}\""), "\"x = {x:>4}\"");
+}
@@ -0,0 +1,314 @@
+use sqwipt::{ast::parse_programme, compile::Compiler, lex::Lex};
+
+// Codes and messages of the compiler diagnostics for `src`.
+fn compile(src: &str) -> Vec<(&'static str, String)> {
+    let mut lex = Lex::new(src);
+    let programme = parse_programme(&mut lex);
+    assert!(lex.diagnostics().is_empty(), "{:?}", lex.diagnostics());
+    let mut compiler = Compiler::new();
+    compiler.compile(&programme);
+    compiler
+        .take_diagnostics()
+        .into_iter()
+        .map(|d| (d.code, d.message))
+        .collect()
+}
+
+// The same as "code message" strings.
+fn codes(src: &str) -> Vec<String> {
+    compile(src)
+        .into_iter()
+        .map(|(code, message)| format!("{code} {message}"))
+        .collect()
+}
+
+#[test]
+fn test_let() {
+    let ok = |src| assert_eq!(compile(src), [], "{src:?}");
+    ok("x = 1\nx = 2\nx += 1\n");
+    ok("let x = 1\nprint(x)\n");
+    ok("let mut x = 1\nx += 1\n");
+    // Shadowing makes a new binding.
+    ok("let x = 1\nlet x = x + 1\n");
+    ok("let x = 1\nif c\n  let x = 2\n  print(x)\n");
+    // Only the name itself is immutable.
+    ok("let a = f()\na[0] = 1\na.b = 2\n");
+    // Loop variables and parameters are plain bindings.
+    ok("for i in 0..3\n  i = 2\n|a| a = 1\n");
+    // A `let` in a block ends with it.
+    ok("if c\n  let x = 1\nx = 2\n");
+    // A closure assigning to a captured `let mut` uses the `mut`.
+    ok("let mut n = 0\nadd = |d|\n  n += d\n");
+
+    let bad = |src, codes: &[(&str, &str)]| {
+        let diagnostics = compile(src);
+        let found = diagnostics
+            .iter()
+            .map(|(code, message)| (*code, message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(found, codes, "{src:?}");
+    };
+    let immutable = ("E0300", "cannot assign twice to immutable variable `x`");
+    bad("let x = 1\nx = 2\n", &[immutable]);
+    bad("let x = 1\nx += 1\n", &[immutable]);
+    bad("let x = 1\nif c\n  x = 2\n", &[immutable]);
+    bad("let x = 1\nf = |y|\n  x = y\n", &[immutable]);
+    bad("x = 1\nlet x = 2\nx = 3\n", &[immutable]);
+    bad(
+        "let mut x = 1\nprint(x)\n",
+        &[("W0300", "variable `x` does not need to be mutable")],
+    );
+    bad(
+        "if c\n  let mut x = 1\n  let x = 2\n  x = 3\n",
+        &[
+            immutable,
+            ("W0300", "variable `x` does not need to be mutable"),
+        ],
+    );
+}
+
+#[test]
+fn test_patterns() {
+    let ok = |src| assert_eq!(compile(src), [], "{src:?}");
+    ok("(a, b) = (1, 2)\n(a, b) = (b, a)\n");
+    ok("let mut (lo, hi) = r\nlo += 1\n");
+    ok("let [_, ..rest] = xs\nlet P(x, y=(a, b)) = p\n");
+    ok("for (i, x) in xs\n  i = x\n");
+    ok("f = |(a, b)|\n  a = b\n");
+
+    assert_eq!(
+        codes("let (a, [b, ..c]) = x\n(a, b) = y\nc = z\n"),
+        [
+            "E0300 cannot assign twice to immutable variable `a`",
+            "E0300 cannot assign twice to immutable variable `b`",
+            "E0300 cannot assign twice to immutable variable `c`",
+        ]
+    );
+    assert_eq!(
+        codes("let P(x, y=z) = p\nx = 1\nz = 2\ny = 3\n"),
+        [
+            "E0300 cannot assign twice to immutable variable `x`",
+            "E0300 cannot assign twice to immutable variable `z`",
+        ]
+    );
+    assert_eq!(
+        codes("let mut (a, b) = x\n"),
+        ["W0300 variables `a`, `b` do not need to be mutable"]
+    );
+}
+
+#[test]
+fn test_loops() {
+    let ok = |src| assert_eq!(compile(src), [], "{src:?}");
+    ok("while c\n  if d\n    break\n  continue\n");
+    ok("for i in xs\n  while c\n    break\n  continue\n");
+
+    assert_eq!(codes("break\n"), ["E0302 `break` outside of a loop"]);
+    // A closure cannot leave a loop it is inside.
+    assert_eq!(
+        codes("for i in x\n  f = |y|\n    continue\n"),
+        ["E0302 `continue` outside of a loop"]
+    );
+}
+
+#[test]
+fn test_match() {
+    let ok = |src| assert_eq!(compile(src), [], "{src:?}");
+    ok("match x\n  0 => a\n  1..3 => b\n  (y, \"s\") if y => c\n  _ => d\n");
+    ok("for i in xs\n  match i\n    0 => break\n    _ => continue\n");
+    // A guard can fail, so later arms are still reachable.
+    ok("match x\n  y if y => 1\n  _ => 2\n");
+    // Variants that hold nothing are tested for, not bound.
+    ok("enum E A B(Int)\nmatch x\n  A => 1\n  None => 2\n  B(1) => 3\n  B(_) => 4\n  Some(y) => y\n");
+
+    assert_eq!(
+        codes("match x\n  y => 1\n  0 => 2\n"),
+        ["W0301 unreachable match arm"]
+    );
+    assert_eq!(
+        codes("match x\n  (_, y) => 1\n  (0, 1) => 2\n"),
+        ["W0301 unreachable match arm"]
+    );
+    assert_eq!(
+        codes("match x\n  Some(y) => 1\n  None => 2\n  Some((1, 2)) => 3\n  None() => 4\n"),
+        ["W0301 unreachable match arm", "W0301 unreachable match arm"]
+    );
+    // Every variant of an enum needs an arm, unless one matches anything.
+    let colors = "enum Color Red Green Blue\nmatch c\n  Red => 1\n";
+    assert_eq!(compile(&format!("{colors}  Green => 2\n  Blue => 3\n")), []);
+    assert_eq!(compile(&format!("{colors}  _ => 2\n")), []);
+    // A variant named with its enum is the same variant.
+    assert_eq!(
+        compile(&format!("{colors}  Color.Green => 2\n  Color.Blue => 3\n")),
+        []
+    );
+    assert_eq!(
+        codes(&format!("{colors}  Color.Red => 2\n  _ => 3\n")),
+        ["W0301 unreachable match arm"]
+    );
+    ok("match x\n  Some(_) => 1\n  None => 2\n");
+    assert_eq!(
+        codes(&format!("{colors}  Green => 2\n")),
+        ["W0302 match does not cover `Blue`"]
+    );
+    assert_eq!(
+        codes(colors),
+        ["W0302 match does not cover `Green` and `Blue`"]
+    );
+    assert_eq!(
+        codes("enum E A B C D\nmatch x\n  A => 1\n"),
+        ["W0302 match does not cover `B`, `C` and `D`"]
+    );
+    // Arms that can fail do not cover their variant.
+    assert_eq!(
+        codes("match x\n  Some(1) => 1\n  Some(y) if y => 2\n  None => 3\n"),
+        ["W0302 match does not cover `Some`"]
+    );
+    // A capitalised name is a variant, which must be one that holds
+    // nothing.
+    assert_eq!(
+        codes("match x\n  Red => 1\n  Some => 2\n  _ => 3\n"),
+        [
+            "E0309 no variant named `Red`",
+            "E0309 variant `Some` holds values, so its name alone does not match it"
+        ]
+    );
+    assert_eq!(
+        codes(
+            "let Option.Red = x
+match x
+  Option.Red(y) => y
+  _ => 0
+"
+        ),
+        [
+            "E0309 no variant named `Option.Red`",
+            "E0309 no variant named `Option.Red`"
+        ]
+    );
+    assert_eq!(
+        codes("let 1 = 2\n"),
+        ["E0301 `1` does not match every value"]
+    );
+    assert_eq!(
+        codes("for 0..2 in x\n  1\n"),
+        ["E0301 `0..2` does not match every value"]
+    );
+}
+
+#[test]
+fn test_params() {
+    let ok = |src| assert_eq!(compile(src), [], "{src:?}");
+    ok("|a, b=a, *rest, c: Int = 1| a\n");
+    // A default sees the names around the closure and earlier parameters.
+    ok("let x = 1\n|y=x, x=y| x\n");
+
+    assert_eq!(
+        codes("|a, a| a\n|b, *b| b\n|(c, d), [c]| c\n"),
+        [
+            "E0304 parameter `a` is named more than once",
+            "E0304 parameter `b` is named more than once",
+            "E0304 parameter `c` is named more than once",
+        ]
+    );
+}
+
+#[test]
+fn test_declarations() {
+    assert_eq!(
+        compile("struct P x y\nimpl P\n  fn f(self)\n    P(1, y=2)\nenum E A B\nE.A\ntrait T\n  fn g(self)\nimpl T for P\n  fn g(self)\n    T\n"),
+        []
+    );
+    assert_eq!(
+        codes("struct P x x\nenum E A A\nimpl E\n  fn f()\n    1\n  fn f()\n    2\n"),
+        [
+            "E0308 field `x` is named more than once",
+            "E0308 variant `A` is named more than once",
+            "E0308 method `f` is named more than once",
+        ]
+    );
+    assert_eq!(
+        codes("enum E A{x, x} B(Int, Int)\n"),
+        ["E0308 field `x` is named more than once"]
+    );
+    assert_eq!(
+        codes("trait T\n  fn f(self)\n  fn f(self)\n    1\n"),
+        ["E0308 method `f` is named more than once"]
+    );
+    assert_eq!(
+        codes("struct P x y\nP(1)\nP(1, 2, 3)\nP(1, z=2)\n"),
+        [
+            "E0306 `P` takes 2 arguments but 1 was given",
+            "E0306 `P` takes 2 arguments but 3 were given",
+            "E0306 `P` has no parameter named `z`",
+        ]
+    );
+    // Variants bound by their own names are checked the same way.
+    assert_eq!(
+        codes("enum S\n  A\n  B(Int)\n  C{w, h}\nB(1, 2)\nC(1, d=2)\n"),
+        [
+            "E0306 `B` takes 1 argument but 2 were given",
+            "E0306 `C` has no parameter named `d`",
+        ]
+    );
+}
+
+#[test]
+fn test_spread() {
+    assert_eq!(compile("[a, ..] = xs\n[..xs, 1]\n"), []);
+    assert_eq!(
+        compile("[1, ..]\n"),
+        [("E0307", "expected a value to spread after `..`".to_string())]
+    );
+}
+
+#[test]
+fn test_keyword_args() {
+    let ok = |src| assert_eq!(compile(src), [], "{src:?}");
+    ok("let f = |a, *, b=1| a\nf(1, b=2)\nf(a=1)\n");
+    // Only names a `let` binds to a closure are checked.
+    ok("f = |a| a\nf(b=1)\nlet mut g = |a| a\ng()\ng = f\n");
+
+    assert_eq!(
+        codes("let f = |a, *, b=1| a\nf()\nf(1, 2)\nf(1, c=2)\nf(1, a=1)\n"),
+        [
+            "E0306 `f` takes 1 argument but 0 were given",
+            "E0306 `f` takes 1 argument but 2 were given",
+            "E0306 `f` has no parameter named `c`",
+            "E0306 `f` got more than one value for `a`",
+        ]
+    );
+    assert_eq!(
+        codes("g(x=1, x=2)\n"),
+        ["E0305 argument `x` is passed more than once"]
+    );
+    // A shadowing `let` is checked against its own closure.
+    assert_eq!(
+        codes("let f = |a| a\nif c\n  let f = || 1\n  f(1)\n"),
+        ["E0306 `f` takes 0 arguments but 1 was given"]
+    );
+}
+
+#[test]
+fn test_diagnostic_labels() {
+    let src = "let x = 1\nx = 2\nlet mut y = 3\n";
+    let mut lex = Lex::new(src);
+    let programme = parse_programme(&mut lex);
+    let mut compiler = Compiler::new();
+    compiler.compile(&programme);
+    let labels = compiler
+        .diagnostics()
+        .iter()
+        .map(|d| {
+            let offset = |s: &str| s.as_ptr() as usize - src.as_ptr() as usize;
+            d.labels
+                .iter()
+                .map(|l| (offset(&l.span), l.primary))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        labels,
+        [vec![(10, true), (4, false)], vec![(20, true), (24, false)]]
+    );
+}
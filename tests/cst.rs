@@ -0,0 +1,109 @@
+use sqwipt::{
+    ast::{parse_programme, Expr, Programme},
+    cst::{Element, Node, SyntaxKind, SyntaxTree},
+    lex::Lex,
+};
+
+// Kinds and token text, leaving out trivia.
+fn dump(node: &Node) -> String {
+    let parts = node
+        .children()
+        .iter()
+        .filter_map(|e| match e {
+            Element::Node(node) => Some(dump(node)),
+            Element::Token(token) if !token.text().is_empty() => Some(token.text().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    format!("{:?}[{}]", node.kind(), parts.join(" "))
+}
+
+#[test]
+fn test_round_trip() {
+    let sources = [
+        "",
+        "\n\n",
+        "1 + 2 * 3",
+        "# header\nf(1, /* two */ 2) // call\n\n  \n",
+        "|a, b|\n    a + b\n\nx.y[0]\n",
+        "f(|x|\n    x * 2\n)(3)",
+        "\u{feff}x\r\ny\r\n",
+        "\"s{x:>8}\" r#\"raw\"# b'\\xff'",
+        "1 +\n  2",
+        "f(1\n",
+        "\"unterminated\nx",
+        "/* unterminated",
+        "1 $ 2\n3 ) 4\n",
+        "a\n  b\n\tc\n",
+        "(1, 2,)",
+        "a = b[0] += 1\n1 = 2",
+        "if a\n  1\nelif b\n  2\nelse # other\n  3\nwhile x\n  break\nfor i in y\n  return i\n",
+        "let x = 1\nlet mut y = x\n",
+        "let (a, [b, ..c]) = x\n[d, ..] = y\nfor P(k, v=w) in z\n  |(e, f)| e\n",
+        "|a 1| a",
+        "|| 1\n|x, y: Int = 2, *rest, key=x| x\n|*, (a, b)=t| a\n",
+        "f(x, scale = 2.0)\ng(a=1, 2)\n",
+        "[1, ..xs,\n  2,]\nm = {\n  \"a\": [], # first\n  ..n\n}\n{a 1}\n",
+        "match x # which\n  0 => a\n  (y, -2) if y => b\n  _ =>\n    c\nmatch y\n  1 b\n",
+        "struct P x y\nenum E\n  A # first\n  B\nimpl P\n  fn len(self, *, scale=1)\n    self.x * scale\nP 1 \"a\"\nstruct 1\n",
+        "enum Shape\n  Empty\n  Circle(Float) # round\n  Rect{w,\n    h}\nmatch s\n  Some((a, 1)) => a\n  None => 0\n",
+        "trait Shape\n  fn area(self) # required\n  fn twice(self, *, by=2)\n    self.area() * by\nimpl Shape for Int\nimpl Shape for P\n  fn area(self)\n    0\ntrait\n",
+    ];
+    for src in sources {
+        let tree = SyntaxTree::parse(src);
+        assert_eq!(tree.to_string(), src);
+
+        // The typed view is the same as parsing without the tree.
+        let mut lex = Lex::new(src);
+        assert_eq!(tree.programme(), &parse_programme(&mut lex), "{src:?}");
+        assert_eq!(tree.diagnostics(), lex.diagnostics(), "{src:?}");
+    }
+}
+
+#[test]
+fn test_nodes() {
+    let tree = SyntaxTree::parse("f(1, -x) # call\ng.h");
+    assert_eq!(
+        dump(tree.root()),
+        "Programme[Call[Ident[f] ( Int[1] , Unary[- Ident[x]] )] Dot[Ident[g] . Ident[h]]]"
+    );
+
+    let tree = SyntaxTree::parse("|a|\n  a ** 2\n1 $");
+    assert_eq!(
+        dump(tree.root()),
+        "Programme[Closure[| FormalArg[a] | Block[Binary[Ident[a] ** Int[2]]]] Int[1] Error[$]]"
+    );
+    assert_eq!(tree.programme(), &Programme::Bad());
+}
+
+#[test]
+fn test_node_of_expr() {
+    let src = "# double\n|x|\n  x * 2\ng(f(3))";
+    let tree = SyntaxTree::parse(src);
+    let Programme::Good(exprs) = tree.programme() else {
+        panic!("{:?}", tree.diagnostics());
+    };
+    let mut found = vec![];
+    for expr in exprs {
+        expr.walk(&mut |e: &Expr| {
+            let node = tree.node(e).unwrap();
+            assert_eq!(node.kind(), SyntaxKind::of(e));
+            found.push(node.to_string().trim().to_string());
+        });
+    }
+    assert_eq!(
+        found,
+        [
+            "|x|\n  x * 2",
+            "x * 2",
+            "x * 2",
+            "x",
+            "2",
+            "g(f(3))",
+            "g",
+            "f(3)",
+            "f",
+            "3"
+        ]
+    );
+}
@@ -0,0 +1,63 @@
+use sqwipt::{
+    ast::{parse_programme, Expr, Parse},
+    diag,
+    lex::Lex,
+};
+
+fn diagnostics(src: &str) -> Vec<String> {
+    let mut lex = Lex::new(src);
+    parse_programme(&mut lex);
+    lex.diagnostics()
+        .iter()
+        .map(|d| {
+            let labels = d
+                .labels
+                .iter()
+                .map(|l| {
+                    format!(
+                        "{}{:?} {:?}",
+                        if l.primary { "^" } else { "-" },
+                        l.span,
+                        l.message
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{} {:?}: {} [{labels}]", d.code, d.severity, d.message)
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_diagnostics() {
+    assert_eq!(diagnostics("print(1)\n"), Vec::<String>::new());
+    assert_eq!(
+        diagnostics("$"),
+        [r#"E0001 Error: unknown token `$` [^"$" ""]"#]
+    );
+    assert_eq!(
+        diagnostics("f(1"),
+        [r#"E0102 Error: expected `)`, found end of file [^"" "", -"(" "unclosed `(`"]"#]
+    );
+    assert_eq!(
+        diagnostics("print(\"abc"),
+        [
+            r#"E0002 Error: unterminated string [^"\"" "string starts here"]"#,
+            r#"E0102 Error: expected `)`, found end of file [^"" "", -"(" "unclosed `(`"]"#,
+        ]
+    );
+    assert_eq!(
+        diagnostics("|x| )"),
+        [r#"E0100 Error: expected expression after |..|, found `)` [^")" ""]"#]
+    );
+}
+
+#[test]
+fn test_take_diagnostics() {
+    let mut lex = Lex::new("$ 1");
+    assert!(Expr::parse(&mut lex).is_none());
+    assert!(lex.diagnostics().has_errors());
+    let taken = lex.take_diagnostics();
+    assert_eq!(taken.codes(), [diag::UNKNOWN_TOKEN]);
+    assert!(lex.diagnostics().is_empty());
+}
@@ -0,0 +1,78 @@
+use sqwipt::{
+    ast::{parse_programme, Programme},
+    incremental::{Document, Edit},
+    lex::Lex,
+};
+
+const SRC: &str =
+    "1 + 2\n|a|\n  a + 1\n  a * 2\n\n# comment\nfoo(2)\nbaz(1,\n  2)\nbar(y, \"s{x}\")\n";
+
+// Byte offsets of every span in a programme, so that reused trees are
+// checked to point at the right place and not just at equal text.
+fn offsets(src: &str, programme: &Programme) -> Vec<usize> {
+    let mut offsets = vec![];
+    if let Programme::Good(exprs) = programme {
+        for e in exprs {
+            e.map_spans(&mut |s| {
+                offsets.push(s.as_ptr() as usize - src.as_ptr() as usize);
+                s.clone()
+            });
+        }
+    }
+    offsets
+}
+
+// Reparse after `edit` and check the result against a fresh parse.
+fn check(doc: &Document, edit: Edit) -> usize {
+    let src = edit.apply(doc.src());
+    let new = doc.reparse(&edit, &src);
+    let mut lex = Lex::new(&src);
+    let programme = parse_programme(&mut lex);
+    assert_eq!(new.programme(), programme, "{src:?}");
+    assert_eq!(offsets(&src, &new.programme()), offsets(&src, &programme));
+    assert_eq!(new.diagnostics(), *lex.diagnostics(), "{src:?}");
+    new.reused()
+}
+
+#[test]
+fn test_reparse() {
+    let doc = Document::new(SRC);
+    assert!(matches!(doc.programme(), Programme::Good(exprs) if exprs.len() == 5));
+
+    let at = |s: &str| SRC.find(s).unwrap();
+    // Only the edited chunk and the one before it are parsed again.
+    assert_eq!(check(&doc, Edit::new(4..5, "20")), 4);
+    assert_eq!(check(&doc, Edit::new(at("a * 2")..at("a * 2") + 1, "b")), 3);
+    assert_eq!(check(&doc, Edit::new(at("foo")..at("foo") + 3, "baz")), 3);
+    assert_eq!(check(&doc, Edit::new(SRC.len()..SRC.len(), "qux\n")), 3);
+
+    // Edits that join or split chunks.
+    check(&doc, Edit::new(at("# ")..at("# ") + 2, ""));
+    check(&doc, Edit::new(at("(1,")..at("(1,") + 3, "(1,\n\n7,"));
+    check(&doc, Edit::new(at("  2)")..at("  2)") + 4, "2)"));
+    check(&doc, Edit::new(at("foo")..at("foo"), "("));
+    check(&doc, Edit::new(at("  a * 2")..at("  a * 2") + 2, "\t"));
+    check(&doc, Edit::new(0..SRC.len(), ""));
+    check(&doc, Edit::new(0..0, "\u{feff}"));
+
+    // Errors come and go.
+    check(&doc, Edit::new(at("bar")..at("bar"), "$ "));
+    check(&doc, Edit::new(at("foo(2)") + 4..at("foo(2)") + 5, "\"2"));
+    check(&doc, Edit::new(at("s{x}") + 2..at("s{x}") + 3, ""));
+}
+
+#[test]
+fn test_reparse_twice() {
+    let doc = Document::new(SRC);
+    let edit = Edit::new(0..0, "$\n");
+    let src = edit.apply(SRC);
+    let doc = doc.reparse(&edit, &src);
+    assert_eq!(doc.programme(), Programme::Bad());
+    assert_eq!(doc.diagnostics().codes(), ["E0001"]);
+
+    let edit = Edit::new(0..2, "");
+    let src = edit.apply(&src);
+    let doc = doc.reparse(&edit, &src);
+    assert_eq!(doc.programme(), Document::new(SRC).programme());
+    assert!(doc.diagnostics().is_empty());
+}
@@ -1,25 +1,32 @@
 use std::str::FromStr;
 
-use sqwipt::{ast::parse_programme, lex::Lex};
+use sqwipt::{ast::parse_programme, lex::Lex, render::Renderer, source::SourceMap, Sqwipt};
 
 
 #[test]
 fn language_design() {
     for f in [
-        "hello-world.sqw"
+        "hello-world.sqw",
+        "comments.sqw",
+        "fibanocci.sqw",
+        "function-call.sqw",
     ] {
         let mut p = std::path::PathBuf::from_str("tests/language-design").unwrap();
         p.push(f);
         let src = std::fs::read_to_string(p).unwrap();
+        let mut map = SourceMap::new();
+        map.add(f, &src);
         let lex = &mut Lex::new(&src);
         use sqwipt::ast::Programme::*;
         match parse_programme(lex) {
-            Bad() => panic!("bad programme {f}"),
-            Good(exprs) => {
-                for _expr in exprs {
-                    // expr.execute();
+            Bad() => panic!("bad programme {f}\n{}", Renderer::new(&map).render_all(lex.diagnostics())),
+            Good(_) => {
+                let mut sqwipt = Sqwipt::new();
+                sqwipt.vm().capture_output();
+                if let Err(e) = sqwipt.run(&src) {
+                    panic!("{f} failed\n{e}");
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
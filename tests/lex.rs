@@ -1,4 +1,7 @@
-use sqwipt::lex::{Token, Lex};
+use sqwipt::{
+    diag::Diagnostics,
+    lex::{decode_interpolated, decode_str, is_interpolated, Fragment, Lex, StrValue, Token, Trivia},
+};
 
 #[test]
 fn test_lex() {
@@ -12,13 +15,29 @@ fn test_lex() {
     }
     lex!("1", Token::Int("1"));
     lex!("1.0", Token::Float("1.0"));
-    lex!("0x12abcd", Token::Hex("0x12abcd"));
+    lex!("0x12abcd", Token::Int("0x12abcd"));
+    lex!("0b1010", Token::Int("0b1010"));
+    lex!("0o777", Token::Int("0o777"));
+    lex!("0xFF_FF", Token::Int("0xFF_FF"));
+    lex!("1_000_000", Token::Int("1_000_000"));
+    lex!("1e10", Token::Float("1e10"));
+    lex!("1.5e+3", Token::Float("1.5e+3"));
+    lex!("2.5E-3", Token::Float("2.5E-3"));
+    lex!("1_0.0_1", Token::Float("1_0.0_1"));
     lex!("\"xyz\"", Token::Str("\"xyz\""));
     lex!("'xyz'", Token::Str("'xyz'"));
     lex!(r#"'xyz\''"#, Token::Str(r#"'xyz\''"#));
     lex!(r#""xyz""#, Token::Str(r#""xyz""#));
     lex!(r#""hello world""#, Token::Str(r#""hello world""#));
     lex!(r#""xyz"#, Token::UnterminatedString("\"xyz"));
+    lex!(r#""\\""#, Token::Str(r#""\\""#));
+    lex!(r#""a\"b""#, Token::Str(r#""a\"b""#));
+    lex!(r#"r"a\""#, Token::Str(r#"r"a\""#));
+    lex!(r##"r#"a"b"#"##, Token::Str(r##"r#"a"b"#"##));
+    lex!(r#"b"ab""#, Token::Str(r#"b"ab""#));
+    lex!(r#"br"\x""#, Token::Str(r#"br"\x""#));
+    lex!("\"\"\"a\n\"b\"\n\"\"\"", Token::Str("\"\"\"a\n\"b\"\n\"\"\""));
+    lex!("''", Token::Str("''"));
     // let mut lex = Lex::new(r#"
     //     struct Bert a b c d
 
@@ -51,3 +70,282 @@ fn test_lex() {
     // }
 }
 
+
+fn tokens(src: &str) -> Vec<String> {
+    let mut lex = Lex::new(src);
+    let mut res = vec![];
+    loop {
+        res.push(format!("{:?}", lex.peek()));
+        if lex.is_eof() {
+            return res;
+        }
+        lex.advance();
+    }
+}
+
+#[test]
+fn test_comments() {
+    assert_eq!(tokens("1 # one"), [r#"Int("1")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("1 // one\n2"), [r#"Int("1")"#, r#"Newline("")"#, r#"Int("2")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("1 /* one /* nested */ */ + 2"), [r#"Int("1")"#, r#"Punct("+")"#, r#"Int("2")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("1 /* one\n two */ + 2"), [r#"Int("1")"#, r#"Punct("+")"#, r#"Int("2")"#, r#"Eof("")"#]);
+
+    // Comment only and blank lines don't indent or dedent.
+    assert_eq!(
+        tokens("f\n  a\n# top level comment\n\n      # deep comment\n  b\n"),
+        [r#"Ident("f")"#, r#"Begin("")"#, r#"Ident("a")"#, r#"Newline("")"#, r#"Ident("b")"#, r#"Newline("")"#, r#"End("")"#, r#"Eof("")"#]
+    );
+    assert_eq!(
+        tokens("a\n/* block\n  comment */\nb"),
+        [r#"Ident("a")"#, r#"Newline("")"#, r#"Ident("b")"#, r#"Eof("")"#]
+    );
+
+    let mut lex = Lex::new("1 /* never closed");
+    lex.advance();
+    assert_eq!(lex.diagnostics().codes(), ["E0003"]);
+}
+
+#[test]
+fn test_trivia() {
+    let src = "# header\nf = |x| # trailing\n    /* inner */ x + 1\n\n// done\n";
+    let mut lex = Lex::with_trivia(src);
+    let mut text = String::new();
+    let mut comments = vec![];
+    loop {
+        for t in lex.leading_trivia() {
+            let (Trivia::Whitespace(s) | Trivia::Newline(s) | Trivia::LineComment(s) | Trivia::BlockComment(s)) = t;
+            if matches!(t, Trivia::LineComment(_) | Trivia::BlockComment(_)) {
+                comments.push(format!("{:?} before {:?}", t, lex.peek()));
+            }
+            text.push_str(s);
+        }
+        text.push_str(&lex.span());
+        if lex.is_eof() {
+            break;
+        }
+        lex.advance();
+    }
+    assert_eq!(text, src);
+    assert_eq!(
+        comments,
+        [
+            r##"LineComment("# header") before Newline("")"##,
+            r##"LineComment("# trailing") before Begin("")"##,
+            r#"BlockComment("/* inner */") before Ident("x")"#,
+            r#"LineComment("// done") before Newline("")"#,
+        ]
+    );
+
+    // Trivia is only collected on request.
+    let lex = Lex::new("# comment\n1");
+    assert!(lex.leading_trivia().is_empty());
+}
+
+#[test]
+fn test_dedent() {
+    // One End per closed block.
+    assert_eq!(
+        tokens("a\n  b\n    c\n      d\ne"),
+        [r#"Ident("a")"#, r#"Begin("")"#, r#"Ident("b")"#, r#"Begin("")"#, r#"Ident("c")"#, r#"Begin("")"#, r#"Ident("d")"#, r#"End("")"#, r#"End("")"#, r#"End("")"#, r#"Ident("e")"#, r#"Eof("")"#]
+    );
+    assert_eq!(
+        tokens("a\n  b\n    c\n  d"),
+        [r#"Ident("a")"#, r#"Begin("")"#, r#"Ident("b")"#, r#"Begin("")"#, r#"Ident("c")"#, r#"End("")"#, r#"Ident("d")"#, r#"End("")"#, r#"Eof("")"#]
+    );
+
+    // A dedent to a column between two levels is reported, and lexes as a
+    // new block so that Begin and End still balance.
+    let src = "a\n    b\n  c\n  d\ne";
+    assert_eq!(
+        tokens(src),
+        [r#"Ident("a")"#, r#"Begin("")"#, r#"Ident("b")"#, r#"End("")"#, r#"Begin("")"#, r#"Ident("c")"#, r#"Newline("")"#, r#"Ident("d")"#, r#"End("")"#, r#"Ident("e")"#, r#"Eof("")"#]
+    );
+    let mut lex = Lex::new(src);
+    while !lex.is_eof() {
+        lex.advance();
+    }
+    assert_eq!(lex.diagnostics().codes(), ["E0004"]);
+
+    // Tabs in indentation are errors, but count to the next tab stop.
+    let src = "a\n\tb\n        c";
+    assert_eq!(
+        tokens(src),
+        [r#"Ident("a")"#, r#"Begin("")"#, r#"Ident("b")"#, r#"Newline("")"#, r#"Ident("c")"#, r#"End("")"#, r#"Eof("")"#]
+    );
+    let mut lex = Lex::new(src);
+    while !lex.is_eof() {
+        lex.advance();
+    }
+    assert_eq!(lex.diagnostics().codes(), ["E0005"]);
+
+    // Tabs between tokens are fine.
+    assert_eq!(tokens("a\t+\tb"), [r#"Ident("a")"#, r#"Punct("+")"#, r#"Ident("b")"#, r#"Eof("")"#]);
+}
+
+#[test]
+fn test_brackets() {
+    // No Begin, Newline or End inside brackets.
+    assert_eq!(
+        tokens("f(1,\n      2,\n  3\n)\ng"),
+        [r#"Ident("f")"#, r#"Punct("(")"#, r#"Int("1")"#, r#"Punct(",")"#, r#"Int("2")"#, r#"Punct(",")"#, r#"Int("3")"#, r#"Punct(")")"#, r#"Newline("")"#, r#"Ident("g")"#, r#"Eof("")"#]
+    );
+    assert_eq!(
+        tokens("[1,\n  [2,\n3]]"),
+        [r#"Punct("[")"#, r#"Int("1")"#, r#"Punct(",")"#, r#"Punct("[")"#, r#"Int("2")"#, r#"Punct(",")"#, r#"Int("3")"#, r#"Punct("]")"#, r#"Punct("]")"#, r#"Eof("")"#]
+    );
+
+    assert_eq!(
+        tokens("{\"a\":\n  1}"),
+        [r#"Punct("{")"#, r#"Str("\"a\"")"#, r#"Punct(":")"#, r#"Int("1")"#, r#"Punct("}")"#, r#"Eof("")"#]
+    );
+
+    // An indented closure body inside brackets is a block again.
+    assert_eq!(
+        tokens("f(|x|\n    a\n    b\n  )"),
+        [r#"Ident("f")"#, r#"Punct("(")"#, r#"Punct("|")"#, r#"Ident("x")"#, r#"Punct("|")"#, r#"Begin("")"#, r#"Ident("a")"#, r#"Newline("")"#, r#"Ident("b")"#, r#"End("")"#, r#"Punct(")")"#, r#"Eof("")"#]
+    );
+    // Closing the bracket closes the block.
+    assert_eq!(
+        tokens("f(|x|\n    |y|\n      y)"),
+        [r#"Ident("f")"#, r#"Punct("(")"#, r#"Punct("|")"#, r#"Ident("x")"#, r#"Punct("|")"#, r#"Begin("")"#, r#"Punct("|")"#, r#"Ident("y")"#, r#"Punct("|")"#, r#"Begin("")"#, r#"Ident("y")"#, r#"End("")"#, r#"End("")"#, r#"Punct(")")"#, r#"Eof("")"#]
+    );
+    // `|` after an operand is bitwise or, not a closure.
+    assert_eq!(
+        tokens("(a |\n b)"),
+        [r#"Punct("(")"#, r#"Ident("a")"#, r#"Punct("|")"#, r#"Ident("b")"#, r#"Punct(")")"#, r#"Eof("")"#]
+    );
+}
+
+#[test]
+fn test_strings() {
+    // Prefixes are only prefixes right before a quote.
+    assert_eq!(tokens("r b rb x"), [r#"Ident("r")"#, r#"Ident("b")"#, r#"Ident("rb")"#, r#"Ident("x")"#, r#"Eof("")"#]);
+
+    // A single quoted string stops at the end of the line.
+    assert_eq!(tokens("\"ab\nc"), [r#"UnterminatedString("\"ab")"#, r#"Newline("")"#, r#"Ident("c")"#, r#"Eof("")"#]);
+
+    // A triple quoted string is one token, whatever its indentation.
+    assert_eq!(
+        tokens("f\n  x = \"\"\"\n  a\nb\n      c\n  \"\"\"\n  y"),
+        [r#"Ident("f")"#, r#"Begin("")"#, r#"Ident("x")"#, r#"Punct("=")"#, "Str(\"\\\"\\\"\\\"\\n  a\\nb\\n      c\\n  \\\"\\\"\\\"\")", r#"Newline("")"#, r#"Ident("y")"#, r#"End("")"#, r#"Eof("")"#]
+    );
+}
+
+#[test]
+fn test_decode_str() {
+    macro_rules! decode {
+        ($s : expr, $value : expr) => {
+            let mut diagnostics = Diagnostics::new();
+            assert_eq!(decode_str($s, &mut diagnostics), $value);
+            assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        }
+    }
+    let s = |s: &str| StrValue::Str(s.to_string());
+    decode!(r#""abc""#, s("abc"));
+    decode!(r#"'a\tb\n\\\'\"\0'"#, s("a\tb\n\\'\"\0"));
+    decode!(r#""\x41\u{1F600}\u{e9}""#, s("A\u{1F600}é"));
+    decode!("\"a\\\n     b\"", s("ab"));
+    decode!(r#"r"a\n""#, s("a\\n"));
+    decode!(r##"r#"a"b"#"##, s("a\"b"));
+    decode!(r#"b"a\xff\n""#, StrValue::Bytes(vec![b'a', 0xff, b'\n']));
+    decode!(r#"br"\xff""#, StrValue::Bytes(b"\\xff".to_vec()));
+    decode!("\"\"\"\n    hello\n      world\n    \"\"\"", s("hello\n  world"));
+    decode!("\"\"\"hello\n  world\"\"\"", s("hello\n  world"));
+    decode!("\"\"\"\n  a \\t\n  \"\"\"", s("a \t"));
+
+    macro_rules! bad {
+        ($s : expr, $codes : expr, $span : expr) => {
+            let mut diagnostics = Diagnostics::new();
+            decode_str($s, &mut diagnostics);
+            assert_eq!(diagnostics.codes(), $codes);
+            assert_eq!(**diagnostics.iter().next().unwrap().primary_span().unwrap(), $span);
+        }
+    }
+    bad!(r#""a\qb""#, ["E0006"], r#"\q"#);
+    bad!(r#""\x4""#, ["E0006"], r#"\x4"#);
+    bad!(r#""\xff""#, ["E0006"], r#"\xff"#);
+    bad!(r#""\u{110000}""#, ["E0006"], r#"\u{110000}"#);
+    bad!(r#""\u41""#, ["E0006"], r#"\u"#);
+    bad!(r#"b"é""#, ["E0007"], "é");
+    bad!(r#"b"\u{41}""#, ["E0006"], r#"\u"#);
+
+    // Interpolated strings split into text and placeholders.
+    let mut diagnostics = Diagnostics::new();
+    assert_eq!(
+        decode_interpolated(r#""a\t{x}{{{f(1)['}']:>4}}}""#, &mut diagnostics),
+        [
+            Fragment::Text("a\t".into()),
+            Fragment::Placeholder("x", None),
+            Fragment::Text("{".into()),
+            Fragment::Placeholder("f(1)['}']", Some(">4")),
+            Fragment::Text("}".into()),
+        ]
+    );
+    assert!(diagnostics.is_empty());
+    assert!(is_interpolated(r#""{x}""#));
+    assert!(!is_interpolated(r#"r"{x}""#));
+    assert!(!is_interpolated(r#"b"{x}""#));
+    assert!(!is_interpolated(r#""x""#));
+    // Without a placeholder, lone and doubled braces are kept as they are.
+    for text in [r#""{""#, r#""a}b""#, r#""{{x}}""#, r#""{x""#, r#""}{""#] {
+        assert!(!is_interpolated(text), "{text}");
+    }
+    decode!(r#""{""#, s("{"));
+    decode!(r#""a}b""#, s("a}b"));
+    decode!(r#""{{x}}""#, s("{{x}}"));
+    let mut lex = Lex::new(r#"("{", "a}b", "}{")"#);
+    while !lex.is_eof() {
+        lex.advance();
+    }
+    assert!(lex.diagnostics().is_empty());
+
+    // The lexer reports bad escapes as it goes.
+    let mut lex = Lex::new(r#"x = "\q""#);
+    while !lex.is_eof() {
+        lex.advance();
+    }
+    assert_eq!(lex.diagnostics().codes(), ["E0006"]);
+}
+
+#[test]
+fn test_numbers() {
+    // A `.` needs a digit after it to make a float.
+    assert_eq!(tokens("0..n"), [r#"Int("0")"#, r#"Punct("..")"#, r#"Ident("n")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("a and b or c"), [r#"Ident("a")"#, r#"Keyword("and")"#, r#"Ident("b")"#, r#"Keyword("or")"#, r#"Ident("c")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("1...2"), [r#"Int("1")"#, r#"Punct("..")"#, r#"Punct(".")"#, r#"Int("2")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("1.max(2)"), [r#"Int("1")"#, r#"Punct(".")"#, r#"Ident("max")"#, r#"Punct("(")"#, r#"Int("2")"#, r#"Punct(")")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("1.5.x"), [r#"Float("1.5")"#, r#"Punct(".")"#, r#"Ident("x")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("2e-x"), [r#"Int("2e")"#, r#"Punct("-")"#, r#"Ident("x")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("match 1 => x"), [r#"Keyword("match")"#, r#"Int("1")"#, r#"Punct("=>")"#, r#"Ident("x")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("struct enum impl trait fn"), [r#"Keyword("struct")"#, r#"Keyword("enum")"#, r#"Keyword("impl")"#, r#"Keyword("trait")"#, r#"Keyword("fn")"#, r#"Eof("")"#]);
+
+    macro_rules! bad {
+        ($s : expr, $message : expr, $span : expr) => {
+            let lex = Lex::new($s);
+            let d = lex.diagnostics().iter().next().expect($s);
+            assert_eq!((d.code, d.message.as_str(), **d.primary_span().unwrap()), ("E0011", $message, $span));
+        }
+    }
+    bad!("12abc", "invalid suffix `abc` on number literal", "abc");
+    bad!("0b102", "invalid digit `2` in binary literal", "2");
+    bad!("0o8", "invalid digit `8` in octal literal", "8");
+    bad!("0xfg", "invalid suffix `g` on hexadecimal literal", "g");
+    bad!("0x", "hexadecimal literal has no digits", "0x");
+    bad!("0b_", "binary literal has no digits", "0b_");
+}
+
+#[test]
+fn test_unicode() {
+    assert_eq!(tokens("café = 1"), [r#"Ident("café")"#, r#"Punct("=")"#, r#"Int("1")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("変数 + _x"), [r#"Ident("変数")"#, r#"Punct("+")"#, r#"Ident("_x")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("_"), [r#"Ident("_")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("\u{feff}x"), [r#"Ident("x")"#, r#"Eof("")"#]);
+    assert_eq!(tokens("x\r\ny\r\n"), [r#"Ident("x")"#, r#"Newline("")"#, r#"Ident("y")"#, r#"Newline("")"#, r#"Eof("")"#]);
+
+    // An unknown character is never split in the middle.
+    let mut lex = Lex::new("x → y");
+    lex.advance();
+    let d = lex.diagnostics().iter().next().unwrap();
+    assert_eq!((d.code, **d.primary_span().unwrap()), ("E0001", "→"));
+    assert_eq!(tokens("1→"), [r#"Int("1")"#, r#"UnknownToken("→")"#, r#"Eof("")"#]);
+}
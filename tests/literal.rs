@@ -0,0 +1,62 @@
+use sqwipt::{
+    ast::{Expr, Parse},
+    diag::Diagnostics,
+    lex::Lex,
+    literal::{check_literals, literal_value},
+    runtime::Variant,
+};
+
+fn value(src: &str) -> (Option<Variant>, Vec<&'static str>) {
+    let mut lex = Lex::new(src);
+    let expr = Expr::parse(&mut lex).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let value = literal_value(&expr, &mut diagnostics);
+    (value, diagnostics.codes())
+}
+
+#[test]
+fn test_literal_value() {
+    assert_eq!(value("123"), (Some(123.into()), vec![]));
+    assert_eq!(value("1_000"), (Some(1000.into()), vec![]));
+    assert_eq!(value("0xff"), (Some(255.into()), vec![]));
+    assert_eq!(value("0o17"), (Some(15.into()), vec![]));
+    assert_eq!(value("0b1010"), (Some(10.into()), vec![]));
+    assert_eq!(value("1.5e3"), (Some(1500.0.into()), vec![]));
+    assert_eq!(value("x"), (None, vec![]));
+
+    // The largest i128 is still an Int.
+    assert_eq!(
+        value("170141183460469231731687303715884105727"),
+        (Some(i128::MAX.into()), vec![])
+    );
+
+    // One more is a BigInt in little endian two's complement.
+    let mut big = vec![0; 16];
+    big[15] = 0x80;
+    big.push(0);
+    assert_eq!(
+        value("170141183460469231731687303715884105728"),
+        (Some(Variant::big_int(&big)), vec![])
+    );
+    let mut big = vec![0; 16];
+    big.push(1);
+    assert_eq!(
+        value("0x1_0000_0000_0000_0000_0000_0000_0000_0000"),
+        (Some(Variant::big_int(&big)), vec![])
+    );
+
+    assert_eq!(value("1e400"), (Some(f64::INFINITY.into()), vec!["E0200"]));
+}
+
+#[test]
+fn test_check_literals() {
+    let mut lex = Lex::new("f(1e999, |x| x + 2e999)");
+    let expr = Expr::parse(&mut lex).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    check_literals(&[expr], &mut diagnostics);
+    let spans = diagnostics
+        .iter()
+        .map(|d| d.primary_span().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(spans, ["1e999", "2e999"]);
+}
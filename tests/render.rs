@@ -0,0 +1,102 @@
+use sqwipt::{
+    ast::parse_programme,
+    diag::{self, Diagnostic},
+    lex::{Lex, Span},
+    render::Renderer,
+    source::SourceMap,
+};
+
+fn render(name: &str, src: &str) -> String {
+    let mut map = SourceMap::new();
+    map.add(name, src);
+    let mut lex = Lex::new(src);
+    parse_programme(&mut lex);
+    Renderer::new(&map).render_all(lex.diagnostics())
+}
+
+#[test]
+fn test_render() {
+    assert_eq!(
+        render("main.sqw", "x\nf(1"),
+        "\
+error[E0102]: expected `)`, found end of file
+ --> main.sqw:2:4
+  |
+2 | f(1
+  |  - ^
+  |  |
+  |  unclosed `(`
+"
+    );
+    assert_eq!(
+        render("main.sqw", "print(1)\nprint(2)\nprint(3) $"),
+        "\
+error[E0001]: unknown token `$`
+ --> main.sqw:3:10
+  |
+3 | print(3) $
+  |          ^
+"
+    );
+}
+
+#[test]
+fn test_render_notes() {
+    let src = "a = 1\n\n\n\n\n\n\n\n\nb = a + 1";
+    let mut map = SourceMap::new();
+    map.add("notes.sqw", src);
+    let d = Diagnostic::warning(diag::EXPECTED_EXPR, "made up")
+        .with_primary(sqwipt_span(src, src.find('b').unwrap()), "used here")
+        .with_secondary(sqwipt_span(src, 0), "defined here")
+        .with_note("a note")
+        .with_help("some help");
+    assert_eq!(
+        Renderer::new(&map).render(&d),
+        "\
+warning[E0100]: made up
+  --> notes.sqw:10:1
+   |
+ 1 | a = 1
+   | - defined here
+  ...
+10 | b = a + 1
+   | ^ used here
+   |
+   = note: a note
+   = help: some help
+"
+    );
+    let coloured = Renderer::new(&map).colour(true).render(&d);
+    assert!(coloured.starts_with("\x1b[1;33mwarning[E0100]\x1b[0m"));
+}
+
+#[test]
+fn test_render_same_column() {
+    let src = "abc = x";
+    let mut map = SourceMap::new();
+    map.add("main.sqw", src);
+    let d = Diagnostic::error(diag::EXPECTED_EXPR, "made up")
+        .with_primary(Span::new(&src[0..3]), "all")
+        .with_secondary(Span::new(&src[0..1]), "first")
+        .with_secondary(Span::new(&src[6..7]), "value");
+    // Labels starting together share a line.
+    assert_eq!(
+        Renderer::new(&map).render(&d),
+        "\
+error[E0100]: made up
+ --> main.sqw:1:1
+  |
+1 | abc = x
+  | ^^^   - value
+  | |
+  | first
+  | |
+  | all
+"
+    );
+}
+
+// The first token at a byte offset.
+fn sqwipt_span(src: &str, offset: usize) -> sqwipt::lex::Span<'_> {
+    Lex::new(&src[offset..]).span()
+}
@@ -0,0 +1,83 @@
+use sqwipt::{
+    lex::{Lex, Token},
+    source::{decode_utf8, SourceMap},
+};
+
+#[test]
+fn test_locate() {
+    let a = "x = 1\nf = |y|\n  y + 1\n";
+    let b = "print(x)";
+    let mut map = SourceMap::new();
+    let fa = map.add("a.sqw", a);
+    let fb = map.add("b.sqw", b);
+
+    let mut lex = Lex::new(a);
+    let mut found = vec![];
+    loop {
+        let span = lex.span();
+        let loc = map.locate(&span).unwrap();
+        assert_eq!(loc.file, fa);
+        found.push((format!("{:?}", lex.peek()), loc.line, loc.column, loc.range));
+        if lex.is_eof() {
+            break;
+        }
+        lex.advance();
+    }
+    let found = found
+        .iter()
+        .map(|(t, l, c, r)| format!("{t} {l}:{c} {r:?}"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            r#"Ident("x") 1:1 0..1"#,
+            r#"Punct("=") 1:3 2..3"#,
+            r#"Int("1") 1:5 4..5"#,
+            r#"Newline("") 2:1 6..6"#,
+            r#"Ident("f") 2:1 6..7"#,
+            r#"Punct("=") 2:3 8..9"#,
+            r#"Punct("|") 2:5 10..11"#,
+            r#"Ident("y") 2:6 11..12"#,
+            r#"Punct("|") 2:7 12..13"#,
+            r#"Begin("") 3:1 14..14"#,
+            r#"Ident("y") 3:3 16..17"#,
+            r#"Punct("+") 3:5 18..19"#,
+            r#"Int("1") 3:7 20..21"#,
+            r#"Newline("") 4:1 22..22"#,
+            r#"End("") 4:1 22..22"#,
+            r#"Eof("") 4:1 22..22"#,
+        ]
+    );
+
+    let mut lex = Lex::new(b);
+    lex.advance();
+    let loc = map.locate(&lex.span()).unwrap();
+    assert_eq!(loc.file, fb);
+    assert_eq!((loc.line, loc.column, loc.range), (1, 6, 5..6));
+    assert_eq!(map.file(fb).name(), "b.sqw");
+    assert!(matches!(lex.peek(), Token::Punct("(")));
+}
+
+#[test]
+fn test_lines() {
+    let src = "ab\n\ncd";
+    let mut map = SourceMap::new();
+    let id = map.add("x.sqw", src);
+    let file = map.file(id);
+    assert_eq!(file.line_count(), 3);
+    assert_eq!(file.line(1), Some("ab"));
+    assert_eq!(file.line(2), Some(""));
+    assert_eq!(file.line(3), Some("cd"));
+    assert_eq!(file.line(4), None);
+    assert_eq!(file.line_col(5), (3, 2));
+}
+
+#[test]
+fn test_decode_utf8() {
+    assert_eq!(decode_utf8("a.sqw", b"x = 1"), Ok("x = 1"));
+    assert_eq!(decode_utf8("a.sqw", b"\xef\xbb\xbfx = 1"), Ok("x = 1"));
+    let d = decode_utf8("a.sqw", b"x = 1\ny = \"\xc3\xa9\xff\"").unwrap_err();
+    assert_eq!(d.code, "E0012");
+    assert_eq!(d.message, "a.sqw is not valid UTF-8");
+    assert_eq!(d.notes, ["invalid byte 0xff at a.sqw:2:7"]);
+}
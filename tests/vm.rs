@@ -0,0 +1,275 @@
+use sqwipt::{runtime::Variant, Error, Sqwipt};
+
+// The value of a programme and what it printed.
+fn run(src: &str) -> (Result<Variant, String>, String) {
+    let mut sqwipt = Sqwipt::new();
+    sqwipt.vm().capture_output();
+    let result = sqwipt.run(src).map_err(|e| e.message);
+    (result, sqwipt.vm().take_output())
+}
+
+fn value(src: &str) -> String {
+    match run(src).0 {
+        Ok(value) => value.to_string(),
+        Err(message) => panic!("{src:?} failed: {message}"),
+    }
+}
+
+fn error(src: &str) -> String {
+    run(src).0.expect_err(src)
+}
+
+#[test]
+fn test_expressions() {
+    assert_eq!(value("1 + 2 * 3"), "7");
+    assert_eq!(value("(7 / 2, 7 % 2, 7.0 / 2, 2 ** 10, 2 ** -1)"), "(3, 1, 3.5, 1024, 0.5)");
+    assert_eq!(value("(-1 >> 1, -1 >>> 120, 1 << 4, 6 & 3, 6 | 3, 6 ^ 3, !0)"), "(-1, 255, 16, 2, 7, 5, -1)");
+    assert_eq!(value("(1 < 2, 2 <= 1, 1 == 1.0, \"a\" != \"b\", !true)"), "(true, false, true, true, false)");
+    assert_eq!(value("(false and x, true or x, true and 1 == 1)"), "(false, true, true)");
+    assert_eq!(value("\"ab\" + \"c\""), "abc");
+    assert_eq!(value("(\"{\", \"a}b\", \"{{\", \"{{{1}}}\")"), "(\"{\", \"a}b\", \"{{\", \"{1}\")");
+    assert_eq!(value("x = 2\ns = \"s\"\n\"x = {x}, {x * 1.5:>6.2}, {255:#x}, {s:?}\""), "x = 2,   3.00, 0xff, \"s\"");
+    assert_eq!(value("0x1_0000_0000_0000_0000_0000_0000_0000_0000"), "340282366920938463463374607431768211456");
+    assert_eq!(value(""), "()");
+
+    assert_eq!(error("1 / 0"), "division by zero");
+    assert_eq!(error("1 + \"a\""), "cannot apply `+` to `Int` and `Str`");
+    assert_eq!(error("if 1\n  2"), "expected a `Bool` condition, found `Int`");
+    assert_eq!(error("nothing"), "cannot find `nothing` in this scope");
+    assert_eq!(error("170141183460469231731687303715884105727 + 1"), "integer overflow in `170141183460469231731687303715884105727 + 1`");
+}
+
+#[test]
+fn test_variables() {
+    assert_eq!(value("x = 1\nx += 2\nx"), "3");
+    assert_eq!(value("let (a, [b, ..c]) = (1, (2, 3, 4))\n(a, b, c)"), "(1, 2, (3, 4))");
+    assert_eq!(value("let mut (a, b) = (1, 2)\n(a, b) = (b, a)\n(a, b)"), "(2, 1)");
+    assert_eq!(value("t = (1, 2)\nt[-1]"), "2");
+    // Shadowing in a block leaves the outer binding alone.
+    assert_eq!(value("let x = 1\nif true\n  let x = 2\n  x\nx"), "1");
+
+    assert_eq!(error("let (a, b) = (1, 2, 3)"), "expected a tuple of 2, found `(1, 2, 3)`");
+}
+
+#[test]
+fn test_collections() {
+    assert_eq!(value("xs = [1, 2]\nxs.push(3)\nxs[0] = 0\n(xs, xs.len())"), "([0, 2, 3], 3)");
+    assert_eq!(value("xs = [2, 3]\n[1, ..xs, 4, ..0..2, ..(5,)]"), "[1, 2, 3, 4, 0, 1, 5]");
+    assert_eq!(value("[a, ..b] = [1, 2, 3]\n(a, b)"), "(1, [2, 3])");
+    assert_eq!(value("m = {\"a\": 1, \"b\": [2]}\nm[\"c\"] = 3\nm[\"a\"] += 1\nm"), "{\"a\": 2, \"b\": [2], \"c\": 3}");
+    // Later keys replace earlier ones but keep their place.
+    assert_eq!(value("m = {1: \"x\", (2, 3): \"y\"}\n{..m, 1: \"z\"}"), "{1: \"z\", (2, 3): \"y\"}");
+    assert_eq!(value("n = 0\nfor (k, v) in {1: 2, 3: 4}\n  n += k * v\nn"), "14");
+    assert_eq!(value("({\"a\": 1, \"b\": 2} == {\"b\": 2, \"a\": 1.0}, {}.len())"), "(true, 0)");
+
+    assert_eq!(error("{\"a\": 1}[\"b\"]"), "no key \"b\" in the map");
+    assert_eq!(error("{[1]: 2}"), "cannot use a value of type `Array` as a map key");
+    assert_eq!(error("{..[1]}"), "cannot spread a value of type `Array` into a map");
+    assert_eq!(error("[..1]"), "cannot iterate over `Int`");
+}
+
+#[test]
+fn test_structs() {
+    let bert = "struct Bert a b\nimpl Bert\n  fn sum(self)\n    self.a + self.b\n  fn new()\n    Bert(0, 0)\n";
+    assert_eq!(value(&format!("{bert}Bert(1, b=2)")), "Bert(a=1, b=2)");
+    assert_eq!(value(&format!("{bert}x = Bert(1, 2)\nx.b = 3\n(x.a, x.sum())")), "(1, 4)");
+    assert_eq!(value(&format!("{bert}(Bert.new(), Bert(1, 2) == Bert(1, 2), Bert(1, 2) == Bert(1, 3))")), "(Bert(a=0, b=0), true, false)");
+    assert_eq!(value("struct Op f\nOp(|x| x + 1).f(2)"), "3");
+    assert_eq!(value("enum Colour Red Green\nimpl Colour\n  fn red(self)\n    self == Colour.Red\n(Colour.Red, Colour.Green.red(), Colour.Red.red(), Colour)"), "(Red, false, true, <type Colour>)");
+
+    assert_eq!(error(&format!("{bert}Bert.nothing")), "`Bert` has no variant or function `nothing`");
+    assert_eq!(error("x = 1\nimpl x\n  fn f()\n    1"), "cannot add methods to `1`, it is not a type");
+    assert_eq!(error("enum E A\nE()"), "cannot call the enum `E`, use one of its variants");
+}
+
+#[test]
+fn test_enums() {
+    let shape = "enum Shape\n  Empty\n  Circle(Float)\n  Rect{w, h}\narea = |s|\n  match s\n    Empty => 0\n    Circle(r) => 3 * r * r\n    Rect(w, h) => w * h\n";
+    assert_eq!(value(&format!("{shape}(area(Shape.Empty), area(Shape.Circle(2)), area(Shape.Rect(h=3, w=2)))")), "(0, 12, 6)");
+    assert_eq!(value(&format!("{shape}(Shape.Circle(1.5), Shape.Rect(1, \"a\"), Shape.Empty, Shape.Circle)")), "(Circle(1.5), Rect(w=1, h=\"a\"), Empty, <fn Shape.Circle>)");
+    assert_eq!(value(&format!("{shape}r = Shape.Rect(1, 2)\n(r.h, r == Shape.Rect(1, 2.0), r == Shape.Rect(1, 3), Shape.Empty == Shape.Empty)")), "(2, true, false, true)");
+    // Patterns match struct-like variants with braces, and may name a variant with its enum.
+    let kind = "kind = |s|\n  match s\n    Shape.Empty => \"unit\"\n    Shape.Circle(r) => \"tuple {r}\"\n    Shape.Rect{w, h=1} => \"square {w}\"\n    Rect{h, w} => \"struct {w} {h}\"\n";
+    assert_eq!(value(&format!("{shape}{kind}[kind(Shape.Empty), kind(Shape.Circle(2)), kind(Shape.Rect(3, 1)), kind(Shape.Rect(3, 4))]")), "[\"unit\", \"tuple 2\", \"square 3\", \"struct 3 4\"]");
+    assert_eq!(value(&format!("{shape}let Some(Shape.Rect{{w}}) = Some(Shape.Rect(5, 6))\nw")), "5");
+    // Fields without names are taken by place.
+    assert_eq!(value("struct P x y\nmatch P(1, 2)\n  P(0, y) => y\n  P(1, y) => -y"), "-2");
+
+    // The prelude's `Option` and `Result` are ordinary enums.
+    assert_eq!(value("(Some(1), None, Ok(\"a\"), Err(2), Option.Some(3), Result)"), "(Some(1), None, Ok(\"a\"), Err(2), Some(3), <type Result>)");
+    assert_eq!(value("first = |xs|\n  match xs\n    [x, ..] => Some(x)\n    _ => None\n(first([]), first([1, 2]))"), "(None, Some(1))");
+    assert_eq!(value("match Some(Ok(2))\n  Some(Err(_)) => 0\n  Some(Ok(n)) => n\n  None => -1"), "2");
+    assert_eq!(value("impl Option\n  fn unwrap_or(self, default)\n    match self\n      Some(x) => x\n      None => default\n(None.unwrap_or(1), Some(2).unwrap_or(1))"), "(1, 2)");
+    assert_eq!(value("let Some(x) = Some(3)\nx"), "3");
+    assert_eq!(value("Ok = |x| x\nOk(1)"), "1");
+    // A declared enum's variants are bound by their own names too, like the prelude's.
+    assert_eq!(value(&format!("{shape}(Empty, Circle(1), Rect(h=2, w=3), Rect, area(Rect(2, 3)))")), "(Empty, Circle(1), Rect(w=3, h=2), <fn Shape.Rect>, 6)");
+    assert_eq!(value("f = ||\n  enum Color Red Green\n  [Red, Color.Green]\nf()"), "[Red, Green]");
+    // Variants are known to patterns before their enum is declared, and
+    // belong to that enum.
+    assert_eq!(value("f = |c|\n  match c\n    Red => 1\n    _ => 2\nenum Color Red Green\n(f(Color.Red), f(Color.Green))"), "(1, 2)");
+    assert_eq!(value("enum A\n  Circle(r)\nenum B\n  Circle(r)\nmatch A.Circle(1)\n  Circle(r) => r\n  _ => 0"), "0");
+
+    assert_eq!(error(&format!("{shape}Shape.Circle(1, 2)")), "`Circle` takes 1 argument but 2 were given");
+    assert_eq!(error(&format!("{shape}Shape.Rect(1, 2).w = 3")), "cannot assign to `w`, values of the enum `Shape` cannot be changed");
+    assert_eq!(error("let Some(x) = None"), "expected a `Some`, found `None`");
+}
+
+#[test]
+fn test_traits() {
+    let shape = "trait Shape\n  fn area(self)\n  fn name(self)\n  fn describe(self)\n    \"{self.name()} of area {self.area()}\"\nstruct Square side\nimpl Shape for Square\n  fn area(self)\n    self.side * self.side\n  fn name(self)\n    \"square\"\n";
+    assert_eq!(value(&format!("{shape}Square(3).describe()")), "square of area 9");
+    // A type's own method is used rather than the default.
+    assert_eq!(value(&format!("{shape}struct Dot\nimpl Shape for Dot\n  fn area(self)\n    0\n  fn name(self)\n    \"dot\"\n  fn describe(self)\n    \"a dot\"\nDot().describe()")), "a dot");
+    assert_eq!(value("trait Twice\n  fn twice(self)\n    self + self\nimpl Twice for Int\nimpl Twice for Str\n(2.twice(), \"ab\".twice(), \"ab\".len())"), "(4, \"abab\", 2)");
+    assert_eq!(value("impl Array\n  fn first(self)\n    self[0]\n[5, 6].first()"), "5");
+    assert_eq!(value("(Int, Str, Array)"), "(<type Int>, <type Str>, <type Array>)");
+
+    assert_eq!(error(&format!("{shape}struct Circle r\nimpl Shape for Circle\n  fn area(self)\n    3")), "`Circle` does not implement `name`, which `Shape` requires");
+    assert_eq!(error(&format!("{shape}1.describe()")), "`Int` has no method `describe`");
+    assert_eq!(error(&format!("{shape}impl Square for Square")), "`<type Square>` is not a trait");
+    assert_eq!(error("Int(1)"), "cannot call the built in type `Int`");
+}
+
+#[test]
+fn test_operators() {
+    let vector = "struct V x y\nimpl V\n  fn add(self, o)\n    V(self.x + o.x, self.y + o.y)\n  fn mul(self, k)\n    V(self.x * k, self.y * k)\n  fn rmul(self, k)\n    self * k\n  fn neg(self)\n    V(-self.x, -self.y)\n  fn lt(self, o)\n    self.x < o\n  fn index(self, i)\n    if i == 0\n      self.x\n    else\n      self.y\n  fn set_index(self, i, v)\n    if i == 0\n      self.x = v\n    else\n      self.y = v\n";
+    assert_eq!(value(&format!("{vector}(V(1, 2) + V(3, 4), V(1, 2) * 3, 3 * V(1, 2), -V(1, 2))")), "(V(x=4, y=6), V(x=3, y=6), V(x=3, y=6), V(x=-1, y=-2))");
+    assert_eq!(value(&format!("{vector}v = V(1, 2)\nv += V(1, 1)\nv[0] = 5\nv[1] += 1\n(v, v[0])")), "(V(x=5, y=4), 5)");
+    // Comparisons turn around for the right operand.
+    assert_eq!(value(&format!("{vector}(V(1, 2) < 2, 2 > V(1, 2), 1 > V(1, 2))")), "(true, true, false)");
+    assert_eq!(value("struct Money cents\nimpl Money\n  fn eq(self, o)\n    self.cents == o\n(Money(5) == 5, 5 == Money(5), Money(5) != 5, Money(1) == Money(1))"), "(true, true, false, true)");
+    // Without an `eq` method structs are equal if their fields are.
+    assert_eq!(value("struct P x\n(P(1) == P(1), P(1) != P(2))"), "(true, true)");
+    assert_eq!(value("enum E A B\nimpl E\n  fn not(self)\n    match self\n      A => E.B\n      B => E.A\n!E.A"), "B");
+    // A method gives `NotImplemented` to let the right operand's method, or else the built in operator, have a go.
+    assert_eq!(value("struct V x\nstruct M k\nimpl V\n  fn mul(self, o)\n    match o\n      M(_) => NotImplemented\n      _ => V(self.x * o)\nimpl M\n  fn rmul(self, v)\n    \"rmul {v.x * self.k}\"\n(V(2) * 3, V(2) * M(3))"), "(V(x=6), \"rmul 6\")");
+    assert_eq!(value("struct P x\nimpl P\n  fn eq(self, o)\n    NotImplemented\n(P(1) == P(1), P(1) != P(2), NotImplemented)"), "(true, true, NotImplemented)");
+
+    assert_eq!(error(&format!("{vector}V(1, 2) - V(1, 2)")), "cannot apply `-` to `V` and `V`");
+    assert_eq!(error(&format!("{vector}\"a\" * V(1, 2)")), "cannot apply `*` to `Int` and `Str`");
+    assert_eq!(error(&format!("{vector}0 < V(1, 2)")), "cannot apply `<` to `Int` and `V`");
+    assert_eq!(error("struct M\nimpl M\n  fn eq(self, o)\n    1\nM() != 1"), "`eq` must give a `Bool` for `!=`, not `Int`");
+    assert_eq!(error("struct A\nimpl A\n  fn add(self, o)\n    NotImplemented\nA() + 1"), "cannot apply `+` to `A` and `Int`");
+    assert_eq!(error("struct V x\nimpl V\n  fn add(self, o)\n    self + o\nV(1) + 1"), "recursion limit exceeded");
+}
+
+#[test]
+fn test_control_flow() {
+    assert_eq!(value("if 1 > 2\n  \"a\"\nelif 2 > 1\n  \"b\"\nelse\n  \"c\""), "b");
+    assert_eq!(value("if false\n  1"), "()");
+    assert_eq!(value("i = 0\nn = 0\nwhile i < 10\n  i += 1\n  if i % 2 == 0\n    continue\n  if i > 7\n    break\n  n += i\nn"), "16");
+    assert_eq!(value("n = 0\nfor i in 0..5\n  n += i\nn"), "10");
+    assert_eq!(value("s = \"\"\nfor c in \"héllo\"\n  s = c + s\ns"), "olléh");
+    assert_eq!(value("n = 0\nfor (a, b) in ((1, 2), (3, 4))\n  n += a * b\nn"), "14");
+    // A break leaves the loop's iterator behind.
+    assert_eq!(value("for i in 0..3\n  for j in 0..3\n    break\n  i\n7"), "7");
+    // Ranges reaching the largest `Int` stop before it.
+    assert_eq!(value("max = 170141183460469231731687303715884105727\nn = 0\nfor i in (max - 2)..max\n  n += 1\n(n, ((max - 2)..max).len())"), "(2, 2)");
+    assert_eq!(value("(5..2).len()"), "0");
+
+    assert_eq!(error("max = 170141183460469231731687303715884105727\n(-max - 1..max).len()"), "the length of `-170141183460469231731687303715884105728..170141183460469231731687303715884105727` does not fit in an `Int`");
+}
+
+#[test]
+fn test_functions() {
+    assert_eq!(value("add = |a, b| a + b\nadd(1, 2)"), "3");
+    assert_eq!(value("fib = |n|\n  if n < 2\n    return n\n  fib(n - 1) + fib(n - 2)\nfib(20)"), "6765");
+    assert_eq!(value("swap = |(a, b)| (b, a)\nswap((1, 2))"), "(2, 1)");
+    // Closures share the variables they capture.
+    assert_eq!(value("counter = |n|\n  |d|\n    n += d\n    n\nc = counter(0)\nc(1)\nc(1)"), "2");
+    assert_eq!(value("fs = (1, 2, 3)\nsum = 0\nfs.for_each(|x| sum += x)\nsum"), "6");
+    assert_eq!(value("adders = (0, 0)\nf = 0\nfor i in 0..2\n  g = |x| x + i\n  f = g\nf(10)"), "11");
+
+    assert_eq!(error("f = |a| a\nf(1, 2)"), "`f` takes 1 argument but 2 were given");
+    assert_eq!(error("1(2)"), "cannot call a value of type `Int`");
+    assert_eq!(error("1.foo()"), "`Int` has no method `foo`");
+    assert_eq!(error("f = || f()\nf()"), "recursion limit exceeded");
+    assert_eq!(error("f = |n|\n  [n].for_each(|x| f(x + 1))\nf(0)"), "recursion limit exceeded");
+
+    let (result, output) = run("print(\"hello\", 1, (2, \"x\"))\nprint()");
+    assert_eq!(result, Ok(Variant::Unit));
+    assert_eq!(output, "hello 1 (2, \"x\")\n\n");
+}
+
+#[test]
+fn test_params() {
+    assert_eq!(value("f = || 1\nf()"), "1");
+    assert_eq!(value("f = |a, b=a * 2| (a, b)\n(f(1), f(1, 3))"), "((1, 2), (1, 3))");
+    assert_eq!(value("f = |x: Int, y: Int = 1| x + y\nf(2)"), "3");
+    assert_eq!(value("f = |first, *rest| (first, rest)\n(f(1), f(1, 2, 3))"), "((1, ()), (1, (2, 3)))");
+    assert_eq!(value("f = |a, *, scale=10| a * scale\nf(2)"), "20");
+    assert_eq!(value("f = |(a, b)=(1, 2)| a + b\n(f(), f((3, 4)))"), "(3, 7)");
+    // Defaults are worked out afresh at every call.
+    assert_eq!(value("n = 0\nnext = ||\n  n += 1\n  n\nf = |x=next()| x\n(f(), f(), f(10), f())"), "(1, 2, 10, 3)");
+
+    assert_eq!(error("f = |a, b=1| a\nf()"), "`f` takes 1 to 2 arguments but 0 were given");
+    assert_eq!(error("f = |a, b=1| a\nf(1, 2, 3)"), "`f` takes 1 to 2 arguments but 3 were given");
+    assert_eq!(error("f = |a, *rest| a\nf()"), "`f` takes at least 1 argument but 0 were given");
+    assert_eq!(error("f = |*, key| key\nf()"), "`f` is missing keyword argument `key`");
+}
+
+#[test]
+fn test_keyword_args() {
+    let f = "f = |a, b=2, *rest, scale=1| (a, b, rest, scale)\n";
+    for (call, expected) in [
+        ("f(1)", "(1, 2, (), 1)"),
+        ("f(1, scale=10)", "(1, 2, (), 10)"),
+        ("f(b=3, a=1)", "(1, 3, (), 1)"),
+        ("f(1, 2, 3, 4, scale=0)", "(1, 2, (3, 4), 0)"),
+    ] {
+        assert_eq!(value(&format!("{f}{call}")), expected, "{call}");
+    }
+    // Defaults still see the arguments passed by name.
+    assert_eq!(value("f = |a, b=a + 1| b\nf(a=1)"), "2");
+
+    // Calls through a name that may be rebound are only checked when run.
+    assert_eq!(error(&format!("{f}f(scale=1)")), "`f` is missing argument `a`");
+    assert_eq!(error(&format!("{f}f(1, size=1)")), "`f` has no parameter named `size`");
+    assert_eq!(error(&format!("{f}f(1, a=1)")), "`f` got more than one value for `a`");
+    assert_eq!(error(&format!("{f}f(1, rest=1)")), "`f` has no parameter named `rest`");
+    assert_eq!(error("(1, 2).len(x=1)"), "`len` has no parameter named `x`");
+
+    let (result, output) = run("print(1, 2, sep=\", \", end=\".\")");
+    assert_eq!(result, Ok(Variant::Unit));
+    assert_eq!(output, "1, 2.");
+    assert_eq!(error("print(1, colour=2)"), "`print` has no parameter named `colour`");
+}
+
+#[test]
+fn test_natives() {
+    let mut sqwipt = Sqwipt::new();
+    sqwipt.vm().register("twice", |_, args| match args.as_slice() {
+        [x] => Ok(Variant::tuple(vec![x.clone(), x.clone()])),
+        _ => Err(Error::new("`twice` takes 1 argument")),
+    });
+    sqwipt.vm().register_with_keywords("scale", |_, mut args| {
+        let by = args.keyword("by").unwrap_or(Variant::from(1));
+        args.finish("scale")?;
+        Ok(Variant::tuple(vec![args.positional[0].clone(), by]))
+    });
+    assert_eq!(sqwipt.run("scale(twice(1), by=3)").map(|v| v.to_string()), Ok("((1, 1), 3)".to_string()));
+    assert_eq!(sqwipt.run("twice(1, x=1)").unwrap_err().message, "`twice` has no parameter named `x`");
+    assert_eq!(sqwipt.run("scale(1, to=3)").unwrap_err().message, "`scale` has no parameter named `to`");
+    // Patterns know the enums of earlier runs.
+    sqwipt.run("enum C\n  Red\n  Green").unwrap();
+    assert_eq!(sqwipt.run("match C.Green\n  Red => 1\n  _ => 2"), Ok(2.into()));
+}
+
+#[test]
+fn test_match() {
+    let classify = "classify = |n|\n  match n\n    0 => \"zero\"\n    1 => \"one\"\n    -1 => \"minus one\"\n    2..10 => \"small\"\n    n if n < 0 => \"negative\"\n    _ => \"big\"\n";
+    for (n, expected) in [("0", "zero"), ("1", "one"), ("-1", "minus one"), ("5", "small"), ("-5", "negative"), ("10", "big"), ("1.0", "one")] {
+        assert_eq!(value(&format!("{classify}classify({n})")), expected);
+    }
+    assert_eq!(value("match \"b\"\n  \"a\" => 1\n  \"b\" => 2"), "2");
+    assert_eq!(value("match (1, (2, 3))\n  (0, _) => 0\n  (1, (x, y)) if x > y => 1\n  (1, (x, y)) => x + y"), "5");
+    assert_eq!(value("match (1, 2)\n  (a, b, c) => 3\n  (a, b) => 2"), "2");
+    // Arms bind their own names and can be blocks.
+    assert_eq!(value("x = 1\nmatch 2\n  x =>\n    x += 1\n    x\n"), "3");
+    assert_eq!(value("x = 1\nmatch 2\n  x => x\nx"), "1");
+    // Loops inside arms can be left early.
+    assert_eq!(value("n = 0\nfor i in 0..10\n  match i\n    3 => continue\n    5 => break\n    _ => n += i\nn"), "7");
+
+    assert_eq!(error("match 3\n  1 => 1\n  2 => 2"), "no arm matches `3`");
+}